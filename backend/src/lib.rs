@@ -1,3 +1,5 @@
+#![allow(non_snake_case)]
+
 /// Execute Description Language that with MachineCode interactive
 pub mod ExecDescLang;
 pub mod MachineCode;
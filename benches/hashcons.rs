@@ -0,0 +1,59 @@
+//! Adding, deduplicating and rebuilding large egraphs, run with
+//! `cargo bench --bench hashcons`.
+
+use std::time::{Duration, Instant};
+
+use cfir::{op::Op, symbol::Symbol, value::Value};
+use cfir_frontend::{cfir_expr, pat};
+use egraph::egraph::EGraph;
+
+fn terms(size: usize) -> Vec<Op> {
+  (0..size)
+    .map(|i| {
+      let src = format!(
+        "arthi.add(x{}, arthi.mul(x{}, arthi.sub(x{}, {}): (int, int) -> int): (int, int) -> int): (int, int) -> int",
+        i % 97,
+        i % 89,
+        i % 83,
+        i % 101
+      );
+      cfir_expr!(&src)
+    })
+    .collect()
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> (Duration, T) {
+  let start = Instant::now();
+  let out = f();
+  (start.elapsed(), out)
+}
+
+fn main() {
+  for size in [1000, 10000, 50000] {
+    let terms = terms(size);
+    let mut egg: EGraph<()> = EGraph::new();
+    let (add, _) = time(|| {
+      for op in &terms {
+        egg.add_op(op);
+      }
+    });
+    let classes = egg.classes().len();
+    // every term is already there
+    let (readd, _) = time(|| {
+      for op in &terms {
+        egg.add_op(op);
+      }
+    });
+    assert_eq!(egg.classes().len(), classes);
+    // x0 = x1 makes many adds congruent
+    let (_, x0) = egg.add_value(&Value::Input(Symbol::new("x0")));
+    let (_, x1) = egg.add_value(&Value::Input(Symbol::new("x1")));
+    egg.union(&x0, &x1);
+    let (rebuild, unions) = time(|| egg.rebuild());
+    let (lookup, found) = time(|| egg.matching_op(pat!("arthi.sub(?a, 0)")).len());
+    println!(
+      "{:>6} terms, {:>6} eclasses  add {:>10.2?}  re-add {:>10.2?}  rebuild {:>10.2?} ({} unions)  match {:>10.2?} ({} found)",
+      size, classes, add, readd, rebuild, unions, lookup, found
+    );
+  }
+}
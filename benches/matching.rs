@@ -0,0 +1,72 @@
+//! Recursive matcher against the matching machine and the relational matcher,
+//! run with `cargo bench --bench matching`.
+
+use std::time::{Duration, Instant};
+
+use cfir_frontend::{catch, cfir_expr, pat};
+use egraph::{egraph::EGraph, machine::Program, relational::Query, rule::Rule};
+
+fn egraph(size: usize) -> EGraph<()> {
+  let mut egg = EGraph::new();
+  for i in 0..size {
+    let src = format!(
+      "arthi.add(x{}, arthi.mul(x{}, arthi.add(x{}, {}): (int, int) -> int): (int, int) -> int): (int, int) -> int",
+      i % 17,
+      i % 13,
+      i % 7,
+      i % 5
+    );
+    egg.add_op(&cfir_expr!(&src));
+  }
+  let rules = [
+    Rule::new(
+      "add-comm",
+      pat!("arthi.add(?a, ?b)"),
+      catch!("arthi.add(?b, ?a)"),
+    ),
+    Rule::new(
+      "mul-comm",
+      pat!("arthi.mul(?a, ?b)"),
+      catch!("arthi.mul(?b, ?a)"),
+    ),
+  ];
+  egg.saturate(&rules, 3);
+  egg
+}
+
+fn time(iters: u32, mut f: impl FnMut() -> usize) -> (Duration, usize) {
+  let mut found = 0;
+  let start = Instant::now();
+  for _ in 0..iters {
+    found = f();
+  }
+  (start.elapsed() / iters, found)
+}
+
+fn main() {
+  let patterns = [
+    "arthi.add(?a, ?b)",
+    "arthi.add(?a, arthi.mul(?b, ?c))",
+    "arthi.add(?a, arthi.mul(?a, ?c))",
+    "arthi.mul(?a, arthi.add(?b, 0))",
+    "arthi.add(?x, arthi.mul(?y, arthi.add(?z, ?w)))",
+  ];
+  for size in [100, 1000] {
+    let mut egg = egraph(size);
+    println!("{} terms, {} eclasses", size, egg.classes().len());
+    for src in patterns {
+      let op = pat!(src);
+      let program = Program::compile_op(&op);
+      let query = Query::compile_op(&op);
+      let (recursive, n) = time(20, || egg.matching_op(op.clone()).len());
+      let (machine, m) = time(20, || egg.run_program(&program).len());
+      let (relational, r) = time(20, || egg.run_query(&query).len());
+      assert_eq!(n, m);
+      assert_eq!(n, r);
+      println!(
+        "  {:<48} {:>6} matches  recursive {:>10.2?}  machine {:>10.2?}  relational {:>10.2?}",
+        src, n, recursive, machine, relational
+      );
+    }
+  }
+}
@@ -0,0 +1,125 @@
+//! Traits of the opcodes of the dialects, the passes rely on them instead of
+//! on opcode names.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::symbol::{Name, Symbol};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OpTrait {
+  /// Reads or writes state, it keeps its order among the effectful ops of its
+  /// block. An op with effectful ops in its regions must have it too.
+  Effect,
+  /// Ends its block.
+  Terminator,
+  /// Binary op whose operands may be swapped.
+  Commutative,
+  /// Binary op whose nested uses may be regrouped, with `Commutative` the
+  /// matcher sees the nested uses as one flat list of operands.
+  Associative,
+}
+
+impl OpTrait {
+  pub const ALL: [OpTrait; 4] = [
+    OpTrait::Effect,
+    OpTrait::Terminator,
+    OpTrait::Commutative,
+    OpTrait::Associative,
+  ];
+
+  /// Name of the trait in rule guards, `commutative(?op)`.
+  pub fn name(&self) -> &'static str {
+    match self {
+      OpTrait::Effect => "effect",
+      OpTrait::Terminator => "terminator",
+      OpTrait::Commutative => "commutative",
+      OpTrait::Associative => "associative",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<OpTrait> {
+    OpTrait::ALL.into_iter().find(|t| t.name() == name)
+  }
+}
+
+/// How the matcher may reorder the operands of an op.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+  /// In order.
+  #[default]
+  None,
+  /// In any order.
+  Commutative,
+  /// In any order, flattened through the operands with the same opcode.
+  AssocComm,
+}
+
+/// Traits of each opcode, an opcode not registered has none and is pure.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+  ops: BTreeMap<Name, BTreeSet<OpTrait>>,
+}
+
+fn name(dialect: &str, op: &str) -> Name {
+  Name(Some(Symbol::new(dialect)), Symbol::new(op))
+}
+
+impl Registry {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Traits of the builtin dialects.
+  pub fn builtin() -> Self {
+    use OpTrait::*;
+    let mut registry = Registry::new()
+      .with(name("fn", "call"), &[Effect])
+      .with(name("fn", "ret"), &[Effect, Terminator])
+      .with(name("mem", "load"), &[Effect])
+      .with(name("mem", "store"), &[Effect])
+      .with(name("scf", "yield"), &[Terminator]);
+    for op in ["add", "mul", "and", "or", "xor"] {
+      registry.register(name("arthi", op), &[Commutative, Associative]);
+    }
+    for op in ["eq", "ne"] {
+      registry.register(name("arthi", op), &[Commutative]);
+    }
+    registry
+  }
+
+  /// Add `traits` to `opcode`.
+  pub fn with(mut self, opcode: Name, traits: &[OpTrait]) -> Self {
+    self.register(opcode, traits);
+    self
+  }
+
+  pub fn register(&mut self, opcode: Name, traits: &[OpTrait]) {
+    self
+      .ops
+      .entry(opcode)
+      .or_default()
+      .extend(traits.iter().copied());
+  }
+
+  pub fn has(&self, opcode: &Name, t: OpTrait) -> bool {
+    self
+      .ops
+      .get(opcode)
+      .is_some_and(|traits| traits.contains(&t))
+  }
+
+  pub fn is_pure(&self, opcode: &Name) -> bool {
+    !self.has(opcode, OpTrait::Effect)
+  }
+
+  pub fn symmetry(&self, opcode: &Name) -> Symmetry {
+    match (
+      self.has(opcode, OpTrait::Commutative),
+      self.has(opcode, OpTrait::Associative),
+    ) {
+      (true, true) => Symmetry::AssocComm,
+      (true, false) => Symmetry::Commutative,
+      _ => Symmetry::None,
+    }
+  }
+}
@@ -1,5 +1,7 @@
 pub mod block;
+pub mod dialect;
 pub mod op;
+pub mod printer;
 pub mod rewriter;
 pub mod symbol;
 pub mod tools;
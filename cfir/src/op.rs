@@ -32,7 +32,7 @@ impl OpHand {
 
 impl Hash for OpHand {
   fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-    Rc::into_raw(self.0.clone()).hash(state);
+    Rc::as_ptr(&self.0).hash(state);
   }
 }
 
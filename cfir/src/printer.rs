@@ -0,0 +1,192 @@
+//! Print cfir in the syntax read by `cfir_frontend`.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::{
+  block::Block,
+  op::{Op, OpHand},
+  symbol::{Name, Symbol},
+  types::{FuncType, GenericType, Type, TypeOrConst},
+  value::{Argument, Constant, Label, Order, Value},
+};
+
+fn list<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> fmt::Result {
+  for (i, item) in items.iter().enumerate() {
+    if i > 0 {
+      write!(f, ", ")?;
+    }
+    write!(f, "{}", item)?;
+  }
+  Ok(())
+}
+
+impl Display for Symbol {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Display for Name {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match &self.0 {
+      Some(dialect) => write!(f, "{}.{}", dialect, self.1),
+      None => write!(f, "{}", self.1),
+    }
+  }
+}
+
+impl Display for Constant {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Constant::Bool(b) => write!(f, "{}", b),
+      Constant::Int(i) => write!(f, "{}", i),
+      Constant::Uint(u) => write!(f, "{}u", u),
+      Constant::String(s) => write!(f, "{:?}", s),
+    }
+  }
+}
+
+impl Display for Type {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Type::GenericType(ty) => write!(f, "{}", ty),
+      Type::FuncType(ty) => write!(f, "{}", ty),
+    }
+  }
+}
+
+impl Display for GenericType {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.name)?;
+    if !self.args.is_empty() {
+      write!(f, "<")?;
+      list(f, &self.args)?;
+      write!(f, ">")?;
+    }
+    Ok(())
+  }
+}
+
+impl Display for TypeOrConst {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      TypeOrConst::Type(ty) => write!(f, "{}", ty),
+      TypeOrConst::Const(c) => write!(f, "{}", c),
+    }
+  }
+}
+
+impl Display for FuncType {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "(")?;
+    list(f, &self.0)?;
+    write!(f, ") -> ")?;
+    list(f, &self.1)
+  }
+}
+
+impl Display for Label {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "^{}", self.0)
+  }
+}
+
+impl Display for Argument {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self.1 {
+      Some(Order::Def) => write!(f, "def {}", self.0),
+      Some(Order::Use) => write!(f, "use {}", self.0),
+      None => write!(f, "{}", self.0),
+    }
+  }
+}
+
+impl Display for Value {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Value::Const(c) => write!(f, "{}", c),
+      Value::Use(op, offset) => {
+        let op = op.as_ref().borrow();
+        // other results can only be used by name
+        match op.defs.get(*offset) {
+          Some(def) if *offset > 0 => write!(f, "{}", def),
+          _ => write!(f, "{}", op),
+        }
+      },
+      Value::Argument(arg) => write!(f, "{}", arg),
+      Value::Label(label) => write!(f, "{}", label),
+      Value::Input(sym) => write!(f, "{}", sym),
+    }
+  }
+}
+
+impl Display for OpHand {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.as_ref().borrow())
+  }
+}
+
+/// `name(uses)[attr]{region}: sign`, attributes and blocks are sorted by name.
+impl Display for Op {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}(", self.opcode)?;
+    list(f, &self.uses)?;
+    write!(f, ")")?;
+    if !self.attr.is_empty() {
+      let mut attr = self.attr.iter().collect::<Vec<_>>();
+      attr.sort_by(|(l, _), (r, _)| l.0.cmp(&r.0));
+      write!(f, "[")?;
+      for (i, (key, value)) in attr.into_iter().enumerate() {
+        if i > 0 {
+          write!(f, ", ")?;
+        }
+        write!(f, "{}: {}", key, value)?;
+      }
+      write!(f, "]")?;
+    }
+    if !self.region.is_empty() {
+      let mut blocks = self.region.values().collect::<Vec<_>>();
+      blocks.sort_by(|l, r| l.0.as_ref().map(|s| &s.0).cmp(&r.0.as_ref().map(|s| &s.0)));
+      write!(f, "{{")?;
+      for block in blocks {
+        write!(f, " {}", block)?;
+      }
+      write!(f, " }}")?;
+    }
+    write!(f, ": ")?;
+    list(f, &self.sign)
+  }
+}
+
+impl Display for Block {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    if let Some(label) = &self.0 {
+      write!(f, "^{}", label)?;
+      if !self.1.is_empty() {
+        let mut args = self.1.iter().collect::<Vec<_>>();
+        args.sort_by(|(l, _), (r, _)| l.0.cmp(&r.0));
+        write!(f, "(")?;
+        for (i, (sym, ty)) in args.into_iter().enumerate() {
+          if i > 0 {
+            write!(f, ", ")?;
+          }
+          write!(f, "{}.{}", sym, ty)?;
+        }
+        write!(f, ")")?;
+      }
+      write!(f, ":")?;
+    }
+    for (i, op) in self.2.iter().enumerate() {
+      if i > 0 || self.0.is_some() {
+        write!(f, " ")?;
+      }
+      let op = op.as_ref().borrow();
+      if !op.defs.is_empty() {
+        list(f, &op.defs)?;
+        write!(f, " = ")?;
+      }
+      write!(f, "{}", op)?;
+    }
+    Ok(())
+  }
+}
@@ -27,13 +27,10 @@ impl PartialEq for Form {
       (Self::Form(l0, l1), Self::Form(r0, r1)) => {
         l0 == r0 && {
           for i in l1.iter().zip(r1.iter()) {
-            match i {
-              (Some(l), Some(r)) => {
-                if l != r {
-                  return false;
-                }
-              },
-              (None, None) | (None, Some(_)) | (Some(_), None) => {},
+            if let (Some(l), Some(r)) = i {
+              if l != r {
+                return false;
+              }
             }
           }
           true
@@ -1,7 +1,9 @@
-use std::{cell::RefCell, hash::Hash, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, hash::Hash, rc::Rc};
 
 use crate::{
+  op::Attr,
   symbol::{Name, Symbol},
+  types::{FuncType, GenericType, Type, TypeOrConst},
   value::{Argument, Constant, Label},
 };
 
@@ -12,20 +14,285 @@ pub trait Matcher<T> {
   fn matching(&self, i: &T) -> Self::Output;
 }
 
+/// Opcode, uses, defs and the constraints on attributes and sign.
+///
+/// The opcode is `add`, `arthi.*` for any op of a dialect or `?op`, which
+/// binds the opcode so the rhs can build an op with it.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct OpPat(
-  // pub Catch<Name>,
-  pub Name,
+  pub Catch<OpcodePat>,
   pub Vec<Catch<ValuePat>>,
   pub Vec<Symbol>,
-  // pub FuncType,
+  pub OpMeta,
 );
 
+impl OpPat {
+  pub fn new(opcode: Name, uses: Vec<Catch<ValuePat>>) -> Self {
+    OpPat(
+      Catch(Some(OpcodePat::Name(opcode)), None),
+      uses,
+      vec![],
+      OpMeta::default(),
+    )
+  }
+
+  /// Pattern variables, the opcode one, the uses left to right, then the
+  /// ones of the attributes and types.
+  pub fn vars(&self) -> Vec<Symbol> {
+    let mut vars = self.0 .1.iter().cloned().collect::<Vec<_>>();
+    vars.extend(self.1.iter().flat_map(Catch::vars));
+    vars.extend(self.3.vars());
+    vars
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpcodePat {
+  Name(Name),
+  /// `arthi.*`, any op of the dialect.
+  Dialect(Symbol),
+}
+
+impl OpcodePat {
+  pub fn accepts(&self, opcode: &Name) -> bool {
+    match self {
+      OpcodePat::Name(name) => name == opcode,
+      OpcodePat::Dialect(dialect) => opcode.0.as_ref() == Some(dialect),
+    }
+  }
+}
+
+impl Catch<OpcodePat> {
+  /// The opcode matched, `None` if it is a wildcard.
+  pub fn name(&self) -> Option<&Name> {
+    match &self.0 {
+      Some(OpcodePat::Name(name)) => Some(name),
+      _ => None,
+    }
+  }
+
+  pub fn matching(&self, opcode: &Name, bindings: &mut Bindings) -> bool {
+    if self.0.as_ref().is_some_and(|pat| !pat.accepts(opcode)) {
+      return false;
+    }
+    let Some(sym) = &self.1 else {
+      return true;
+    };
+    match bindings.opcodes.get(sym) {
+      Some(bound) => bound == opcode,
+      None => {
+        bindings.opcodes.insert(sym.clone(), opcode.clone());
+        true
+      },
+    }
+  }
+
+  /// Opcode of an op built from the pattern, `None` for a wildcard not bound.
+  pub fn build(&self, bindings: &Bindings) -> Option<Name> {
+    match self {
+      Catch(_, Some(sym)) => bindings.opcodes.get(sym).cloned(),
+      Catch(Some(OpcodePat::Name(name)), None) => Some(name.clone()),
+      _ => None,
+    }
+  }
+}
+
+/// Attribute and type constraints of an op pattern,
+/// `[inline: true, k: ?v]: (?t, ?t) -> ?t`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpMeta {
+  /// Each attribute must be present, equal to the constant or bound to the variable.
+  pub attr: Vec<(Symbol, Catch<Constant>)>,
+  /// Pattern of the sign, any sign if `None`.
+  pub sign: Option<Vec<TypePat>>,
+}
+
+/// Values bound to the opcode, attribute and type variables of a pattern.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bindings {
+  pub opcodes: HashMap<Symbol, Name>,
+  pub attrs: HashMap<Symbol, Constant>,
+  pub types: HashMap<Symbol, TypeOrConst>,
+}
+
+impl Bindings {
+  /// Add the bindings of `other`, fails if a variable is bound to another value.
+  pub fn merge(&mut self, other: &Bindings) -> bool {
+    fn merge<T: Clone + PartialEq>(l: &mut HashMap<Symbol, T>, r: &HashMap<Symbol, T>) -> bool {
+      for (sym, value) in r {
+        match l.get(sym) {
+          Some(bound) if bound != value => return false,
+          Some(_) => {},
+          None => {
+            l.insert(sym.clone(), value.clone());
+          },
+        }
+      }
+      true
+    }
+    merge(&mut self.opcodes, &other.opcodes)
+      && merge(&mut self.attrs, &other.attrs)
+      && merge(&mut self.types, &other.types)
+  }
+}
+
+impl OpMeta {
+  pub fn is_empty(&self) -> bool {
+    self.attr.is_empty() && self.sign.is_none()
+  }
+
+  pub fn vars(&self) -> Vec<Symbol> {
+    let mut vars = self
+      .attr
+      .iter()
+      .filter_map(|(_, catch)| catch.1.clone())
+      .collect::<Vec<_>>();
+    for ty in self.sign.iter().flatten() {
+      ty.vars(&mut vars);
+    }
+    vars
+  }
+
+  /// Whether an op with `attr` and `sign` satisfies the constraints,
+  /// its variables are bound in `bindings`.
+  pub fn matching(&self, attr: &Attr, sign: &[Type], bindings: &mut Bindings) -> bool {
+    for (key, catch) in &self.attr {
+      let Some(value) = attr.get(key) else {
+        return false;
+      };
+      if catch.0.as_ref().is_some_and(|c| c != value) {
+        return false;
+      }
+      if let Some(sym) = &catch.1 {
+        match bindings.attrs.get(sym) {
+          Some(bound) if bound != value => return false,
+          Some(_) => {},
+          None => {
+            bindings.attrs.insert(sym.clone(), value.clone());
+          },
+        }
+      }
+    }
+    match &self.sign {
+      Some(pats) => {
+        pats.len() == sign.len()
+          && pats
+            .iter()
+            .zip(sign)
+            .all(|(pat, ty)| pat.matching(&TypeOrConst::Type(ty.clone()), bindings))
+      },
+      None => true,
+    }
+  }
+
+  /// Attributes and sign of an op built from the pattern, `None` if a variable
+  /// is not bound. Without a sign pattern the sign is `any`.
+  pub fn build(&self, bindings: &Bindings) -> Option<(Attr, Vec<Type>)> {
+    let attr = self
+      .attr
+      .iter()
+      .map(|(key, catch)| {
+        let value = match catch {
+          Catch(Some(c), _) => c.clone(),
+          Catch(None, Some(sym)) => bindings.attrs.get(sym)?.clone(),
+          Catch(None, None) => return None,
+        };
+        Some((key.clone(), value))
+      })
+      .collect::<Option<Attr>>()?;
+    let sign = match &self.sign {
+      Some(pats) => pats
+        .iter()
+        .map(|pat| pat.build_type(bindings))
+        .collect::<Option<Vec<_>>>()?,
+      None => vec![Type::any_type()],
+    };
+    Some((attr, sign))
+  }
+}
+
+/// Pattern of a type, `?t` stands for the same type or constant everywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypePat {
+  Var(Symbol),
+  Generic(Name, Vec<TypePat>),
+  Func(Vec<TypePat>, Vec<TypePat>),
+  /// Constant argument of a generic type.
+  Const(Constant),
+}
+
+impl TypePat {
+  fn vars(&self, out: &mut Vec<Symbol>) {
+    match self {
+      TypePat::Var(sym) => out.push(sym.clone()),
+      TypePat::Generic(_, args) => args.iter().for_each(|arg| arg.vars(out)),
+      TypePat::Func(params, rets) => params.iter().chain(rets).for_each(|ty| ty.vars(out)),
+      TypePat::Const(_) => {},
+    }
+  }
+
+  pub fn matching(&self, ty: &TypeOrConst, bindings: &mut Bindings) -> bool {
+    let all = |pats: &[TypePat], tys: Vec<TypeOrConst>, bindings: &mut Bindings| {
+      pats.len() == tys.len() && pats.iter().zip(&tys).all(|(p, t)| p.matching(t, bindings))
+    };
+    match (self, ty) {
+      (TypePat::Var(sym), ty) => match bindings.types.get(sym) {
+        Some(bound) => bound == ty,
+        None => {
+          bindings.types.insert(sym.clone(), ty.clone());
+          true
+        },
+      },
+      (TypePat::Generic(name, args), TypeOrConst::Type(Type::GenericType(ty))) => {
+        *name == ty.name && all(args, ty.args.clone(), bindings)
+      },
+      (TypePat::Func(params, rets), TypeOrConst::Type(Type::FuncType(FuncType(p, r)))) => {
+        let types = |tys: &[Type]| tys.iter().cloned().map(TypeOrConst::Type).collect();
+        all(params, types(p), bindings) && all(rets, types(r), bindings)
+      },
+      (TypePat::Const(c), TypeOrConst::Const(c1)) => c == c1,
+      _ => false,
+    }
+  }
+
+  pub fn build(&self, bindings: &Bindings) -> Option<TypeOrConst> {
+    Some(match self {
+      TypePat::Var(sym) => bindings.types.get(sym)?.clone(),
+      TypePat::Const(c) => TypeOrConst::Const(c.clone()),
+      pat => TypeOrConst::Type(pat.build_type(bindings)?),
+    })
+  }
+
+  /// Type built from the pattern, `None` for a constant.
+  pub fn build_type(&self, bindings: &Bindings) -> Option<Type> {
+    let types = |pats: &[TypePat]| {
+      pats
+        .iter()
+        .map(|pat| pat.build_type(bindings))
+        .collect::<Option<Vec<_>>>()
+    };
+    match self {
+      TypePat::Var(sym) => match bindings.types.get(sym)? {
+        TypeOrConst::Type(ty) => Some(ty.clone()),
+        TypeOrConst::Const(_) => None,
+      },
+      TypePat::Generic(name, args) => Some(Type::GenericType(GenericType {
+        name: name.clone(),
+        args: args
+          .iter()
+          .map(|arg| arg.build(bindings))
+          .collect::<Option<_>>()?,
+      })),
+      TypePat::Func(params, rets) => Some(Type::FuncType(FuncType(types(params)?, types(rets)?))),
+      TypePat::Const(_) => None,
+    }
+  }
+}
+
 impl GetForm for OpPat {
   fn get_form(&self) -> Option<Form> {
     Some(Form::Form(
-      // self.0 .0.clone(),
-      self.0.clone(),
+      self.0.name()?.clone(),
       self.1.iter().map(GetForm::get_form).collect(),
     ))
   }
@@ -54,19 +321,57 @@ impl AsRef<RefCell<OpPat>> for OpPatHand {
 
 impl Hash for OpPatHand {
   fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-    Rc::into_raw(self.0.clone()).hash(state);
+    Rc::as_ptr(&self.0).hash(state);
   }
 }
 
 impl PartialEq for OpPatHand {
   fn eq(&self, other: &Self) -> bool {
-    Rc::into_raw(self.0.clone()) as usize == Rc::into_raw(other.0.clone()) as usize
+    Rc::ptr_eq(&self.0, &other.0)
   }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Catch<T>(pub Option<T>, pub Option<Symbol>);
 
+impl Catch<ValuePat> {
+  /// Pattern variables, left to right.
+  pub fn vars(&self) -> Vec<Symbol> {
+    let mut vars = self.0.as_ref().map(ValuePat::vars).unwrap_or_default();
+    vars.extend(self.1.clone());
+    vars
+  }
+
+  /// Dialects of the opcode wildcards not bound to a variable, left to right.
+  pub fn wildcards(&self) -> Vec<Symbol> {
+    let Some(ValuePat::Use(op, _)) = &self.0 else {
+      return vec![];
+    };
+    let op = op.as_ref().borrow();
+    let mut dialects = match &op.0 {
+      Catch(Some(OpcodePat::Dialect(dialect)), None) => vec![dialect.clone()],
+      _ => vec![],
+    };
+    dialects.extend(op.1.iter().flat_map(Catch::wildcards));
+    dialects
+  }
+
+  /// Variables used as a rhs before they are bound, `?x:pat` binds `?x`.
+  pub fn unbound_vars(&self, bound: &mut Vec<Symbol>) -> Vec<Symbol> {
+    let mut unbound = self
+      .0
+      .as_ref()
+      .map(|pat| pat.unbound_vars(bound))
+      .unwrap_or_default();
+    match self {
+      Catch(None, Some(sym)) if !bound.contains(sym) => unbound.push(sym.clone()),
+      Catch(Some(_), Some(sym)) => bound.push(sym.clone()),
+      _ => {},
+    }
+    unbound
+  }
+}
+
 impl<T: GetForm> GetForm for Catch<T> {
   fn get_form(&self) -> Option<Form> {
     self.0.get_form()
@@ -93,6 +398,34 @@ pub enum ValuePat {
   Input(Symbol),
 }
 
+impl ValuePat {
+  pub fn vars(&self) -> Vec<Symbol> {
+    match self {
+      ValuePat::Use(op, _) => op.as_ref().borrow().vars(),
+      _ => vec![],
+    }
+  }
+
+  pub fn unbound_vars(&self, bound: &mut Vec<Symbol>) -> Vec<Symbol> {
+    match self {
+      ValuePat::Use(op, _) => {
+        let op = op.as_ref().borrow();
+        let mut unbound = op
+          .0
+           .1
+          .iter()
+          .filter(|sym| !bound.contains(sym))
+          .cloned()
+          .collect::<Vec<_>>();
+        unbound.extend(op.1.iter().flat_map(|catch| catch.unbound_vars(bound)));
+        unbound.extend(op.3.vars().into_iter().filter(|sym| !bound.contains(sym)));
+        unbound
+      },
+      _ => vec![],
+    }
+  }
+}
+
 impl GetForm for ValuePat {
   fn get_form(&self) -> Option<Form> {
     match self {
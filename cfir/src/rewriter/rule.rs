@@ -0,0 +1,136 @@
+use crate::{dialect::OpTrait, symbol::Symbol, value::Constant};
+
+use super::pattern::{Catch, OpPat, OpPatHand, ValuePat};
+
+/// `name: lhs => rhs if cond && ...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleDef {
+  pub name: Symbol,
+  pub lhs: RuleLhs,
+  pub rhs: RuleRhs,
+  pub guards: Vec<Cond>,
+}
+
+impl RuleDef {
+  /// `rhs => lhs` of a `<=>` rule, both sides must be single op patterns.
+  pub fn reversed(&self) -> Option<RuleDef> {
+    let (RuleLhs::Op(lhs), RuleRhs::Value(Catch(Some(ValuePat::Use(rhs, 0)), None))) =
+      (&self.lhs, &self.rhs)
+    else {
+      return None;
+    };
+    Some(RuleDef {
+      name: Symbol::new(&format!("{}-rev", self.name.0)),
+      lhs: RuleLhs::Op(rhs.as_ref().borrow().clone()),
+      rhs: RuleRhs::Value(Catch(
+        Some(ValuePat::Use(OpPatHand::new(lhs.clone()), 0)),
+        None,
+      )),
+      guards: self.guards.clone(),
+    })
+  }
+}
+
+/// Lhs of a rule, a multi-pattern matches all of its patterns at once,
+/// the patterns share their variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleLhs {
+  Op(OpPat),
+  /// `?q:arthi.div(?a, ?b), ?r:arthi.rem(?a, ?b)`
+  Multi(Vec<Catch<ValuePat>>),
+}
+
+impl RuleLhs {
+  pub fn vars(&self) -> Vec<Symbol> {
+    match self {
+      RuleLhs::Op(op) => op.vars(),
+      RuleLhs::Multi(pats) => pats.iter().flat_map(Catch::vars).collect(),
+    }
+  }
+}
+
+/// Rhs of a rule, the term is unioned with the matched eclass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleRhs {
+  Value(Catch<ValuePat>),
+  /// `?q:pat, ?r:pat`, each term is unioned with the eclass bound to its variable.
+  Multi(Vec<Catch<ValuePat>>),
+}
+
+impl RuleRhs {
+  /// Variables used before they are bound, given the variables of the lhs.
+  pub fn unbound_vars(&self, mut bound: Vec<Symbol>) -> Vec<Symbol> {
+    match self {
+      RuleRhs::Value(rhs) => rhs.unbound_vars(&mut bound),
+      RuleRhs::Multi(rhs) => rhs
+        .iter()
+        .flat_map(|rhs| rhs.unbound_vars(&mut bound))
+        .collect(),
+    }
+  }
+
+  /// Dialects of the `arthi.*` opcodes, an op can not be built from them.
+  pub fn wildcards(&self) -> Vec<Symbol> {
+    match self {
+      RuleRhs::Value(rhs) => rhs.wildcards(),
+      RuleRhs::Multi(rhs) => rhs.iter().flat_map(Catch::wildcards).collect(),
+    }
+  }
+}
+
+/// Named rules of a rule file, top level rules are in the group `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleGroup {
+  pub name: Option<Symbol>,
+  pub rules: Vec<RuleDef>,
+}
+
+/// Declarative guard of a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cond {
+  Eq(Operand, Operand),
+  Ne(Operand, Operand),
+  /// Builtin predicate, such as `pow2(?c)`.
+  Call(Symbol, Vec<Operand>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+  Var(Symbol),
+  Const(Constant),
+}
+
+/// Builtin predicates of rule guards, each takes one variable. The name of an
+/// `OpTrait` is a predicate too, on an opcode variable: `commutative(?op)`.
+pub const PREDICATES: [&str; 5] = ["closed", "const", "pow2", "signed", "unsigned"];
+
+impl Cond {
+  /// Whether a predicate is builtin and called on one variable.
+  pub fn check(&self) -> Result<(), String> {
+    let Cond::Call(name, args) = self else {
+      return Ok(());
+    };
+    let name = name.0.as_str();
+    if !PREDICATES.contains(&name) && OpTrait::from_name(name).is_none() {
+      return Err(format!("unknown predicate `{name}`"));
+    }
+    if !matches!(args.as_slice(), [Operand::Var(_)]) {
+      return Err(format!("predicate `{name}` takes one variable"));
+    }
+    Ok(())
+  }
+
+  pub fn vars(&self) -> Vec<Symbol> {
+    let operands = match self {
+      Cond::Eq(l, r) | Cond::Ne(l, r) => vec![l, r],
+      Cond::Call(_, args) => args.iter().collect(),
+    };
+    operands
+      .into_iter()
+      .filter_map(|operand| match operand {
+        Operand::Var(sym) => Some(sym.clone()),
+        Operand::Const(_) => None,
+      })
+      .collect()
+  }
+}
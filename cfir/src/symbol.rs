@@ -1,13 +1,13 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Symbol(pub Rc<String>);
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(pub Arc<String>);
 
 impl Symbol {
   pub fn new(value: &str) -> Self {
-    Symbol(Rc::new(value.to_string()))
+    Symbol(Arc::new(value.to_string()))
   }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Name(pub Option<Symbol>, pub Symbol);
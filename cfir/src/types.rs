@@ -34,6 +34,15 @@ impl Type {
   }
 }
 
+/// Operand and result types of an op signature,
+/// the sign is either `(args) -> rets` or a plain list of result types.
+pub fn signature(sign: &[Type]) -> (&[Type], &[Type]) {
+  match sign {
+    [Type::FuncType(FuncType(params, rets))] => (params, rets),
+    _ => (&[], sign),
+  }
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct GenericType {
   pub name: Name,
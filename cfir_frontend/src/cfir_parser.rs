@@ -185,20 +185,13 @@ fn block_head_opt_parse_from(
 impl CFIRParseFrom for (Option<Symbol>, HashMap<Symbol, Type>) {
   fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
     debug_assert_eq!(pair.as_rule(), Rule::block_head);
-    if let Some(pair) = pair.into_inner().next() {
-      let mut pairs = pair.into_inner();
-      let pair0 = pairs.next().unwrap();
-      if let Some(pair1) = pairs.next() {
-        let label = CFIRParseFrom::parse_from(pair0, path);
-        let args: HashMap<Symbol, Type> = CFIRParseFrom::parse_from(pair1, path);
-        (Some(label), args)
-      } else {
-        let args: HashMap<Symbol, Type> = CFIRParseFrom::parse_from(pair0, path);
-        (None, args)
-      }
-    } else {
-      (None, HashMap::new())
-    }
+    let mut pairs = pair.into_inner();
+    let label: Label = next!(pairs, path);
+    let args = pairs
+      .next()
+      .map(|pair| CFIRParseFrom::parse_from(pair, path))
+      .unwrap_or_default();
+    (Some(label.0), args)
   }
 }
 
@@ -366,8 +359,13 @@ impl CFIRParseFrom for Constant {
     debug_assert_eq!(pair.as_rule(), Rule::constant);
     let pair = pair.into_inner().next().unwrap();
     match pair.as_rule() {
-      Rule::int_lit => str::parse(pair.as_str()).map(Constant::Int).unwrap(),
-      Rule::uint_lit => str::parse(pair.as_str()).map(Constant::Uint).unwrap(),
+      // parse the number without its `i`/`u` suffix
+      Rule::int_lit => str::parse(pair.into_inner().as_str())
+        .map(Constant::Int)
+        .unwrap(),
+      Rule::uint_lit => str::parse(pair.into_inner().as_str())
+        .map(Constant::Uint)
+        .unwrap(),
       Rule::bool_lit => str::parse(pair.as_str()).map(Constant::Bool).unwrap(),
       Rule::string_lit => str::parse(pair.as_str()).map(Constant::String).unwrap(),
       _ => unreachable!(),
@@ -448,4 +446,18 @@ mod test {
       println!("{:?}", i);
     }
   }
+  #[test]
+  fn test_block_head() {
+    use crate::cfir_parser::{CFIRParseFrom, Rule, CFIR};
+    use cfir::{op::Op, symbol::Symbol};
+    use pest::Parser;
+
+    let src = "scf.for (n) { ^body(i.int, j.int): scf.yield (i): (int) -> never }: (int) -> int";
+    let pair = CFIR::parse(Rule::op, src).unwrap().next().unwrap();
+    let op: Op = CFIRParseFrom::parse_from(pair, "<test>");
+    let block = &op.region[&Some(Symbol::new("body"))];
+    assert_eq!(block.0, Some(Symbol::new("body")));
+    assert_eq!(block.1.len(), 2);
+    assert_eq!(block.2.len(), 1);
+  }
 }
@@ -1,2 +1,3 @@
 pub mod cfir_parser;
 pub mod pattern_parser;
+pub mod rule_parser;
@@ -1,5 +1,5 @@
 use cfir::{
-  rewriter::pattern::{Catch, OpPat, OpPatHand, ValuePat},
+  rewriter::pattern::{Catch, OpMeta, OpPat, OpPatHand, OpcodePat, TypePat, ValuePat},
   symbol::{Name, Symbol},
   value::{Argument, Constant, Label, Order},
 };
@@ -64,16 +64,108 @@ impl PatternParseFrom for OpPat {
   fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
     debug_assert_eq!(pair.as_rule(), Rule::op_pat);
     let mut pairs = pair.into_inner();
-    let opcode: Name = next!(pairs, path);
+    let opcode = next!(pairs, path);
     let uses = next!(pairs, path);
-    // let attr: HashMap<Symbol, Constant> = next!(pairs, path);
-    OpPat(opcode, uses, vec![])
+    let attr = pairs
+      .next()
+      .unwrap()
+      .into_inner()
+      .map(|pair| PatternParseFrom::parse_from(pair, path))
+      .collect();
+    let sign = pairs.next().map(|pair| {
+      let pair = pair.into_inner().next().unwrap();
+      PatternParseFrom::parse_from(pair, path)
+    });
+    OpPat(opcode, uses, vec![], OpMeta { attr, sign })
+  }
+}
+
+impl PatternParseFrom for Catch<OpcodePat> {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    debug_assert_eq!(pair.as_rule(), Rule::opcode_pat);
+    let pair = pair.into_inner().next().unwrap();
+    match pair.as_rule() {
+      Rule::opcode_var => {
+        let mut pairs = pair.into_inner().next().unwrap().into_inner();
+        Catch(None, Some(next!(pairs, path)))
+      },
+      Rule::dialect_pat => {
+        let mut pairs = pair.into_inner();
+        Catch(Some(OpcodePat::Dialect(next!(pairs, path))), None)
+      },
+      _ => Catch(
+        Some(OpcodePat::Name(PatternParseFrom::parse_from(pair, path))),
+        None,
+      ),
+    }
+  }
+}
+
+impl PatternParseFrom for (Symbol, Catch<Constant>) {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    debug_assert_eq!(pair.as_rule(), Rule::attr_entry_pat);
+    let mut pairs = pair.into_inner();
+    let key = next!(pairs, path);
+    let pair = pairs.next().unwrap();
+    let value = if pair.as_rule() == Rule::var {
+      let mut pairs = pair.into_inner();
+      Catch(None, Some(next!(pairs, path)))
+    } else {
+      Catch(Some(PatternParseFrom::parse_from(pair, path)), None)
+    };
+    (key, value)
+  }
+}
+
+impl PatternParseFrom for Vec<TypePat> {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    debug_assert_eq!(pair.as_rule(), Rule::type_list_pat);
+    pair
+      .into_inner()
+      .map(|pair| PatternParseFrom::parse_from(pair, path))
+      .collect()
+  }
+}
+
+impl PatternParseFrom for TypePat {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    let pair = match pair.as_rule() {
+      Rule::type_pat | Rule::type_arg_pat => pair.into_inner().next().unwrap(),
+      _ => pair,
+    };
+    let mut pairs = pair.clone().into_inner();
+    match pair.as_rule() {
+      Rule::var => TypePat::Var(next!(pairs, path)),
+      Rule::constant => TypePat::Const(PatternParseFrom::parse_from(pair, path)),
+      Rule::type_pat => PatternParseFrom::parse_from(pair, path),
+      Rule::generic_type_pat => {
+        let name = next!(pairs, path);
+        TypePat::Generic(
+          name,
+          pairs
+            .map(|pair| PatternParseFrom::parse_from(pair, path))
+            .collect(),
+        )
+      },
+      _ => {
+        // pair.as_rule() == Rule::func_type_pat
+        let mut params = pairs.collect::<Vec<_>>();
+        let rets = params.pop().unwrap();
+        TypePat::Func(
+          params
+            .into_iter()
+            .map(|pair| PatternParseFrom::parse_from(pair, path))
+            .collect(),
+          PatternParseFrom::parse_from(rets, path),
+        )
+      },
+    }
   }
 }
 
 impl PatternParseFrom for Vec<Catch<ValuePat>> {
   fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
-    debug_assert_eq!(pair.as_rule(), Rule::uses);
+    debug_assert!(matches!(pair.as_rule(), Rule::uses | Rule::multi_pat));
     pair
       .into_inner()
       .map(|pair| PatternParseFrom::parse_from(pair, path))
@@ -164,8 +256,13 @@ impl PatternParseFrom for Constant {
     debug_assert_eq!(pair.as_rule(), Rule::constant);
     let pair = pair.into_inner().next().unwrap();
     match pair.as_rule() {
-      Rule::int_lit => str::parse(pair.as_str()).map(Constant::Int).unwrap(),
-      Rule::uint_lit => str::parse(pair.as_str()).map(Constant::Uint).unwrap(),
+      // parse the number without its `i`/`u` suffix
+      Rule::int_lit => str::parse(pair.into_inner().as_str())
+        .map(Constant::Int)
+        .unwrap(),
+      Rule::uint_lit => str::parse(pair.into_inner().as_str())
+        .map(Constant::Uint)
+        .unwrap(),
       Rule::bool_lit => str::parse(pair.as_str()).map(Constant::Bool).unwrap(),
       Rule::string_lit => str::parse(pair.as_str()).map(Constant::String).unwrap(),
       _ => unreachable!(),
@@ -212,6 +309,22 @@ macro_rules! value_pat {
   }};
 }
 
+#[macro_export]
+macro_rules! catch {
+  ($src:expr) => {{
+    use cfir::rewriter::pattern::{Catch, ValuePat};
+    use cfir_frontend::pattern_parser::Pattern;
+    use cfir_frontend::pattern_parser::{PatternParseFrom, Rule};
+    use pest::Parser;
+    let pair = Pattern::parse(Rule::catch, $src).unwrap();
+    pair
+      .into_iter()
+      .map(|pair| -> Catch<ValuePat> { PatternParseFrom::parse_from(pair, "<test>") })
+      .next()
+      .unwrap()
+  }};
+}
+
 mod test {
   #[test]
   fn test_parser() {
@@ -230,4 +343,68 @@ mod test {
       println!("{:?}", i);
     }
   }
+  #[test]
+  fn test_meta() {
+    use pest::Parser;
+
+    use crate::pattern_parser::{Pattern, PatternParseFrom, Rule};
+    use cfir::{
+      rewriter::pattern::{Catch, OpPat, TypePat},
+      symbol::Symbol,
+      value::Constant,
+    };
+
+    let parse = |src| -> OpPat {
+      let pair = Pattern::parse(Rule::op_pat, src).unwrap().next().unwrap();
+      PatternParseFrom::parse_from(pair, "<test>")
+    };
+    let op = parse("add(?a, ?b) [inline: true, k: ?v]: (?t, ?t) -> ?t");
+    assert_eq!(
+      op.3.attr,
+      vec![
+        (
+          Symbol::new("inline"),
+          Catch(Some(Constant::Bool(true)), None)
+        ),
+        (Symbol::new("k"), Catch(None, Some(Symbol::new("v")))),
+      ]
+    );
+    let t = TypePat::Var(Symbol::new("t"));
+    assert_eq!(
+      op.3.sign,
+      Some(vec![TypePat::Func(vec![t.clone(), t.clone()], vec![t])])
+    );
+    assert_eq!(op.vars().len(), 6);
+    assert!(parse("add(?a, ?b)").3.is_empty());
+  }
+  #[test]
+  fn test_opcode() {
+    use pest::Parser;
+
+    use crate::pattern_parser::{Pattern, PatternParseFrom, Rule};
+    use cfir::{
+      rewriter::pattern::{Catch, OpPat, OpcodePat, ValuePat},
+      symbol::Symbol,
+    };
+
+    let parse = |src| -> OpPat {
+      let pair = Pattern::parse(Rule::op_pat, src).unwrap().next().unwrap();
+      PatternParseFrom::parse_from(pair, "<test>")
+    };
+    let op = parse("?op(?x, ?y:arthi.*(?a), ?z:?f(?b))");
+    assert_eq!(op.0, Catch(None, Some(Symbol::new("op"))));
+    assert_eq!(op.1[0], Catch(None, Some(Symbol::new("x"))));
+    let Some(ValuePat::Use(y, _)) = &op.1[1].0 else {
+      panic!("{:?}", op.1[1]);
+    };
+    assert_eq!(
+      y.as_ref().borrow().0,
+      Catch(Some(OpcodePat::Dialect(Symbol::new("arthi"))), None)
+    );
+    let Some(ValuePat::Use(z, _)) = &op.1[2].0 else {
+      panic!("{:?}", op.1[2]);
+    };
+    assert_eq!(z.as_ref().borrow().0, Catch(None, Some(Symbol::new("f"))));
+    assert_eq!(op.vars().len(), 7);
+  }
 }
@@ -0,0 +1,403 @@
+use std::{fmt, path::Path};
+
+use cfir::{
+  rewriter::{
+    pattern::{Catch, ValuePat},
+    rule::{Cond, Operand, RuleDef, RuleGroup, RuleLhs, RuleRhs},
+  },
+  symbol::Symbol,
+  value::Constant,
+};
+use pest::{error::LineColLocation, iterators::Pair, Parser};
+
+use crate::pattern_parser::{ParseError, Pattern, PatternParseFrom, Rule};
+
+macro_rules! next {
+  ($pairs:expr, $path:expr) => {
+    PatternParseFrom::parse_from($pairs.next().unwrap(), $path)
+  };
+}
+
+/// Error in a rule file, `line` and `col` start from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub path: String,
+  pub line: usize,
+  pub col: usize,
+  pub message: String,
+}
+
+impl Diagnostic {
+  fn at(pair: &Pair<Rule>, path: &str, message: String) -> Self {
+    let (line, col) = pair.as_span().start_pos().line_col();
+    Diagnostic {
+      path: path.to_string(),
+      line,
+      col,
+      message,
+    }
+  }
+
+  fn from_parse_error(error: ParseError, path: &str) -> Self {
+    let (line, col) = match error.line_col {
+      LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+    };
+    Diagnostic {
+      path: path.to_string(),
+      line,
+      col,
+      message: error.variant.message().to_string(),
+    }
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}:{}:{}: {}",
+      self.path, self.line, self.col, self.message
+    )
+  }
+}
+
+/// Parse the source of a `.cfrules` file.
+pub fn parse_rules(src: &str, path: &str) -> Result<Vec<RuleGroup>, Vec<Diagnostic>> {
+  let file = Pattern::parse(Rule::rule_file, src)
+    .map_err(|error| vec![Diagnostic::from_parse_error(error, path)])?
+    .next()
+    .unwrap();
+
+  let mut errors = vec![];
+  let mut names: Vec<Symbol> = vec![];
+  let mut rule_defs = |pairs: Vec<Pair<Rule>>, errors: &mut Vec<Diagnostic>| {
+    let mut rules = vec![];
+    for pair in pairs {
+      match rule_def_parse_from(pair.clone(), path) {
+        Ok(defs) => {
+          for def in defs {
+            if names.contains(&def.name) {
+              let message = format!("rule `{}` is defined twice", def.name.0);
+              errors.push(Diagnostic::at(&pair, path, message));
+            }
+            names.push(def.name.clone());
+            rules.push(def);
+          }
+        },
+        Err(e) => errors.extend(e),
+      }
+    }
+    rules
+  };
+
+  let mut top = vec![];
+  let mut groups = vec![];
+  for pair in file.into_inner() {
+    match pair.as_rule() {
+      Rule::rule_group => {
+        let mut pairs = pair.into_inner();
+        let name: Symbol = next!(pairs, path);
+        let rules = rule_defs(pairs.collect(), &mut errors);
+        groups.push(RuleGroup {
+          name: Some(name),
+          rules,
+        });
+      },
+      Rule::rule_def => top.extend(rule_defs(vec![pair], &mut errors)),
+      Rule::EOI => {},
+      _ => unreachable!(),
+    }
+  }
+  if !top.is_empty() {
+    groups.insert(
+      0,
+      RuleGroup {
+        name: None,
+        rules: top,
+      },
+    );
+  }
+
+  if errors.is_empty() {
+    Ok(groups)
+  } else {
+    Err(errors)
+  }
+}
+
+/// Read and parse a `.cfrules` file.
+pub fn load_rules(path: impl AsRef<Path>) -> Result<Vec<RuleGroup>, Vec<Diagnostic>> {
+  let path = path.as_ref().display().to_string();
+  let src = std::fs::read_to_string(&path).map_err(|error| {
+    vec![Diagnostic {
+      path: path.clone(),
+      line: 1,
+      col: 1,
+      message: error.to_string(),
+    }]
+  })?;
+  parse_rules(&src, &path)
+}
+
+/// A `<=>` rule gives the rule and its reverse.
+pub fn rule_def_parse_from(pair: Pair<Rule>, path: &str) -> Result<Vec<RuleDef>, Vec<Diagnostic>> {
+  debug_assert_eq!(pair.as_rule(), Rule::rule_def);
+  let mut pairs = pair.clone().into_inner();
+  let name: Symbol = next!(pairs, path);
+  let lhs: RuleLhs = next!(pairs, path);
+  let bidirectional = pairs.next().unwrap().as_str() == "<=>";
+  let rhs: RuleRhs = next!(pairs, path);
+  let guard = pairs.next();
+  let guards: Vec<Cond> = guard
+    .clone()
+    .map(|pair| PatternParseFrom::parse_from(pair, path))
+    .unwrap_or_default();
+  // unknown predicates are reported at the condition that calls them
+  let guard_errors = guard
+    .into_iter()
+    .flat_map(Pair::into_inner)
+    .zip(&guards)
+    .filter_map(|(pair, cond)| {
+      let message = cond.check().err()?;
+      Some(Diagnostic::at(
+        &pair,
+        path,
+        format!("guard of rule `{}`: {}", name.0, message),
+      ))
+    })
+    .collect::<Vec<_>>();
+  let def = RuleDef {
+    name,
+    lhs,
+    rhs,
+    guards,
+  };
+
+  let mut errors = vec![];
+  let mut defs = vec![def.clone()];
+  if bidirectional {
+    match def.reversed() {
+      Some(rev) => defs.push(rev),
+      None => errors.push(format!(
+        "the rhs of `<=>` rule `{}` is not an op pattern",
+        def.name.0
+      )),
+    }
+  }
+  if let RuleLhs::Multi(lhs) = &def.lhs {
+    if !lhs
+      .iter()
+      .all(|pat| matches!(pat.0, Some(ValuePat::Use(..))))
+    {
+      errors.push(format!(
+        "every pattern of multi-pattern rule `{}` must be an op pattern",
+        def.name.0
+      ));
+    }
+  }
+  let lhs_vars = def.lhs.vars();
+  if let RuleRhs::Multi(rhs) = &def.rhs {
+    for pat in rhs {
+      match pat {
+        Catch(Some(_), Some(sym)) if lhs_vars.contains(sym) => {},
+        _ => errors.push(format!(
+          "every term of the rhs of rule `{}` must be `?x:pat` with `?x` bound by the lhs",
+          def.name.0
+        )),
+      }
+    }
+  }
+  for def in &defs {
+    for dialect in def.rhs.wildcards() {
+      errors.push(format!(
+        "the rhs of rule `{}` can not build an op of `{}.*`, bind its opcode with `?op`",
+        def.name.0, dialect.0
+      ));
+    }
+    let lhs_vars = def.lhs.vars();
+    for sym in def.rhs.unbound_vars(lhs_vars.clone()) {
+      errors.push(format!(
+        "`?{}` is not bound by the lhs of rule `{}`",
+        sym.0, def.name.0
+      ));
+    }
+    for sym in def.guards.iter().flat_map(Cond::vars) {
+      if !lhs_vars.contains(&sym) {
+        errors.push(format!(
+          "guard of rule `{}` uses `?{}`, which is not bound by the lhs",
+          def.name.0, sym.0
+        ));
+      }
+    }
+  }
+  errors.dedup();
+
+  if errors.is_empty() && guard_errors.is_empty() {
+    Ok(defs)
+  } else {
+    Err(
+      errors
+        .into_iter()
+        .map(|message| Diagnostic::at(&pair, path, message))
+        .chain(guard_errors)
+        .collect(),
+    )
+  }
+}
+
+impl PatternParseFrom for RuleLhs {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    debug_assert_eq!(pair.as_rule(), Rule::rule_lhs);
+    let pair = pair.into_inner().next().unwrap();
+    if pair.as_rule() == Rule::multi_pat {
+      RuleLhs::Multi(PatternParseFrom::parse_from(pair, path))
+    } else {
+      RuleLhs::Op(PatternParseFrom::parse_from(pair, path))
+    }
+  }
+}
+
+impl PatternParseFrom for RuleRhs {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    debug_assert_eq!(pair.as_rule(), Rule::rule_rhs);
+    let pair = pair.into_inner().next().unwrap();
+    if pair.as_rule() == Rule::multi_pat {
+      RuleRhs::Multi(PatternParseFrom::parse_from(pair, path))
+    } else {
+      RuleRhs::Value(PatternParseFrom::parse_from(pair, path))
+    }
+  }
+}
+
+impl PatternParseFrom for Vec<Cond> {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    debug_assert_eq!(pair.as_rule(), Rule::guard);
+    pair
+      .into_inner()
+      .map(|pair| PatternParseFrom::parse_from(pair, path))
+      .collect()
+  }
+}
+
+impl PatternParseFrom for Cond {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    debug_assert_eq!(pair.as_rule(), Rule::cond);
+    let pair = pair.into_inner().next().unwrap();
+    let mut pairs = pair.clone().into_inner();
+    if pair.as_rule() == Rule::cond_cmp {
+      let lhs = next!(pairs, path);
+      let op = pairs.next().unwrap().as_str();
+      let rhs = next!(pairs, path);
+      if op == "==" {
+        Cond::Eq(lhs, rhs)
+      } else {
+        Cond::Ne(lhs, rhs)
+      }
+    } else {
+      // pair.as_rule() == Rule::cond_call
+      let name = next!(pairs, path);
+      Cond::Call(
+        name,
+        pairs
+          .map(|pair| PatternParseFrom::parse_from(pair, path))
+          .collect(),
+      )
+    }
+  }
+}
+
+impl PatternParseFrom for Operand {
+  fn parse_from(pair: Pair<Rule>, path: &str) -> Self {
+    debug_assert_eq!(pair.as_rule(), Rule::operand);
+    let pair = pair.into_inner().next().unwrap();
+    if pair.as_rule() == Rule::var {
+      let mut pairs = pair.into_inner();
+      Operand::Var(next!(pairs, path))
+    } else {
+      // pair.as_rule() == Rule::constant
+      Operand::Const(Constant::parse_from(pair, path))
+    }
+  }
+}
+
+mod test {
+  #[test]
+  fn test_rule_parser() {
+    use crate::rule_parser::parse_rules;
+    use cfir::rewriter::rule::{Cond, Operand, RuleLhs, RuleRhs};
+    use cfir::{symbol::Symbol, value::Constant};
+
+    let src = "
+    // x + 0 = x
+    add-zero: arthi.add(?x, 0) => ?x
+
+    group comm {
+      add-comm: arthi.add(?a, ?b) <=> arthi.add(?b, ?a)
+      udiv-8: arthi.div(?x, ?c) => arthi.shr(?x, 3) if ?c == 8u && unsigned(?x)
+    }";
+    let groups = parse_rules(src, "<test>").unwrap();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].name, None);
+    assert_eq!(groups[1].name, Some(Symbol::new("comm")));
+    let names: Vec<_> = groups[1].rules.iter().map(|r| r.name.0.as_str()).collect();
+    assert_eq!(names, ["add-comm", "add-comm-rev", "udiv-8"]);
+    assert_eq!(
+      groups[1].rules[2].guards,
+      vec![
+        Cond::Eq(
+          Operand::Var(Symbol::new("c")),
+          Operand::Const(Constant::Uint(8))
+        ),
+        Cond::Call(
+          Symbol::new("unsigned"),
+          vec![Operand::Var(Symbol::new("x"))]
+        ),
+      ]
+    );
+
+    let src = "id: a(?x) => ?x\nid: a(?x) => ?x\nbad: arthi.add(?x, 0) => ?y";
+    let errors = parse_rules(src, "<test>")
+      .unwrap_err()
+      .into_iter()
+      .map(|e| e.to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      errors,
+      [
+        "<test>:2:1: rule `id` is defined twice",
+        "<test>:3:1: `?y` is not bound by the lhs of rule `bad`",
+      ]
+    );
+    assert!(parse_rules("bad: arthi.add(?x, 0) =>", "<test>").is_err());
+
+    let src = "ok: a(?x) => ?x if commutative(?x)\nbad: a(?x, ?y) => ?x if ?y == 1 && power2(?x) && const(?x, ?y)";
+    let errors = parse_rules(src, "<test>")
+      .unwrap_err()
+      .into_iter()
+      .map(|e| e.to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      errors,
+      [
+        "<test>:2:36: guard of rule `bad`: unknown predicate `power2`",
+        "<test>:2:50: guard of rule `bad`: predicate `const` takes one variable",
+      ]
+    );
+
+    let src =
+      "divrem: ?q:arthi.div(?a, ?b), ?r:arthi.rem(?a, ?b) => ?q:divrem(?a, ?b), ?r:divrem(?a, ?b)";
+    let groups = parse_rules(src, "<test>").unwrap();
+    assert!(matches!(groups[0].rules[0].lhs, RuleLhs::Multi(ref pats) if pats.len() == 2));
+    assert!(matches!(groups[0].rules[0].rhs, RuleRhs::Multi(ref pats) if pats.len() == 2));
+    assert!(parse_rules(
+      "bad: ?q:arthi.div(?a, ?b), ?r:arthi.rem(?a, ?b) => ?s:f(?a), ?r:f(?b)",
+      "<test>"
+    )
+    .is_err());
+    assert!(parse_rules(
+      "bad: ?q:arthi.div(?a, ?b), ?r:arthi.rem(?a, ?b) <=> ?q:f(?a), ?r:f(?b)",
+      "<test>"
+    )
+    .is_err());
+  }
+}
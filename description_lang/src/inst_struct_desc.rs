@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 /// example:
-/// ```
+/// ```text
 /// sip(Register(r)) = (r)
 /// sip(Literal(l)) = (0x114, l)
 /// ```
@@ -0,0 +1,26 @@
+use crate::{eclass::Id, egraph::EGraph, enode::RawENode};
+
+/// E-class analysis over the data `D` carried by every eclass.
+///
+/// The analysis is implemented on the data type itself, so an `EGraph<D>`
+/// always knows how to compute and maintain its `D`.
+pub trait Analysis: Sized + Clone {
+  /// Data of a fresh eclass holding only `enode`.
+  /// The uses of `enode` are canonical and their data is up to date.
+  fn make(egraph: &EGraph<Self>, enode: &RawENode<Self>) -> Self;
+
+  /// Merge `other` into `self`, returns whether `self` changed.
+  fn merge(&mut self, other: Self) -> bool;
+
+  /// Hook called after an eclass is created or its data changed,
+  /// it may add nodes or union eclasses.
+  fn modify(_egraph: &mut EGraph<Self>, _id: &Id<Self>) {}
+}
+
+impl Analysis for () {
+  fn make(_egraph: &EGraph<Self>, _enode: &RawENode<Self>) -> Self {}
+
+  fn merge(&mut self, _other: Self) -> bool {
+    false
+  }
+}
@@ -0,0 +1,197 @@
+use cfir::{
+  symbol::Name,
+  types::{signature, GenericType, Type},
+  value::Constant,
+};
+
+use crate::{
+  analysis::Analysis,
+  eclass::Id,
+  egraph::EGraph,
+  enode::{EOp, RawENode},
+};
+
+/// Constant-folding analysis for the integer and boolean ops of `arthi`.
+/// Eclasses with a known value get a `RawENode::Const` node.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConstFold(pub Option<Constant>);
+
+impl Analysis for ConstFold {
+  fn make(_egraph: &EGraph<Self>, enode: &RawENode<Self>) -> Self {
+    match enode {
+      RawENode::Const(c) => ConstFold(Some(c.clone())),
+      RawENode::Use(op, 0) => ConstFold(fold_op(&op.as_ref().borrow())),
+      _ => ConstFold(None),
+    }
+  }
+
+  fn merge(&mut self, other: Self) -> bool {
+    match (&self.0, other.0) {
+      (None, Some(c)) => {
+        self.0 = Some(c);
+        true
+      },
+      // a fold and an untyped literal of the same value may differ in
+      // signedness, `l` is kept either way
+      (Some(_), Some(_)) | (_, None) => false,
+    }
+  }
+
+  fn modify(egraph: &mut EGraph<Self>, id: &Id<Self>) {
+    if let Some(c) = id.get_data().0 {
+      let (c, _) = egraph.add_raw_node(RawENode::Const(c));
+      egraph.union(id, &c);
+    }
+  }
+}
+
+/// Integer types known to the folder, with their width in bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntType {
+  Bool,
+  Int(u32),
+  Uint(u32),
+}
+
+impl IntType {
+  /// `bool`, `int`, `uint`, `i8`..`i64` and `u8`..`u64`.
+  pub fn from_type(ty: &Type) -> Option<Self> {
+    let Type::GenericType(GenericType {
+      name: Name(None, sym),
+      args,
+    }) = ty
+    else {
+      return None;
+    };
+    if !args.is_empty() {
+      return None;
+    }
+    let bits = |s: &str| s.parse().ok().filter(|b| matches!(b, 8 | 16 | 32 | 64));
+    match sym.0.as_str() {
+      "bool" => Some(IntType::Bool),
+      "int" => Some(IntType::Int(64)),
+      "uint" => Some(IntType::Uint(64)),
+      s => {
+        if let Some(s) = s.strip_prefix('i') {
+          bits(s).map(IntType::Int)
+        } else {
+          bits(s.strip_prefix('u')?).map(IntType::Uint)
+        }
+      },
+    }
+  }
+
+  /// Type of an untyped constant literal.
+  pub fn of(c: &Constant) -> Option<Self> {
+    match c {
+      Constant::Bool(_) => Some(IntType::Bool),
+      Constant::Int(_) => Some(IntType::Int(64)),
+      Constant::Uint(_) => Some(IntType::Uint(64)),
+      Constant::String(_) => None,
+    }
+  }
+
+  pub fn bits(self) -> u32 {
+    match self {
+      IntType::Bool => 1,
+      IntType::Int(bits) | IntType::Uint(bits) => bits,
+    }
+  }
+
+  /// Reinterpret the low bits of `v` as a value of this type.
+  pub fn wrap(self, v: i128) -> i128 {
+    match self {
+      IntType::Bool => v & 1,
+      IntType::Int(bits) => {
+        let shift = 128 - bits;
+        (v << shift) >> shift
+      },
+      IntType::Uint(bits) => v & ((1 << bits) - 1),
+    }
+  }
+
+  pub fn constant(self, v: i128) -> Constant {
+    match self {
+      IntType::Bool => Constant::Bool(self.wrap(v) != 0),
+      IntType::Int(_) => Constant::Int(self.wrap(v) as i64),
+      IntType::Uint(_) => Constant::Uint(self.wrap(v) as u64),
+    }
+  }
+}
+
+/// Numeric value of an integer or boolean constant.
+pub fn value(c: &Constant) -> Option<i128> {
+  match c {
+    Constant::Bool(b) => Some(*b as i128),
+    Constant::Int(i) => Some(*i as i128),
+    Constant::Uint(u) => Some(*u as i128),
+    Constant::String(_) => None,
+  }
+}
+
+fn fold_op<D: Clone + Into<Option<Constant>>>(op: &EOp<D>) -> Option<Constant> {
+  let Name(Some(dialect), opcode) = &op.opcode else {
+    return None;
+  };
+  if dialect.0.as_str() != "arthi" {
+    return None;
+  }
+  let args = op
+    .uses
+    .iter()
+    .map(|id| id.get_data().into())
+    .collect::<Option<Vec<_>>>()?;
+  let (params, rets) = signature(&op.sign);
+  // the literals decide the type only of ops without a signature
+  let arg_ty = match params.first() {
+    Some(param) => IntType::from_type(param)?,
+    None => IntType::of(args.first()?)?,
+  };
+  let compare = matches!(opcode.0.as_str(), "eq" | "ne" | "lt" | "le" | "gt" | "ge");
+  let ret = match rets.first().and_then(IntType::from_type) {
+    Some(ret) => ret,
+    None if compare => IntType::Bool,
+    None => arg_ty,
+  };
+  fold(opcode.0.as_str(), &args, arg_ty, ret)
+}
+
+/// Evaluate `opcode` on operands of type `arg_ty`, wrapping to `ret`.
+pub fn fold(opcode: &str, args: &[Constant], arg_ty: IntType, ret: IntType) -> Option<Constant> {
+  let args = args
+    .iter()
+    .map(|c| value(c).map(|v| arg_ty.wrap(v)))
+    .collect::<Option<Vec<_>>>()?;
+  let r = match (opcode, args.as_slice()) {
+    ("add", [a, b]) => a.wrapping_add(*b),
+    ("sub", [a, b]) => a.wrapping_sub(*b),
+    ("mul", [a, b]) => a.wrapping_mul(*b),
+    ("div" | "rem", [_, 0]) => return None,
+    // both truncate toward zero, `a` is sign-extended for signed types only
+    ("div", [a, b]) => a / b,
+    ("rem", [a, b]) => a % b,
+    ("and", [a, b]) => a & b,
+    ("or", [a, b]) => a | b,
+    ("xor", [a, b]) => a ^ b,
+    ("not", [a]) => !a,
+    ("shl" | "shr", [_, b]) if !(0..ret.bits() as i128).contains(b) => return None,
+    ("shl", [a, b]) => a << b,
+    // `a` is sign-extended for signed types only, so this is an arithmetic
+    // shift on signed and a logical shift on unsigned values
+    ("shr", [a, b]) => a >> b,
+    ("eq", [a, b]) => (a == b) as i128,
+    ("ne", [a, b]) => (a != b) as i128,
+    ("lt", [a, b]) => (a < b) as i128,
+    ("le", [a, b]) => (a <= b) as i128,
+    ("gt", [a, b]) => (a > b) as i128,
+    ("ge", [a, b]) => (a >= b) as i128,
+    _ => return None,
+  };
+  Some(ret.constant(r))
+}
+
+impl From<ConstFold> for Option<Constant> {
+  fn from(value: ConstFold) -> Self {
+    value.0
+  }
+}
@@ -0,0 +1,221 @@
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  fmt::Write,
+};
+
+use crate::{
+  eclass::Id,
+  egraph::EGraph,
+  enode::{ENode, RawENode},
+  matching::MatchRecord,
+};
+
+/// What `to_dot` and `to_json` show.
+pub struct DumpOptions<D> {
+  /// Eclass and radius, only the eclasses at most this many uses away from it
+  /// in either direction are shown.
+  pub focus: Option<(Id<D>, usize)>,
+  pub highlight: Vec<ENode<D>>,
+}
+
+impl<D> Default for DumpOptions<D> {
+  fn default() -> Self {
+    DumpOptions {
+      focus: None,
+      highlight: vec![],
+    }
+  }
+}
+
+impl<D> DumpOptions<D> {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn around(mut self, id: &Id<D>, radius: usize) -> Self {
+    self.focus = Some((id.clone(), radius));
+    self
+  }
+
+  pub fn highlight(mut self, nodes: impl IntoIterator<Item = ENode<D>>) -> Self {
+    self.highlight.extend(nodes);
+    self
+  }
+
+  /// Highlight the nodes matched by a rule, as returned by `search_rule`.
+  pub fn highlight_matches(self, matches: &[(ENode<D>, MatchRecord<D>)]) -> Self {
+    self.highlight(matches.iter().map(|(node, _)| node.clone()))
+  }
+}
+
+/// Eclasses to show, numbered in the order they were added, the numbers do
+/// not depend on the focus.
+struct View<D> {
+  classes: Vec<(usize, Id<D>)>,
+  index: HashMap<Id<D>, usize>,
+}
+
+impl<D> View<D> {
+  fn new(egraph: &EGraph<D>, opts: &DumpOptions<D>) -> Self {
+    let all = egraph.classes();
+    let shown = opts
+      .focus
+      .as_ref()
+      .map(|(id, radius)| neighborhood(&all, &id.find(), *radius));
+    let classes = all
+      .into_iter()
+      .enumerate()
+      .filter(|(_, id)| shown.as_ref().is_none_or(|shown| shown.contains(id)))
+      .collect::<Vec<_>>();
+    #[allow(clippy::mutable_key_type)]
+    let index = classes.iter().map(|(i, id)| (id.clone(), *i)).collect();
+    View { classes, index }
+  }
+
+  fn get(&self, id: &Id<D>) -> Option<usize> {
+    self.index.get(&id.find()).copied()
+  }
+}
+
+/// Eclasses at most `radius` uses away from `center`.
+#[allow(clippy::mutable_key_type)]
+fn neighborhood<D>(classes: &[Id<D>], center: &Id<D>, radius: usize) -> HashSet<Id<D>> {
+  let mut edges: HashMap<Id<D>, Vec<Id<D>>> = HashMap::new();
+  for id in classes {
+    for node in &id.as_ref().borrow().nodes {
+      for child in node.body.children() {
+        let child = child.find();
+        edges.entry(id.clone()).or_default().push(child.clone());
+        edges.entry(child).or_default().push(id.clone());
+      }
+    }
+  }
+  let mut seen = HashSet::from([center.clone()]);
+  let mut queue = VecDeque::from([(center.clone(), 0)]);
+  while let Some((id, dist)) = queue.pop_front() {
+    if dist == radius {
+      continue;
+    }
+    for next in edges.get(&id).into_iter().flatten() {
+      if seen.insert(next.clone()) {
+        queue.push_back((next.clone(), dist + 1));
+      }
+    }
+  }
+  seen
+}
+
+/// Label of a node, the opcode of an op and the atom itself otherwise.
+fn label<D>(node: &RawENode<D>) -> String {
+  match node {
+    RawENode::Use(op, 0) => op.as_ref().borrow().opcode.to_string(),
+    RawENode::Use(op, offset) => format!("{}#{}", op.as_ref().borrow().opcode, offset),
+    RawENode::Const(c) => c.to_string(),
+    RawENode::Argument(arg) => arg.to_string(),
+    RawENode::Label(label) => label.to_string(),
+    RawENode::Input(sym) => sym.to_string(),
+  }
+}
+
+fn escape(s: &str) -> String {
+  let mut out = String::new();
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      c if (c as u32) < 0x20 => {
+        let _ = write!(out, "\\u{:04x}", c as u32);
+      },
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+impl<D> EGraph<D> {
+  /// Graphviz graph with an eclass per cluster and an edge from every enode
+  /// to the eclasses it uses.
+  pub fn to_dot(&self, opts: &DumpOptions<D>) -> String {
+    let view = View::new(self, opts);
+    let mut out = String::from("digraph egraph {\n  compound=true\n  clusterrank=local\n");
+    let mut edges = String::new();
+    for (i, id) in &view.classes {
+      let eclass = id.as_ref().borrow();
+      let _ = writeln!(
+        out,
+        "  subgraph cluster_{} {{\n    style=dotted\n    label=\"{}\"",
+        i, i
+      );
+      for (j, node) in eclass.nodes.iter().enumerate() {
+        let style = match opts.highlight.contains(node) {
+          true => ", style=filled, fillcolor=yellow",
+          false => "",
+        };
+        let _ = writeln!(
+          out,
+          "    \"{}.{}\" [label=\"{}\"{}]",
+          i,
+          j,
+          escape(&label(&node.body)),
+          style
+        );
+        for (k, child) in node.body.children().iter().enumerate() {
+          // the edges to the eclasses out of focus are left out
+          let Some(c) = view.get(child) else {
+            continue;
+          };
+          let _ = writeln!(
+            edges,
+            "  \"{}.{}\" -> \"{}.0\" [lhead=cluster_{}, label=\"{}\"]",
+            i, j, c, c, k
+          );
+        }
+      }
+      out.push_str("  }\n");
+    }
+    out.push_str(&edges);
+    out.push_str("}\n");
+    out
+  }
+
+  /// JSON in the serialized egraph format of the egraph visualizers, a node
+  /// has the op, the eclass and the first node of every child eclass.
+  pub fn to_json(&self, opts: &DumpOptions<D>) -> String {
+    let view = View::new(self, opts);
+    let mut nodes = vec![];
+    for (i, id) in &view.classes {
+      for (j, node) in id.as_ref().borrow().nodes.iter().enumerate() {
+        let children = node
+          .body
+          .children()
+          .iter()
+          .filter_map(|child| Some(format!("\"{}.0\"", view.get(child)?)))
+          .collect::<Vec<_>>();
+        let mut entry = format!(
+          "\"{}.{}\": {{\"op\": \"{}\", \"children\": [{}], \"eclass\": \"{}\", \"cost\": 1.0",
+          i,
+          j,
+          escape(&label(&node.body)),
+          children.join(", "),
+          i
+        );
+        if opts.highlight.contains(node) {
+          entry.push_str(", \"highlight\": true");
+        }
+        entry.push('}');
+        nodes.push(entry);
+      }
+    }
+    let roots = self
+      .root
+      .iter()
+      .filter_map(|id| Some(format!("\"{}\"", view.get(id)?)))
+      .collect::<Vec<_>>();
+    format!(
+      "{{\"nodes\": {{{}}}, \"root_eclasses\": [{}]}}\n",
+      nodes.join(", "),
+      roots.join(", ")
+    )
+  }
+}
@@ -1,11 +1,22 @@
-use std::{cell::RefCell, hash::Hash, rc::Rc};
+use std::{cell::RefCell, fmt, hash::Hash, rc::Rc};
 
 use cfir::rewriter::form::{Form, GetForm};
 
 use crate::enode::ENode;
 
-#[derive(Debug)]
-pub struct Id<D>(pub Rc<RefCell<EClass<D>>>); // warning: multi-thread unsound
+/// Eclass handle, bound to the thread of its egraph. `EGraph::freeze` makes
+/// an index-based copy to search on other threads.
+pub struct Id<D>(pub Rc<RefCell<EClass<D>>>);
+
+/// Only the index, the eclass may be part of a cycle.
+impl<D> fmt::Debug for Id<D> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.0.try_borrow() {
+      Ok(class) => write!(f, "Id({})", class.index),
+      Err(_) => write!(f, "Id({:p})", Rc::as_ptr(&self.0)),
+    }
+  }
+}
 
 impl<D> Id<D> {
   pub fn new(value: EClass<D>) -> Self {
@@ -15,6 +26,34 @@ impl<D> Id<D> {
   pub fn get_forms(&self) -> Vec<Form> {
     self.as_ref().borrow().get_forms()
   }
+
+  /// Follow the union-find links to the canonical eclass.
+  pub fn find(&self) -> Id<D> {
+    let mut id = self.clone();
+    loop {
+      let leader = id.as_ref().borrow().leader.clone();
+      match leader {
+        Some(leader) => id = leader,
+        None => return id,
+      }
+    }
+  }
+
+  pub fn is_canonical(&self) -> bool {
+    self.as_ref().borrow().leader.is_none()
+  }
+
+  /// Order the eclass was added in, stable across runs.
+  pub fn index(&self) -> usize {
+    self.as_ref().borrow().index
+  }
+}
+
+impl<D: Clone> Id<D> {
+  /// Analysis data of the canonical eclass.
+  pub fn get_data(&self) -> D {
+    self.find().as_ref().borrow().data.clone()
+  }
 }
 
 impl<D> AsRef<RefCell<EClass<D>>> for Id<D> {
@@ -23,15 +62,35 @@ impl<D> AsRef<RefCell<EClass<D>>> for Id<D> {
   }
 }
 
+/// By address, the eclass behind an `Id` changes but never its key, so the
+/// maps keyed by `Id` allow `clippy::mutable_key_type`.
 impl<D> Hash for Id<D> {
   fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-    Rc::into_raw(self.0.clone()).hash(state);
+    Rc::as_ptr(&self.0).hash(state);
   }
 }
 
 impl<D> PartialEq for Id<D> {
   fn eq(&self, other: &Self) -> bool {
-    Rc::into_raw(self.0.clone()) as usize == Rc::into_raw(other.0.clone()) as usize
+    Rc::ptr_eq(&self.0, &other.0)
+  }
+}
+
+impl<D> Eq for Id<D> {}
+
+/// In the order the eclasses were added, the ids of one egraph.
+impl<D> Ord for Id<D> {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    self
+      .index()
+      .cmp(&other.index())
+      .then_with(|| Rc::as_ptr(&self.0).cmp(&Rc::as_ptr(&other.0)))
+  }
+}
+
+impl<D> PartialOrd for Id<D> {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
   }
 }
 
@@ -53,6 +112,10 @@ impl<D> Id<D> {
 pub struct EClass<D> {
   pub nodes: Vec<ENode<D>>,
   pub data: D,
+  /// Eclasses added to the egraph before this one.
+  pub index: usize,
+  /// Set once this eclass has been merged into another one.
+  pub leader: Option<Id<D>>,
 }
 
 impl<D: Default> Default for EClass<D> {
@@ -60,6 +123,8 @@ impl<D: Default> Default for EClass<D> {
     Self {
       nodes: Default::default(),
       data: Default::default(),
+      index: 0,
+      leader: None,
     }
   }
 }
@@ -73,10 +138,12 @@ impl<D: Default> From<ENode<D>> for EClass<D> {
 }
 
 impl<D> EClass<D> {
-  pub fn new(data: D) -> Self {
+  pub fn new(index: usize, data: D) -> Self {
     Self {
       nodes: Default::default(),
       data,
+      index,
+      leader: None,
     }
   }
 
@@ -1,20 +1,62 @@
+use std::{
+  cell::{OnceCell, RefCell},
+  collections::{hash_map::Entry, HashMap, HashSet},
+  rc::Rc,
+};
+
 use cfir::{
+  block::Region,
+  dialect::Registry,
   op::Op,
   rewriter::form::{Form, GetForm},
   value::Value,
 };
 
 use crate::{
-  eclass::Id,
-  elike::ELike,
+  analysis::Analysis,
+  eclass::{EClass, Id},
   enode::{ENode, EOp, EOpHand, RawENode},
+  explain::{Explain, Justification},
+  frontier::Frontier,
+  hashcons::{Hashcons, NodeKey},
+  region::Scope,
+  relational::Relations,
+  snapshot::Undo,
 };
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct EGraph<D> {
   pub root: Vec<Id<D>>,
-  pub likes: ELike<D>,
+  /// Every node by key and by opcode, the repaired nodes are keyed again on
+  /// rebuild.
+  pub hashcons: Hashcons<D>,
+  /// Every eclass added, merged ones too until `gc`.
   pub eclasses: Vec<Id<D>>,
+  /// Eclasses merged or changed since the last rebuild.
+  pub pending: Vec<Id<D>>,
+  /// Eclasses added or changed since the frontier was last taken.
+  pub touched: Vec<Id<D>>,
+  /// Eclasses new matches are rooted in, see `search_rule_in`.
+  pub(crate) frontier: Option<Frontier<D>>,
+  /// Relations of the search phase, indexed by the first query run in it,
+  /// see `search_phase`.
+  pub(crate) relations: Option<OnceCell<Relations<D>>>,
+  /// Changes since the oldest snapshot, `None` if none is taken.
+  pub(crate) log: Option<Vec<Undo<D>>>,
+  /// Snapshots taken so far.
+  pub(crate) marks: usize,
+  /// Eclasses added so far, the index of the next one.
+  pub(crate) added: usize,
+  /// Proof forest, recorded only if explanations are enabled.
+  pub explain: Option<Explain<D>>,
+  /// Traits of the opcodes, the effectful ops of a block are sequenced.
+  pub registry: Registry,
+}
+
+impl<D> Default for EGraph<D> {
+  fn default() -> Self {
+    EGraph::new()
+  }
 }
 
 impl<D> EGraph<D> {
@@ -22,14 +64,77 @@ impl<D> EGraph<D> {
     EGraph {
       root: Default::default(),
       eclasses: Default::default(),
-      likes: Default::default(),
+      hashcons: Default::default(),
+      pending: Default::default(),
+      touched: Default::default(),
+      frontier: None,
+      relations: None,
+      log: None,
+      marks: 0,
+      added: 0,
+      explain: None,
+      registry: Registry::builtin(),
+    }
+  }
+
+  pub fn with_registry(mut self, registry: Registry) -> Self {
+    self.registry = registry;
+    self
+  }
+
+  /// Canonical eclasses, in the order they were first added.
+  pub fn classes(&self) -> Vec<Id<D>> {
+    #[allow(clippy::mutable_key_type)]
+    let mut seen = HashSet::new();
+    self
+      .eclasses
+      .iter()
+      .map(Id::find)
+      .filter(|id| seen.insert(id.clone()))
+      .collect()
+  }
+
+  pub fn is_clean(&self) -> bool {
+    self.pending.is_empty()
+  }
+
+  pub(crate) fn rebuild_hashcons(&mut self) {
+    let mut hashcons = Hashcons::new();
+    for id in self.classes() {
+      for node in &id.as_ref().borrow().nodes {
+        hashcons.insert(node.clone());
+      }
     }
+    self.hashcons = hashcons;
   }
 }
 
-impl<D: Default> EGraph<D> {
+impl<D: Analysis> EGraph<D> {
   pub fn add_op(&mut self, o: &Op) -> (Id<D>, EOpHand<D>) {
-    let r = o.uses.iter().map(|i| self.add_value(i)).collect::<Vec<_>>();
+    self.add_op_in(o, &mut Scope::new())
+  }
+
+  /// Add `o` with the names of `scope` resolved, its blocks become trailing
+  /// `region.block` uses.
+  ///
+  /// In a block, an effectful op also uses the state left by the previous one
+  /// and leaves a new state as the result after its defs.
+  pub(crate) fn add_op_in(&mut self, o: &Op, scope: &mut Scope<D>) -> (Id<D>, EOpHand<D>) {
+    let mut r = o
+      .uses
+      .iter()
+      .map(|i| self.add_value_in(i, scope))
+      .collect::<Vec<_>>();
+    r.extend(self.add_region(&o.region, scope));
+    let state = match self.registry.is_pure(&o.opcode) {
+      true => None,
+      false => scope.state(),
+    };
+    if let Some(state) = &state {
+      let form = state.get_form().unwrap();
+      let (id, _) = self.add_raw_node(state.clone());
+      r.push((form, id));
+    }
 
     let form = r.iter().map(|(f, _)| Some(f.clone())).collect::<Vec<_>>();
 
@@ -45,49 +150,269 @@ impl<D: Default> EGraph<D> {
       defs: o.defs.clone(),
       uses,
       attr: o.attr.clone(),
-      region: o.region.clone(),
+      region: Region::new(),
       sign: o.sign.clone(),
+      symmetry: self.registry.symmetry(&o.opcode),
+      state: state.is_some(),
     };
     let eop = EOpHand::new(eop);
     let node = RawENode::Use(eop.clone(), 0); // FIXME: rewrite system
 
-    let (id, _node) = self.add_raw_node(node);
+    let (id, node) = self.add_raw_node(node);
+
+    // keep the deduplicated op, so the caller sees the one in the egraph
+    let eop = match node.body {
+      RawENode::Use(eop, _) => eop,
+      _ => eop,
+    };
+    if state.is_some() {
+      scope.set_state(RawENode::Use(eop.clone(), o.defs.len()));
+    }
 
     (id, eop)
   }
 
   pub fn add_value(&mut self, value: &Value) -> (Form, Id<D>) {
-    let node = self.make_enode(value);
+    self.add_value_in(value, &mut Scope::new())
+  }
+
+  pub(crate) fn add_value_in(&mut self, value: &Value, scope: &mut Scope<D>) -> (Form, Id<D>) {
+    let node = self.make_enode_in(value, scope);
     let f = node.get_form().unwrap();
     let (id, _node) = self.add_raw_node(node);
     (f, id)
   }
 
-  pub fn add_node(&mut self, node: ENode<D>) -> Id<D> {
-    let input_id = node.get_id();
-    let id = self.likes.add_node(&node.get_form().unwrap(), node);
-    if id != input_id {
-      self.eclasses.push(id.clone());
-    }
-    id
-  }
-
+  /// Add a node unless an equal one is in the egraph, returns its eclass and
+  /// the node in the egraph.
   pub fn add_raw_node(&mut self, node: RawENode<D>) -> (Id<D>, ENode<D>) {
-    let (id, enode) = self.likes.add_raw_node(&node.get_form().unwrap(), node);
+    self.canonicalize(&node);
+    if let Some(enode) = self.hashcons.get(&node).cloned() {
+      return (enode.get_id(), enode);
+    }
+    let data = D::make(self, &node);
+    let id = Id(Rc::new_cyclic(|eclass| {
+      let mut class = EClass::new(self.added, data);
+      class.add_node(ENode {
+        eclass: eclass.clone(),
+        body: node,
+      });
+      RefCell::new(class)
+    }));
+    self.added += 1;
+    let enode = id.as_ref().borrow().nodes[0].clone();
+    if let Some(explain) = self.explain.as_mut() {
+      explain.add(&enode);
+    }
+    self.hashcons.insert(enode.clone());
     self.eclasses.push(id.clone());
+    self.touched.push(id.clone());
+    self.with_reason(Justification::Analysis, |egraph| D::modify(egraph, &id));
     (id, enode)
   }
 
   pub fn make_enode(&mut self, value: &Value) -> RawENode<D> {
+    self.make_enode_in(value, &mut Scope::new())
+  }
+
+  fn make_enode_in(&mut self, value: &Value, scope: &mut Scope<D>) -> RawENode<D> {
     match value {
       Value::Use(op, offset) => {
-        let (_id, eop) = self.add_op(&op.as_ref().borrow());
+        let (_id, eop) = self.add_op_in(&op.as_ref().borrow(), scope);
         RawENode::Use(eop, *offset)
       },
       Value::Const(n) => RawENode::Const(n.clone()),
       Value::Argument(n) => RawENode::Argument(n.clone()),
       Value::Label(n) => RawENode::Label(n.clone()),
-      Value::Input(n) => RawENode::Input(n.clone()),
+      Value::Input(n) => scope
+        .resolve(n)
+        .unwrap_or_else(|| RawENode::Input(n.clone())),
+    }
+  }
+
+  /// Merge two eclasses, returns false if they were already equal.
+  /// The egraph must be rebuilt before it is matched again.
+  pub fn union(&mut self, a: &Id<D>, b: &Id<D>) -> bool {
+    let a = a.find().as_ref().borrow().nodes[0].clone();
+    let b = b.find().as_ref().borrow().nodes[0].clone();
+    self.union_nodes(&a, &b)
+  }
+
+  /// Merge the eclasses of two enodes, with explanations enabled the union
+  /// is recorded between these two enodes.
+  pub fn union_nodes(&mut self, a: &ENode<D>, b: &ENode<D>) -> bool {
+    if !self.merge(a.get_id(), b.get_id()) {
+      return false;
+    }
+    if let Some(explain) = self.explain.as_mut() {
+      explain.union(a, b);
+    }
+    true
+  }
+
+  fn merge(&mut self, a: Id<D>, b: Id<D>) -> bool {
+    if a == b {
+      return false;
+    }
+    // the bigger eclass stays the leader
+    let (leader, other) = if a.as_ref().borrow().nodes.len() >= b.as_ref().borrow().nodes.len() {
+      (a, b)
+    } else {
+      (b, a)
+    };
+    self.record(|| {
+      let class = leader.as_ref().borrow();
+      Undo::Merge {
+        leader: leader.clone(),
+        other: other.clone(),
+        nodes: class.nodes.len(),
+        data: class.data.clone(),
+      }
+    });
+    let (mut nodes, data) = {
+      let mut other = other.as_ref().borrow_mut();
+      other.leader = Some(leader.clone());
+      (std::mem::take(&mut other.nodes), other.data.clone())
+    };
+    for node in nodes.iter_mut() {
+      node.eclass = Rc::downgrade(&leader.0);
+    }
+    {
+      let mut leader = leader.as_ref().borrow_mut();
+      leader.nodes.extend(nodes);
+      leader.data.merge(data);
+    }
+    self.hashcons.merge_users(&leader, &other);
+    self.touched.push(leader.clone());
+    self.pending.push(leader);
+    true
+  }
+
+  /// Restore congruence closure and the analysis invariants after unions,
+  /// returns the number of unions found by congruence. Only the eclasses
+  /// merged or changed since the last rebuild and their users are repaired.
+  pub fn rebuild(&mut self) -> usize {
+    let mut unions = 0;
+    loop {
+      #[allow(clippy::mutable_key_type)]
+      let mut seen = HashSet::new();
+      let pending = std::mem::take(&mut self.pending)
+        .iter()
+        .map(Id::find)
+        .filter(|id| seen.insert(id.clone()))
+        .collect::<Vec<_>>();
+      if pending.is_empty() {
+        break;
+      }
+      unions += self.repair_congruence(&pending);
+      self.repair_analysis(&pending);
+      // the unions and changes found by the repairs are pending, they are
+      // repaired and modified in the next pass
+      self.with_reason(Justification::Analysis, |egraph| {
+        for id in &pending {
+          D::modify(egraph, &id.find());
+        }
+      });
+    }
+    self.root = self.root.iter().map(Id::find).collect();
+    unions
+  }
+
+  /// Canonical eclasses of the users of `pending`, in the order they were
+  /// used, after `pending` itself.
+  fn users_of(&self, pending: &[Id<D>]) -> Vec<Id<D>> {
+    #[allow(clippy::mutable_key_type)]
+    let mut seen = HashSet::new();
+    pending
+      .iter()
+      .cloned()
+      .chain(pending.iter().flat_map(|id| {
+        self
+          .hashcons
+          .users(&id.find())
+          .iter()
+          .map(ENode::get_id)
+          .collect::<Vec<_>>()
+      }))
+      .map(|id| id.find())
+      .filter(|id| seen.insert(id.clone()))
+      .collect()
+  }
+
+  /// Deduplicate the nodes of the eclasses using `pending` and merge the
+  /// congruent ones, every node whose key changed uses one of them. The
+  /// hashcons is updated for these nodes only.
+  fn repair_congruence(&mut self, pending: &[Id<D>]) -> usize {
+    #[allow(clippy::mutable_key_type)]
+    let mut memo: HashMap<NodeKey<D>, ENode<D>> = HashMap::new();
+    let mut congruent = vec![];
+    for id in self.users_of(pending) {
+      let nodes = std::mem::take(&mut id.as_ref().borrow_mut().nodes);
+      for node in &nodes {
+        self.hashcons.forget(node);
+      }
+      #[allow(clippy::mutable_key_type)]
+      let mut keys = HashSet::new();
+      let (mut uniq, mut dropped) = (Vec::with_capacity(nodes.len()), vec![]);
+      for node in &nodes {
+        self.canonicalize(&node.body);
+        // congruent nodes merged into one eclass are one node
+        match keys.insert(NodeKey::new(&node.body)) {
+          true => uniq.push(node.clone()),
+          false => dropped.push(node.clone()),
+        }
+      }
+      if !dropped.is_empty() {
+        self.hashcons.remove(&dropped);
+        self.record(|| Undo::Nodes(id.clone(), nodes));
+      }
+      for node in &uniq {
+        self.hashcons.memo(node.clone());
+      }
+      for node in &uniq {
+        // atoms are deduplicated on insertion and never become congruent
+        if !matches!(node.body, RawENode::Use(..)) {
+          continue;
+        }
+        match memo.entry(NodeKey::new(&node.body)) {
+          Entry::Occupied(other) => congruent.push((other.get().clone(), node.clone())),
+          Entry::Vacant(slot) => {
+            slot.insert(node.clone());
+          },
+        }
+      }
+      id.as_ref().borrow_mut().nodes = uniq;
+    }
+    self.with_reason(Justification::Congruence, |egraph| {
+      congruent
+        .into_iter()
+        .filter(|(a, b)| egraph.union_nodes(a, b))
+        .count()
+    })
+  }
+
+  /// Remake the data of the users of `pending` until it no longer changes,
+  /// an eclass whose data changed is repaired in turn.
+  fn repair_analysis(&mut self, pending: &[Id<D>]) {
+    let mut work = pending.to_vec();
+    let mut next = 0;
+    while let Some(changed) = work.get(next).map(Id::find) {
+      next += 1;
+      let users = self.hashcons.users(&changed).to_vec();
+      for node in users {
+        let id = node.get_id();
+        let data = D::make(self, &node.body);
+        let old = self
+          .log
+          .is_some()
+          .then(|| id.as_ref().borrow().data.clone());
+        if id.as_ref().borrow_mut().data.merge(data) {
+          self.record(|| Undo::Data(id.clone(), old.unwrap()));
+          self.touched.push(id.clone());
+          self.pending.push(id.clone());
+          work.push(id);
+        }
+      }
     }
   }
 }
@@ -5,10 +5,11 @@ use std::{
 
 use cfir::{
   block::Region,
+  dialect::Symmetry,
   op::Attr,
   rewriter::form::{Form, GetForm},
   symbol::{Name, Symbol},
-  types::Type,
+  types::{signature, Type},
   value::{Argument, Constant, Label},
 };
 
@@ -24,9 +25,15 @@ pub struct EOp<D> {
   pub defs: Vec<Symbol>,
   pub uses: Vec<Id<D>>,
   pub attr: Attr,
+  /// Empty for the ops added from cfir, their blocks are `region.block` uses.
   pub region: Region,
   // pub sign: FuncType,
   pub sign: Vec<Type>,
+  /// Symmetry of the opcode in the registry, looked up when the op is added.
+  pub symmetry: Symmetry,
+  /// Its last use is the state left by the previous effectful op of its
+  /// block, only effectful ops added in a block have one.
+  pub state: bool,
 }
 
 impl<D> GetForm for EOp<D> {
@@ -75,6 +82,11 @@ impl<D> EOpHand<D> {
   pub fn new(value: EOp<D>) -> Self {
     Self(Rc::new(RefCell::new(value)))
   }
+
+  /// Address of the op, which identifies it while it is alive.
+  pub fn as_ptr(&self) -> *const RefCell<EOp<D>> {
+    Rc::as_ptr(&self.0)
+  }
 }
 
 impl<D> AsRef<RefCell<EOp<D>>> for EOpHand<D> {
@@ -90,8 +102,9 @@ pub struct ENode<D> {
 }
 
 impl<D> ENode<D> {
+  /// The canonical eclass this node belongs to.
   pub fn get_id(&self) -> Id<D> {
-    Id(self.eclass.upgrade().unwrap())
+    Id(self.eclass.upgrade().unwrap()).find()
   }
 }
 
@@ -127,6 +140,36 @@ pub enum RawENode<D> {
   Input(Symbol),
 }
 
+impl<D> RawENode<D> {
+  /// Eclasses used by this node, empty for atoms.
+  pub fn children(&self) -> Vec<Id<D>> {
+    match self {
+      RawENode::Use(op, _) => op.as_ref().borrow().uses.clone(),
+      _ => vec![],
+    }
+  }
+
+  /// Result type of this node, known for op results only.
+  pub fn get_type(&self) -> Option<Type> {
+    match self {
+      RawENode::Use(op, offset) => signature(&op.as_ref().borrow().sign)
+        .1
+        .get(*offset)
+        .cloned(),
+      _ => None,
+    }
+  }
+
+  /// Point the uses of this node at their canonical eclasses.
+  pub fn canonicalize(&self) {
+    if let RawENode::Use(op, _) = self {
+      for id in op.as_ref().borrow_mut().uses.iter_mut() {
+        *id = id.find();
+      }
+    }
+  }
+}
+
 impl<D> Clone for RawENode<D> {
   fn clone(&self) -> Self {
     match self {
@@ -142,8 +185,8 @@ impl<D> Clone for RawENode<D> {
 impl<D> GetForm for RawENode<D> {
   fn get_form(&self) -> Option<Form> {
     match self {
-      RawENode::Use(op, 0) => op.get_form(),
-      RawENode::Use(_op, _) => None, // FIXME: rewrite system
+      // every result of an op has the form of the op, matching checks the offset
+      RawENode::Use(op, _) => op.get_form(),
       RawENode::Const(_) | RawENode::Argument(_) | RawENode::Label(_) | RawENode::Input(_) => {
         Some(Form::Atom)
       },
@@ -158,6 +201,7 @@ impl<D> PartialEq for RawENode<D> {
       (Self::Use(l0, l1), Self::Use(r0, r1)) => l0 == r0 && l1 == r1,
       (Self::Argument(l0), Self::Argument(r0)) => l0 == r0,
       (Self::Label(l0), Self::Label(r0)) => l0 == r0,
+      (Self::Input(l0), Self::Input(r0)) => l0 == r0,
       _ => false,
     }
   }
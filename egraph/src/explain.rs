@@ -0,0 +1,366 @@
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  fmt,
+};
+
+use cfir::{
+  op::{Op, OpHand},
+  symbol::Symbol,
+  value::{Argument, Constant, Label, Value},
+};
+
+use crate::{
+  egraph::EGraph,
+  enode::{ENode, RawENode},
+  matching::MatchRecord,
+};
+
+/// Why two enodes were unioned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Justification {
+  /// Unioned by the user.
+  Given,
+  /// Unioned by `Analysis::modify`.
+  Analysis,
+  /// The children of both nodes are equal.
+  Congruence,
+  /// Rule name and the substitution of its lhs, as node indices.
+  Rule(Symbol, Vec<(Symbol, usize)>),
+}
+
+/// Identity of an enode, ops by address and atoms by value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum NodeKey {
+  Op(usize, usize),
+  Const(Constant),
+  Argument(Argument),
+  Label(Label),
+  Input(Symbol),
+}
+
+impl NodeKey {
+  fn new<D>(node: &RawENode<D>) -> Self {
+    match node {
+      RawENode::Use(op, offset) => NodeKey::Op(op.as_ptr() as usize, *offset),
+      RawENode::Const(c) => NodeKey::Const(c.clone()),
+      RawENode::Argument(arg) => NodeKey::Argument(arg.clone()),
+      RawENode::Label(label) => NodeKey::Label(label.clone()),
+      RawENode::Input(sym) => NodeKey::Input(sym.clone()),
+    }
+  }
+}
+
+/// Proof forest over the enodes of an egraph, every successful union adds one
+/// edge between the two enodes it was asked to union.
+///
+/// Each enode keeps the children it had when it was added, so the terms of an
+/// explanation are the terms that were added and rewritten, not the canonical ones.
+pub struct Explain<D> {
+  nodes: Vec<ENode<D>>,
+  children: Vec<Vec<usize>>,
+  index: HashMap<NodeKey, usize>,
+  edges: Vec<Vec<(usize, usize)>>,
+  reasons: Vec<(usize, usize, Justification)>,
+  /// Justification of the unions made until it is changed.
+  pub(crate) reason: Justification,
+}
+
+impl<D> Default for Explain<D> {
+  fn default() -> Self {
+    Explain {
+      nodes: vec![],
+      children: vec![],
+      index: HashMap::new(),
+      edges: vec![],
+      reasons: vec![],
+      reason: Justification::Given,
+    }
+  }
+}
+
+impl<D> fmt::Debug for Explain<D> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Explain")
+      .field("nodes", &self.nodes.len())
+      .field("unions", &self.reasons.len())
+      .finish()
+  }
+}
+
+impl<D> Explain<D> {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Enodes and unions recorded.
+  pub(crate) fn len(&self) -> (usize, usize) {
+    (self.nodes.len(), self.reasons.len())
+  }
+
+  /// Forget the enodes and unions recorded after the first `nodes` and `unions`.
+  pub(crate) fn truncate(&mut self, nodes: usize, unions: usize) {
+    self.nodes.truncate(nodes);
+    self.children.truncate(nodes);
+    self.edges.truncate(nodes);
+    self.index.retain(|_, i| *i < nodes);
+    self.reasons.truncate(unions);
+    for edges in self.edges.iter_mut() {
+      edges.retain(|&(_, reason)| reason < unions);
+    }
+  }
+
+  fn get(&self, node: &RawENode<D>) -> Option<usize> {
+    self.index.get(&NodeKey::new(node)).copied()
+  }
+
+  /// Index of `node`, registering it and its children on first sight.
+  pub(crate) fn add(&mut self, node: &ENode<D>) -> usize {
+    if let Some(i) = self.get(&node.body) {
+      return i;
+    }
+    let children = node
+      .body
+      .children()
+      .iter()
+      .map(|id| {
+        let child = id.find().as_ref().borrow().nodes[0].clone();
+        self.add(&child)
+      })
+      .collect();
+    let i = self.nodes.len();
+    self.index.insert(NodeKey::new(&node.body), i);
+    self.nodes.push(node.clone());
+    self.children.push(children);
+    self.edges.push(vec![]);
+    i
+  }
+
+  pub(crate) fn union(&mut self, a: &ENode<D>, b: &ENode<D>) {
+    let (a, b) = (self.add(a), self.add(b));
+    let reason = self.reasons.len();
+    self.reasons.push((a, b, self.reason.clone()));
+    self.edges[a].push((b, reason));
+    self.edges[b].push((a, reason));
+  }
+
+  /// Justification of applying `rule` with `record`.
+  pub(crate) fn rule(&mut self, rule: &Symbol, record: &MatchRecord<D>) -> Justification {
+    let mut subst = record
+      .iter()
+      .map(|(sym, node)| (sym.clone(), self.add(node)))
+      .collect::<Vec<_>>();
+    subst.sort_by(|(l, _), (r, _)| l.0.cmp(&r.0));
+    Justification::Rule(rule.clone(), subst)
+  }
+
+  /// Term of the `i`th node, built from the children it was added with.
+  fn term(&self, i: usize) -> Value {
+    match &self.nodes[i].body {
+      RawENode::Use(op, offset) => {
+        let op = op.as_ref().borrow();
+        let uses = self.children[i].iter().map(|&c| self.term(c)).collect();
+        let op = Op {
+          opcode: op.opcode.clone(),
+          defs: op.defs.clone(),
+          uses,
+          attr: op.attr.clone(),
+          region: op.region.clone(),
+          sign: op.sign.clone(),
+        };
+        Value::Use(OpHand::new(op), *offset)
+      },
+      RawENode::Const(c) => Value::Const(c.clone()),
+      RawENode::Argument(arg) => Value::Argument(arg.clone()),
+      RawENode::Label(label) => Value::Label(label.clone()),
+      RawENode::Input(sym) => Value::Input(sym.clone()),
+    }
+  }
+
+  /// Path of `(node, union)` steps from `a` to `b` in the proof forest.
+  fn path(&self, a: usize, b: usize) -> Option<Vec<(usize, usize)>> {
+    let mut prev: HashMap<usize, (usize, usize)> = HashMap::new();
+    let mut queue = VecDeque::from([a]);
+    while let Some(i) = queue.pop_front() {
+      if i == b {
+        let mut path = vec![];
+        let mut i = b;
+        while i != a {
+          let (from, reason) = prev[&i];
+          path.push((i, reason));
+          i = from;
+        }
+        path.reverse();
+        return Some(path);
+      }
+      for &(j, reason) in &self.edges[i] {
+        if j != a && !prev.contains_key(&j) {
+          prev.insert(j, (i, reason));
+          queue.push_back(j);
+        }
+      }
+    }
+    None
+  }
+
+  fn explain(&self, a: usize, b: usize, seen: &mut HashSet<(usize, usize)>) -> Option<Explanation> {
+    let mut steps = vec![Step {
+      term: self.term(a),
+      reason: None,
+    }];
+    let mut from = a;
+    for (to, reason) in self.path(a, b)? {
+      let (l, _, why) = &self.reasons[reason];
+      let forward = *l == from;
+      let reason = match why {
+        Justification::Given => Reason::Given,
+        Justification::Analysis => Reason::Analysis,
+        Justification::Congruence => {
+          let mut children = vec![];
+          // a cyclic egraph may need a proof to explain itself, that part is left out
+          if seen.insert((from, to)) {
+            for (&l, &r) in self.children[from].iter().zip(&self.children[to]) {
+              if l != r {
+                children.push(self.explain(l, r, seen)?);
+              }
+            }
+            seen.remove(&(from, to));
+          }
+          Reason::Congruence(children)
+        },
+        Justification::Rule(name, subst) => Reason::Rule {
+          name: name.clone(),
+          subst: subst
+            .iter()
+            .map(|(sym, i)| (sym.clone(), self.term(*i)))
+            .collect(),
+          forward,
+        },
+      };
+      steps.push(Step {
+        term: self.term(to),
+        reason: Some(reason),
+      });
+      from = to;
+    }
+    Some(Explanation { steps })
+  }
+}
+
+/// Chain of rewrites between two equal terms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+  pub steps: Vec<Step>,
+}
+
+/// Term of a step and why it equals the term of the previous step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+  pub term: Value,
+  pub reason: Option<Reason>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+  Given,
+  Analysis,
+  /// Explanations of the children that differ.
+  Congruence(Vec<Explanation>),
+  /// `forward` is false if the rule was applied from this term to the previous one.
+  Rule {
+    name: Symbol,
+    subst: Vec<(Symbol, Value)>,
+    forward: bool,
+  },
+}
+
+impl Explanation {
+  /// Names of the rules used, including the ones under congruence.
+  pub fn rules(&self) -> Vec<Symbol> {
+    self
+      .steps
+      .iter()
+      .flat_map(|step| match &step.reason {
+        Some(Reason::Rule { name, .. }) => vec![name.clone()],
+        Some(Reason::Congruence(children)) => children.iter().flat_map(Self::rules).collect(),
+        _ => vec![],
+      })
+      .collect()
+  }
+
+  fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+    for step in &self.steps {
+      write!(f, "{:indent$}", "")?;
+      match &step.reason {
+        None => writeln!(f, "{}", step.term)?,
+        Some(reason) => {
+          write!(f, "= {}  by ", step.term)?;
+          match reason {
+            Reason::Given => writeln!(f, "union")?,
+            Reason::Analysis => writeln!(f, "analysis")?,
+            Reason::Congruence(children) => {
+              writeln!(f, "congruence")?;
+              for child in children {
+                child.write(f, indent + 4)?;
+              }
+            },
+            Reason::Rule {
+              name,
+              subst,
+              forward,
+            } => {
+              write!(f, "{}", name)?;
+              if !forward {
+                write!(f, " (reversed)")?;
+              }
+              for (sym, term) in subst {
+                write!(f, ", ?{} = {}", sym, term)?;
+              }
+              writeln!(f)?;
+            },
+          }
+        },
+      }
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for Explanation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.write(f, 0)
+  }
+}
+
+impl<D> EGraph<D> {
+  /// Record a justification for every union from now on,
+  /// must be called before anything is added.
+  pub fn with_explanations(mut self) -> Self {
+    assert!(
+      self.eclasses.is_empty(),
+      "explanations must be enabled on an empty egraph"
+    );
+    self.explain = Some(Explain::new());
+    self
+  }
+
+  /// Run `f` with the unions it makes justified by `why`.
+  pub(crate) fn with_reason<R>(&mut self, why: Justification, f: impl FnOnce(&mut Self) -> R) -> R {
+    let Some(explain) = self.explain.as_mut() else {
+      return f(self);
+    };
+    let old = std::mem::replace(&mut explain.reason, why);
+    let r = f(self);
+    if let Some(explain) = self.explain.as_mut() {
+      explain.reason = old;
+    }
+    r
+  }
+
+  /// Rewrite chain from `a` to `b`, `None` if they are not equal or
+  /// explanations are disabled.
+  pub fn explain_equivalence(&self, a: &RawENode<D>, b: &RawENode<D>) -> Option<Explanation> {
+    let explain = self.explain.as_ref()?;
+    let (a, b) = (explain.get(a)?, explain.get(b)?);
+    explain.explain(a, b, &mut HashSet::new())
+  }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use cfir::{
+  block::{Block, Region},
+  op::{Op, OpHand, Space},
+  symbol::Symbol,
+  types::{FuncType, Type},
+  value::{Constant, Value},
+};
+
+use crate::{
+  eclass::Id,
+  egraph::EGraph,
+  enode::{ENode, EOpHand, RawENode},
+  region::{binder_index, block_opcode},
+};
+
+/// Cost of an enode from the costs of its children.
+pub trait CostFunction<D> {
+  fn cost(&mut self, node: &RawENode<D>, children: &[usize]) -> usize;
+}
+
+impl<D, C: CostFunction<D>> CostFunction<D> for &mut C {
+  fn cost(&mut self, node: &RawENode<D>, children: &[usize]) -> usize {
+    (**self).cost(node, children)
+  }
+}
+
+/// Number of nodes of a term.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AstSize;
+
+impl<D> CostFunction<D> for AstSize {
+  fn cost(&mut self, _node: &RawENode<D>, children: &[usize]) -> usize {
+    children
+      .iter()
+      .fold(1usize, |sum, cost| sum.saturating_add(*cost))
+  }
+}
+
+/// Cheapest node of every eclass of a rebuilt egraph.
+pub struct Extractor<D> {
+  best: HashMap<Id<D>, (usize, ENode<D>)>,
+}
+
+impl<D> Extractor<D> {
+  pub fn new(egraph: &EGraph<D>, mut cost: impl CostFunction<D>) -> Self {
+    #[allow(clippy::mutable_key_type)]
+    let mut best: HashMap<Id<D>, (usize, ENode<D>)> = HashMap::new();
+    let classes = egraph.classes();
+    // a node has a cost once all its children have one
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for id in &classes {
+        for node in &id.as_ref().borrow().nodes {
+          let children = node
+            .body
+            .children()
+            .iter()
+            .map(|child| best.get(&child.find()).map(|(cost, _)| *cost))
+            .collect::<Option<Vec<_>>>();
+          let Some(children) = children else {
+            continue;
+          };
+          let cost = cost.cost(&node.body, &children);
+          if best.get(id).is_none_or(|(old, _)| cost < *old) {
+            best.insert(id.clone(), (cost, node.clone()));
+            changed = true;
+          }
+        }
+      }
+    }
+    Extractor { best }
+  }
+
+  pub fn find_best_cost(&self, id: &Id<D>) -> Option<usize> {
+    Some(self.best.get(&id.find())?.0)
+  }
+
+  /// Cheapest term of eclass `id`, ops used twice are shared.
+  pub fn find_best(&self, id: &Id<D>) -> Option<Value> {
+    Builder::new(self).value(id)
+  }
+
+  /// Effectful ops leading to `state` in their order, with their cheapest
+  /// operands. The ops are the ones of the cheapest node of every state on
+  /// the way, the state uses are dropped.
+  pub fn extract_block(&self, state: &Id<D>) -> Option<Space> {
+    let mut builder = Builder::new(self);
+    let chain = self.chain(state)?;
+    chain.iter().map(|eop| builder.op(eop)).collect()
+  }
+
+  /// Ops of the cheapest nodes of the states leading to `state`, the first
+  /// one first.
+  fn chain(&self, state: &Id<D>) -> Option<Vec<EOpHand<D>>> {
+    let mut chain = vec![];
+    let mut state = state.find();
+    loop {
+      let (_, node) = self.best.get(&state)?;
+      let RawENode::Use(eop, _) = &node.body else {
+        break;
+      };
+      let prev = {
+        let op = eop.as_ref().borrow();
+        if !op.state {
+          break;
+        }
+        op.uses.last()?.find()
+      };
+      chain.push(eop.clone());
+      state = prev;
+    }
+    chain.reverse();
+    Some(chain)
+  }
+
+  /// Op of the cheapest node of `id` if it is the state left by the op.
+  fn state_op(&self, id: &Id<D>) -> Option<EOpHand<D>> {
+    let (_, node) = self.best.get(&id.find())?;
+    let RawENode::Use(eop, offset) = &node.body else {
+      return None;
+    };
+    let op = eop.as_ref().borrow();
+    (op.state && *offset == op.defs.len()).then(|| eop.clone())
+  }
+
+  /// Op of the cheapest node of `id` if it is a `region.block` term.
+  fn block_term(&self, id: &Id<D>) -> Option<EOpHand<D>> {
+    let (_, node) = self.best.get(&id.find())?;
+    match &node.body {
+      RawENode::Use(eop, 0) if eop.as_ref().borrow().opcode == block_opcode() => Some(eop.clone()),
+      _ => None,
+    }
+  }
+}
+
+/// Builds the cfir of the cheapest nodes, each eop once per block depth.
+struct Builder<'e, D> {
+  extractor: &'e Extractor<D>,
+  ops: HashMap<(usize, usize), OpHand>,
+  /// Argument names of the blocks being built, the innermost last.
+  blocks: Vec<Vec<Symbol>>,
+  /// Ops placed in the blocks being built, by the depth of their block, their
+  /// results are used by name.
+  placed: HashMap<usize, usize>,
+}
+
+impl<'e, D> Builder<'e, D> {
+  fn new(extractor: &'e Extractor<D>) -> Self {
+    Builder {
+      extractor,
+      ops: HashMap::new(),
+      blocks: vec![],
+      placed: HashMap::new(),
+    }
+  }
+
+  fn value(&mut self, id: &Id<D>) -> Option<Value> {
+    let (_, node) = self.extractor.best.get(&id.find())?;
+    Some(match &node.body {
+      RawENode::Use(eop, offset) => {
+        let def = eop.as_ref().borrow().defs.get(*offset).cloned();
+        match def {
+          Some(def) if self.placed.contains_key(&(eop.as_ptr() as usize)) => Value::Input(def),
+          _ => Value::Use(self.op(eop)?, *offset),
+        }
+      },
+      RawENode::Const(c) => Value::Const(c.clone()),
+      // the binders of the blocks being built are their arguments
+      RawENode::Argument(arg) => match binder_index(arg) {
+        Some((depth, index)) if depth < self.blocks.len() => {
+          let names = &self.blocks[self.blocks.len() - 1 - depth];
+          Value::Input(names.get(index)?.clone())
+        },
+        _ => Value::Argument(arg.clone()),
+      },
+      RawENode::Label(label) => Value::Label(label.clone()),
+      RawENode::Input(sym) => Value::Input(sym.clone()),
+    })
+  }
+
+  fn op(&mut self, eop: &EOpHand<D>) -> Option<OpHand> {
+    // the same binder is another argument at another depth
+    let key = (eop.as_ptr() as usize, self.blocks.len());
+    if let Some(op) = self.ops.get(&key) {
+      return Some(op.clone());
+    }
+    let eop = eop.as_ref().borrow();
+    let mut uses = eop.uses.as_slice();
+    // the state of an effectful op in a block is implicit in cfir
+    if eop.state {
+      uses = &uses[..uses.len() - 1];
+    }
+    // the blocks of its region are the last uses
+    let blocks = uses
+      .iter()
+      .rev()
+      .take_while(|id| self.extractor.block_term(id).is_some())
+      .count();
+    let (uses, blocks) = uses.split_at(uses.len() - blocks);
+    let uses = uses
+      .iter()
+      .map(|id| self.value(id))
+      .collect::<Option<Vec<_>>>()?;
+    let region = blocks
+      .iter()
+      .map(|id| {
+        let block = self.block(&self.extractor.block_term(id)?)?;
+        Some((block.0.clone(), block))
+      })
+      .collect::<Option<Region>>()?;
+    let op = OpHand::new(Op {
+      opcode: eop.opcode.clone(),
+      defs: eop.defs.clone(),
+      uses,
+      attr: eop.attr.clone(),
+      region,
+      sign: eop.sign.clone(),
+    });
+    self.ops.insert(key, op.clone());
+    Some(op)
+  }
+
+  /// Block of a `region.block` term, its arguments are named `a<depth>_<i>`.
+  ///
+  /// The effectful ops leading to its final state come first, then its
+  /// roots.
+  fn block(&mut self, term: &EOpHand<D>) -> Option<Block> {
+    let term = term.as_ref().borrow();
+    let Some(Type::FuncType(FuncType(params, _))) = term.sign.first() else {
+      return None;
+    };
+    let depth = self.blocks.len();
+    let names = (0..params.len())
+      .map(|i| Symbol::new(&format!("a{depth}_{i}")))
+      .collect::<Vec<_>>();
+    let args = names.iter().cloned().zip(params.iter().cloned()).collect();
+    let label = match term.attr.get(&Symbol::new("label")) {
+      Some(Constant::String(label)) => Some(Symbol::new(label)),
+      _ => None,
+    };
+    self.blocks.push(names);
+    let ops = self.block_ops(&term.uses);
+    self.blocks.pop();
+    self.placed.retain(|_, placed| *placed <= depth);
+    Some(Block(label, args, ops?))
+  }
+
+  fn block_ops(&mut self, uses: &[Id<D>]) -> Option<Space> {
+    let (roots, chain) = match uses.split_last() {
+      Some((last, roots)) if self.extractor.state_op(last).is_some() => {
+        (roots, self.extractor.chain(last)?)
+      },
+      _ => (uses, vec![]),
+    };
+    let mut ops = vec![];
+    for eop in &chain {
+      ops.push(self.op(eop)?);
+      self.placed.insert(eop.as_ptr() as usize, self.blocks.len());
+    }
+    for id in roots {
+      // a root folded to a constant leaves nothing to do
+      if let Value::Use(op, _) = self.value(id)? {
+        ops.push(op);
+      }
+    }
+    Some(ops)
+  }
+}
@@ -0,0 +1,140 @@
+use std::{collections::HashSet, fmt, rc::Rc};
+
+use cfir::rewriter::{
+  pattern::{Catch, OpPatHand, ValuePat},
+  rule::RuleLhs,
+};
+
+use crate::{
+  analysis::Analysis,
+  eclass::Id,
+  egraph::EGraph,
+  enode::{ENode, RawENode},
+  matching::MatchRecord,
+  rule::Rule,
+};
+
+/// Eclasses where matches not found before may be rooted: the eclasses added
+/// or changed since the last search and their users, up to the depth of the
+/// patterns.
+pub struct Frontier<D>(Rc<HashSet<Id<D>>>);
+
+impl<D> Clone for Frontier<D> {
+  fn clone(&self) -> Self {
+    Frontier(self.0.clone())
+  }
+}
+
+impl<D> fmt::Debug for Frontier<D> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Frontier({} eclasses)", self.0.len())
+  }
+}
+
+impl<D> Frontier<D> {
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  /// Whether `id` is in the frontier, it must be canonical.
+  pub fn contains(&self, id: &Id<D>) -> bool {
+    self.0.contains(id)
+  }
+}
+
+/// Levels of ops in `pat`, a match rooted `depth` users above a changed
+/// eclass may be new.
+pub fn depth(pat: &ValuePat) -> usize {
+  match pat {
+    ValuePat::Use(op, _) => {
+      let op = op.as_ref().borrow();
+      1 + op
+        .1
+        .iter()
+        .filter_map(|Catch(pat, _)| pat.as_ref().map(depth))
+        .max()
+        .unwrap_or(0)
+    },
+    _ => 0,
+  }
+}
+
+impl<D> EGraph<D> {
+  /// Frontier of the eclasses touched since the last call, for patterns up to
+  /// `depth` levels deep. The egraph must be rebuilt.
+  pub fn take_frontier(&mut self, depth: usize) -> Frontier<D> {
+    #[allow(clippy::mutable_key_type)]
+    let mut frontier: HashSet<Id<D>> = std::mem::take(&mut self.touched)
+      .iter()
+      .map(Id::find)
+      .collect();
+    if frontier.is_empty() || depth == 0 {
+      return Frontier(Rc::new(frontier));
+    }
+    let mut users: Vec<(Id<D>, Id<D>)> = vec![];
+    for id in self.classes() {
+      for node in &id.as_ref().borrow().nodes {
+        if let RawENode::Use(op, _) = &node.body {
+          for used in &op.as_ref().borrow().uses {
+            users.push((used.find(), id.clone()));
+          }
+        }
+      }
+    }
+    #[allow(clippy::mutable_key_type)]
+    let mut level = frontier.clone();
+    for _ in 0..depth {
+      level = users
+        .iter()
+        .filter(|(used, user)| level.contains(used) && !frontier.contains(user))
+        .map(|(_, user)| user.clone())
+        .collect();
+      if level.is_empty() {
+        break;
+      }
+      frontier.extend(level.iter().cloned());
+    }
+    Frontier(Rc::new(frontier))
+  }
+
+  /// Depth of the frontier `rule` is searched in, `None` if it must be
+  /// searched in full.
+  pub fn frontier_depth(&self, rule: &Rule<D>) -> Option<usize> {
+    let RuleLhs::Op(lhs) = &rule.lhs else {
+      // the patterns of a multi-rule are rooted apart
+      return None;
+    };
+    let pat = ValuePat::Use(OpPatHand::new(lhs.clone()), 0);
+    // flattening goes deeper than the pattern
+    (rule.local && !self.flattens(&pat)).then(|| depth(&pat))
+  }
+
+  pub(crate) fn in_frontier(&self, node: &ENode<D>) -> bool {
+    self
+      .frontier
+      .as_ref()
+      .is_none_or(|frontier| frontier.contains(&node.get_id()))
+  }
+}
+
+impl<D: Analysis> EGraph<D> {
+  /// Matches of `rule` rooted in `frontier`, or every match if the rule must
+  /// be searched in full.
+  pub fn search_rule_in(
+    &mut self,
+    rule: &Rule<D>,
+    frontier: &Frontier<D>,
+  ) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    if self.frontier_depth(rule).is_none() {
+      return self.search_rule(rule);
+    }
+    let old = self.frontier.replace(frontier.clone());
+    let matches = self.search_rule(rule);
+    self.frontier = old;
+    matches
+  }
+}
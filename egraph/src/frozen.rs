@@ -0,0 +1,304 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use cfir::{
+  dialect::Symmetry,
+  op::Attr,
+  rewriter::pattern::Bindings,
+  symbol::{Name, Symbol},
+  types::Type,
+};
+
+use crate::{
+  analysis::Analysis,
+  egraph::EGraph,
+  enode::{ENode, RawENode},
+  machine::{Atom, Classes, OpNode, Program, Reg, Root},
+  matching::MatchRecord,
+  rule::Rule,
+};
+
+/// Op node of a frozen egraph, its uses are eclass indexes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrozenOp {
+  pub opcode: Name,
+  pub uses: Vec<usize>,
+  pub offset: usize,
+  pub attr: Attr,
+  pub sign: Vec<Type>,
+  pub symmetry: Symmetry,
+}
+
+impl FrozenOp {
+  fn node(&self) -> OpNode<'_, usize> {
+    OpNode {
+      opcode: &self.opcode,
+      uses: &self.uses,
+      offset: self.offset,
+      attr: &self.attr,
+      sign: &self.sign,
+      symmetry: self.symmetry,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+struct FrozenClass {
+  ops: Vec<FrozenOp>,
+  atoms: Vec<Atom>,
+}
+
+/// Copy of a rebuilt egraph for the search phase, without reference counts
+/// or cells so it is `Send` and `Sync`. An eclass is its index in
+/// `EGraph::classes`, the candidates are in the order of the hashcons.
+#[derive(Debug, Clone, Default)]
+pub struct Frozen {
+  classes: Vec<FrozenClass>,
+  /// Op nodes by opcode and arity.
+  ops: BTreeMap<(Name, usize), Vec<FrozenOp>>,
+  atoms: Vec<Atom>,
+}
+
+/// Node of the hashcons a match is rooted at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Candidate {
+  /// Index in the op nodes of this opcode and arity.
+  Op(Name, usize, usize),
+  /// Index in the atoms.
+  Atom(usize),
+}
+
+/// Match found in a frozen egraph, see `EGraph::thaw`.
+#[derive(Debug, Clone)]
+pub struct FrozenMatch {
+  pub root: Candidate,
+  /// Eclass of each variable.
+  pub vars: Vec<(Symbol, usize)>,
+  pub bindings: Bindings,
+}
+
+impl Classes for Frozen {
+  type Class = usize;
+  type Record = (Vec<(Symbol, usize)>, Bindings);
+
+  fn each_op(&self, class: &usize, f: &mut dyn FnMut(OpNode<'_, usize>)) {
+    for op in &self.classes[*class].ops {
+      f(op.node());
+    }
+  }
+
+  fn has_atom(&self, class: &usize, atom: &Atom) -> bool {
+    self.classes[*class].atoms.contains(atom)
+  }
+
+  fn find(&self, class: &usize) -> usize {
+    *class
+  }
+
+  fn record(&self, vars: &[(Symbol, Reg)], regs: &[usize], bindings: &Bindings) -> Self::Record {
+    let vars = vars
+      .iter()
+      .map(|(sym, reg)| (sym.clone(), regs[*reg]))
+      .collect();
+    (vars, bindings.clone())
+  }
+}
+
+impl Frozen {
+  /// Number of eclasses.
+  pub fn len(&self) -> usize {
+    self.classes.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.classes.is_empty()
+  }
+
+  /// Matches of a compiled pattern, like `EGraph::run_program`.
+  pub fn run_program(&self, program: &Program) -> Vec<FrozenMatch> {
+    let mut out = vec![];
+    let mut push = |root: Candidate, found: Vec<(Vec<(Symbol, usize)>, Bindings)>| {
+      out.extend(found.into_iter().map(|(vars, bindings)| FrozenMatch {
+        root: root.clone(),
+        vars,
+        bindings,
+      }))
+    };
+    match &program.root {
+      Root::Op { opcode, arity, .. } => {
+        for ((name, n), nodes) in &self.ops {
+          if *n != *arity || opcode.0.as_ref().is_some_and(|pat| !pat.accepts(name)) {
+            continue;
+          }
+          for (k, op) in nodes.iter().enumerate() {
+            push(
+              Candidate::Op(name.clone(), *n, k),
+              program.run_op(self, op.node()),
+            );
+          }
+        }
+      },
+      Root::Atom(_) => {
+        for (k, atom) in self.atoms.iter().enumerate() {
+          push(
+            Candidate::Atom(k),
+            program.run_atom(self, |pat| pat == atom),
+          );
+        }
+      },
+    }
+    out
+  }
+
+  /// Matches of every program, run on `threads` threads. `None` programs
+  /// are skipped.
+  pub fn search(&self, programs: &[Option<&Program>], threads: usize) -> Vec<Vec<FrozenMatch>> {
+    let next = AtomicUsize::new(0);
+    let mut found = vec![vec![]; programs.len()];
+    std::thread::scope(|scope| {
+      let workers = (0..threads.max(1))
+        .map(|_| {
+          scope.spawn(|| {
+            let mut done = vec![];
+            loop {
+              let index = next.fetch_add(1, Ordering::Relaxed);
+              let Some(program) = programs.get(index) else {
+                break done;
+              };
+              if let Some(program) = program {
+                done.push((index, self.run_program(program)));
+              }
+            }
+          })
+        })
+        .collect::<Vec<_>>();
+      for worker in workers {
+        for (index, matches) in worker.join().unwrap() {
+          found[index] = matches;
+        }
+      }
+    });
+    found
+  }
+}
+
+impl<D> EGraph<D> {
+  /// Freeze the egraph for a search on other threads, it must be rebuilt.
+  pub fn freeze(&self) -> Frozen {
+    let classes = self.classes();
+    #[allow(clippy::mutable_key_type)]
+    let index = classes
+      .iter()
+      .enumerate()
+      .map(|(i, id)| (id.clone(), i))
+      .collect::<HashMap<_, _>>();
+    let freeze_op = |node: &ENode<D>| {
+      let RawENode::Use(op, offset) = &node.body else {
+        return None;
+      };
+      let op = op.as_ref().borrow();
+      Some(FrozenOp {
+        opcode: op.opcode.clone(),
+        uses: op.uses.iter().map(|id| index[&id.find()]).collect(),
+        offset: *offset,
+        attr: op.attr.clone(),
+        sign: op.sign.clone(),
+        symmetry: op.symmetry,
+      })
+    };
+    let freeze_atom = |node: &ENode<D>| match &node.body {
+      RawENode::Use(..) => None,
+      RawENode::Const(v) => Some(Atom::Const(v.clone())),
+      RawENode::Argument(v) => Some(Atom::Argument(v.clone())),
+      RawENode::Label(v) => Some(Atom::Label(v.clone())),
+      RawENode::Input(v) => Some(Atom::Input(v.clone())),
+    };
+    Frozen {
+      classes: classes
+        .iter()
+        .map(|id| {
+          let eclass = id.as_ref().borrow();
+          FrozenClass {
+            ops: eclass.nodes.iter().filter_map(freeze_op).collect(),
+            atoms: eclass.nodes.iter().filter_map(freeze_atom).collect(),
+          }
+        })
+        .collect(),
+      ops: self
+        .hashcons
+        .opcodes()
+        .map(|(opcode, arity, nodes)| {
+          let nodes = nodes.iter().map(|node| freeze_op(node).unwrap()).collect();
+          ((opcode.clone(), arity), nodes)
+        })
+        .collect(),
+      atoms: self
+        .hashcons
+        .atoms()
+        .iter()
+        .map(|node| freeze_atom(node).unwrap())
+        .collect(),
+    }
+  }
+
+  /// Matches found in the frozen copy of this egraph, which must not have
+  /// changed since it was frozen.
+  pub fn thaw(&self, matches: Vec<FrozenMatch>) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    let classes = self.classes();
+    matches
+      .into_iter()
+      .map(|found| {
+        let node = match &found.root {
+          Candidate::Op(opcode, arity, k) => &self.hashcons.ops(opcode, *arity)[*k],
+          Candidate::Atom(k) => &self.hashcons.atoms()[*k],
+        };
+        let record = found
+          .vars
+          .into_iter()
+          .map(|(sym, class)| (sym, classes[class].as_ref().borrow().nodes[0].clone()))
+          .collect::<MatchRecord<D>>()
+          .with_bindings(found.bindings);
+        (node.clone(), record)
+      })
+      .collect()
+  }
+}
+
+impl<D: Analysis> EGraph<D> {
+  /// Matches of every rule, the same as `search_rule` on each.
+  ///
+  /// Only the rules run by the matching machine are searched on `threads`
+  /// threads, in a frozen copy. The rules of `Matcher::Tree` and
+  /// `Matcher::Relational` are searched on the calling thread after them,
+  /// and so are the guards of every rule, a set of such rules gets no
+  /// faster with more threads.
+  pub fn search_rules(
+    &mut self,
+    rules: &[&Rule<D>],
+    threads: usize,
+  ) -> Vec<Vec<(ENode<D>, MatchRecord<D>)>> {
+    let programs = rules.iter().map(|rule| rule.program()).collect::<Vec<_>>();
+    let found = if programs.iter().any(Option::is_some) {
+      self.freeze().search(&programs, threads)
+    } else {
+      vec![vec![]; rules.len()]
+    };
+    self.search_phase(|egraph| {
+      rules
+        .iter()
+        .zip(programs)
+        .zip(found)
+        .map(|((rule, program), found)| match program {
+          Some(_) => egraph
+            .thaw(found)
+            .into_iter()
+            .filter(|(node, record)| egraph.in_frontier(node) && rule.check(egraph, record))
+            .collect(),
+          None => egraph.search_rule(rule),
+        })
+        .collect()
+    })
+  }
+}
@@ -0,0 +1,166 @@
+use std::{
+  collections::{HashMap, HashSet},
+  fmt,
+  mem::size_of,
+};
+
+use crate::{
+  analysis::Analysis,
+  eclass::{EClass, Id},
+  egraph::EGraph,
+  enode::{ENode, EOp, RawENode},
+  extract::{CostFunction, Extractor},
+  snapshot::Undo,
+};
+
+/// Sizes of an egraph, see `EGraph::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+  /// Entries of `EGraph::eclasses`, merged eclasses included.
+  pub ids: usize,
+  pub classes: usize,
+  pub nodes: usize,
+  /// Ops held by the nodes, an op is shared by the nodes of its results.
+  pub ops: usize,
+  /// Nodes in the hashcons.
+  pub hashed: usize,
+  /// Estimate of the memory held by the eclasses, their nodes and the
+  /// hashcons, in bytes.
+  pub bytes: usize,
+}
+
+impl fmt::Display for Stats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} eclasses ({} ids), {} enodes, {} ops, {} hashed, ~{} KiB",
+      self.classes,
+      self.ids,
+      self.nodes,
+      self.ops,
+      self.hashed,
+      self.bytes.div_ceil(1024)
+    )
+  }
+}
+
+impl<D> EGraph<D> {
+  pub fn stats(&self) -> Stats {
+    let classes = self.classes();
+    let mut ops = HashSet::new();
+    let mut nodes = 0;
+    let mut bytes = self.eclasses.capacity() * size_of::<Id<D>>();
+    for id in &classes {
+      let class = id.as_ref().borrow();
+      nodes += class.nodes.len();
+      bytes += size_of::<EClass<D>>() + class.nodes.capacity() * size_of::<ENode<D>>();
+      for node in &class.nodes {
+        if let RawENode::Use(op, _) = &node.body {
+          if ops.insert(op.as_ptr()) {
+            let op = op.as_ref().borrow();
+            bytes += size_of::<EOp<D>>() + op.uses.capacity() * size_of::<Id<D>>();
+          }
+        }
+      }
+    }
+    // a key holds about as much as the node it maps to, and both indexes
+    // hold the node
+    let hashed = self.hashcons.len();
+    bytes += hashed * 3 * size_of::<ENode<D>>();
+    Stats {
+      ids: self.eclasses.len(),
+      classes: classes.len(),
+      nodes,
+      ops: ops.len(),
+      hashed,
+      bytes,
+    }
+  }
+}
+
+impl<D: Analysis> EGraph<D> {
+  /// Drop the eclasses not reachable from `root` through the uses of their
+  /// nodes, and the ids of merged eclasses. Returns the number of eclasses
+  /// dropped.
+  ///
+  /// With no root nothing is reachable from, nothing is dropped: push the
+  /// eclasses to keep to `root` first. The ids of the dropped eclasses must
+  /// not be used again, the proof forest keeps its terms.
+  pub fn gc(&mut self) -> usize {
+    if self.root.is_empty() {
+      return 0;
+    }
+    self.rebuild();
+    #[allow(clippy::mutable_key_type)]
+    let mut reachable = HashSet::new();
+    let mut stack = self.root.clone();
+    while let Some(id) = stack.pop() {
+      if !reachable.insert(id.clone()) {
+        continue;
+      }
+      for node in &id.as_ref().borrow().nodes {
+        stack.extend(node.body.children().iter().map(Id::find));
+      }
+    }
+    let (keep, dropped): (Vec<_>, Vec<_>) = self
+      .classes()
+      .into_iter()
+      .partition(|id| reachable.contains(id));
+    // the nodes of dropped eclasses may use each other, free them
+    for id in &dropped {
+      let nodes = std::mem::take(&mut id.as_ref().borrow_mut().nodes);
+      self.record(|| Undo::Nodes(id.clone(), nodes));
+    }
+    let old = std::mem::replace(&mut self.eclasses, keep);
+    self.record(|| Undo::Classes(old));
+    self.touched.retain(|id| reachable.contains(&id.find()));
+    self.rebuild_hashcons();
+    dropped.len()
+  }
+
+  /// Drop the nodes costing more than `slack` over the cheapest node of
+  /// their eclass, and the nodes with no finite cost in an eclass having one.
+  /// Returns the number of nodes dropped.
+  ///
+  /// The eclasses only the dropped nodes used are left, `gc` drops them.
+  pub fn prune(&mut self, mut cost: impl CostFunction<D>, slack: usize) -> usize {
+    self.rebuild();
+    let classes = self.classes();
+    #[allow(clippy::mutable_key_type)]
+    let best: HashMap<Id<D>, usize> = {
+      let extractor = Extractor::new(self, &mut cost);
+      classes
+        .iter()
+        .filter_map(|id| Some((id.clone(), extractor.find_best_cost(id)?)))
+        .collect()
+    };
+    let mut pruned = 0;
+    for id in &classes {
+      let Some(&limit) = best.get(id) else {
+        continue;
+      };
+      let nodes = id.as_ref().borrow().nodes.clone();
+      let keep = nodes
+        .iter()
+        .filter(|node| {
+          let children = node
+            .body
+            .children()
+            .iter()
+            .map(|child| best.get(&child.find()).copied())
+            .collect::<Option<Vec<_>>>();
+          children
+            .is_some_and(|children| cost.cost(&node.body, &children) <= limit.saturating_add(slack))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+      if keep.len() < nodes.len() {
+        pruned += nodes.len() - keep.len();
+        id.as_ref().borrow_mut().nodes = keep;
+        self.record(|| Undo::Nodes(id.clone(), nodes));
+      }
+    }
+    self.rebuild_hashcons();
+    pruned
+  }
+}
@@ -22,6 +22,8 @@ impl<D> Gencfir for EOpHand<D> {
   }
 }
 
+/// Blocks stay `region.block` uses with `%depth_index` arguments, they are not
+/// turned back into regions.
 impl<D> Gencfir for EOp<D> {
   type Output = Vec<OpHand>;
 
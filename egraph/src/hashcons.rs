@@ -0,0 +1,288 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  hash::{Hash, Hasher},
+};
+
+use cfir::{
+  symbol::{Name, Symbol},
+  types::Type,
+  value::{Argument, Constant, Label},
+};
+
+use crate::{
+  eclass::Id,
+  enode::{ENode, RawENode},
+};
+
+/// Everything that identifies a node, equal keys are equal nodes.
+///
+/// The uses are the canonical eclasses at the time the key is made, so a key
+/// goes stale when a use is merged and is remade on rebuild. The region is not
+/// part of the key, it is empty for the ops of an egraph.
+pub enum NodeKey<D> {
+  Op {
+    opcode: Name,
+    uses: Vec<Id<D>>,
+    offset: usize,
+    /// Sorted by key.
+    attr: Vec<(Symbol, Constant)>,
+    sign: Vec<Type>,
+  },
+  Const(Constant),
+  Argument(Argument),
+  Label(Label),
+  Input(Symbol),
+}
+
+impl<D> NodeKey<D> {
+  pub fn new(node: &RawENode<D>) -> Self {
+    NodeKey::make(node, true)
+  }
+
+  /// Key `node` was memoized under, its uses are the canonical eclasses of
+  /// then until it is canonicalized again.
+  fn stored(node: &RawENode<D>) -> Self {
+    NodeKey::make(node, false)
+  }
+
+  fn make(node: &RawENode<D>, canonical: bool) -> Self {
+    match node {
+      RawENode::Use(op, offset) => {
+        let op = op.as_ref().borrow();
+        let mut attr = op
+          .attr
+          .iter()
+          .map(|(key, value)| (key.clone(), value.clone()))
+          .collect::<Vec<_>>();
+        attr.sort_by(|l, r| l.0 .0.cmp(&r.0 .0));
+        NodeKey::Op {
+          opcode: op.opcode.clone(),
+          uses: match canonical {
+            true => op.uses.iter().map(Id::find).collect(),
+            false => op.uses.clone(),
+          },
+          offset: *offset,
+          attr,
+          sign: op.sign.clone(),
+        }
+      },
+      RawENode::Const(c) => NodeKey::Const(c.clone()),
+      RawENode::Argument(arg) => NodeKey::Argument(arg.clone()),
+      RawENode::Label(label) => NodeKey::Label(label.clone()),
+      RawENode::Input(sym) => NodeKey::Input(sym.clone()),
+    }
+  }
+}
+
+impl<D> PartialEq for NodeKey<D> {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (
+        NodeKey::Op {
+          opcode,
+          uses,
+          offset,
+          attr,
+          sign,
+        },
+        NodeKey::Op {
+          opcode: opcode1,
+          uses: uses1,
+          offset: offset1,
+          attr: attr1,
+          sign: sign1,
+        },
+      ) => {
+        opcode == opcode1 && uses == uses1 && offset == offset1 && attr == attr1 && sign == sign1
+      },
+      (NodeKey::Const(l), NodeKey::Const(r)) => l == r,
+      (NodeKey::Argument(l), NodeKey::Argument(r)) => l == r,
+      (NodeKey::Label(l), NodeKey::Label(r)) => l == r,
+      (NodeKey::Input(l), NodeKey::Input(r)) => l == r,
+      _ => false,
+    }
+  }
+}
+
+impl<D> Eq for NodeKey<D> {}
+
+impl<D> Hash for NodeKey<D> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    std::mem::discriminant(self).hash(state);
+    match self {
+      NodeKey::Op {
+        opcode,
+        uses,
+        offset,
+        attr,
+        sign,
+      } => {
+        opcode.hash(state);
+        uses.hash(state);
+        offset.hash(state);
+        attr.hash(state);
+        sign.hash(state);
+      },
+      NodeKey::Const(c) => c.hash(state),
+      NodeKey::Argument(arg) => arg.hash(state),
+      NodeKey::Label(label) => label.hash(state),
+      NodeKey::Input(sym) => sym.hash(state),
+    }
+  }
+}
+
+/// Whether `l` and `r` are the same node, ops equal by value are not.
+fn same<D>(l: &RawENode<D>, r: &RawENode<D>) -> bool {
+  match (l, r) {
+    (RawENode::Use(l, lo), RawENode::Use(r, ro)) => l.as_ptr() == r.as_ptr() && lo == ro,
+    (l, r) => l == r,
+  }
+}
+
+/// Nodes of an egraph, by key for deduplication and by opcode for matching.
+pub struct Hashcons<D> {
+  memo: HashMap<NodeKey<D>, ENode<D>>,
+  /// Op nodes by opcode and arity, ordered for matching to be deterministic.
+  ops: BTreeMap<(Name, usize), Vec<ENode<D>>>,
+  atoms: Vec<ENode<D>>,
+  /// Op nodes by the canonical eclasses they use, for the repairs of a
+  /// rebuild. The nodes of merged eclasses move to the leader.
+  users: HashMap<Id<D>, Vec<ENode<D>>>,
+}
+
+impl<D> Default for Hashcons<D> {
+  fn default() -> Self {
+    Hashcons {
+      memo: HashMap::new(),
+      ops: BTreeMap::new(),
+      atoms: vec![],
+      users: HashMap::new(),
+    }
+  }
+}
+
+impl<D> std::fmt::Debug for Hashcons<D> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Hashcons")
+      .field("nodes", &self.memo.len())
+      .field("opcodes", &self.ops.len())
+      .field("atoms", &self.atoms.len())
+      .finish()
+  }
+}
+
+impl<D> Hashcons<D> {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Node equal to `node` once its uses are canonical.
+  pub fn get(&self, node: &RawENode<D>) -> Option<&ENode<D>> {
+    self.memo.get(&NodeKey::new(node))
+  }
+
+  /// Add `node` to the indexes, it replaces an equal node in the hashcons.
+  pub fn insert(&mut self, node: ENode<D>) {
+    match &node.body {
+      RawENode::Use(op, _) => {
+        let key = {
+          let op = op.as_ref().borrow();
+          (op.opcode.clone(), op.uses.len())
+        };
+        self.ops.entry(key).or_default().push(node.clone());
+        let mut uses = op
+          .as_ref()
+          .borrow()
+          .uses
+          .iter()
+          .map(Id::find)
+          .collect::<Vec<_>>();
+        uses.dedup();
+        for id in uses {
+          self.users.entry(id).or_default().push(node.clone());
+        }
+      },
+      _ => self.atoms.push(node.clone()),
+    }
+    self.memo.insert(NodeKey::new(&node.body), node);
+  }
+
+  /// Drop the key of `node` before its uses are canonicalized, see `memo`.
+  pub(crate) fn forget(&mut self, node: &ENode<D>) {
+    let key = NodeKey::stored(&node.body);
+    if self
+      .memo
+      .get(&key)
+      .is_some_and(|n| same(&n.body, &node.body))
+    {
+      self.memo.remove(&key);
+    }
+  }
+
+  /// Key `node` under its canonical uses, it replaces an equal node.
+  pub(crate) fn memo(&mut self, node: ENode<D>) {
+    self.memo.insert(NodeKey::new(&node.body), node);
+  }
+
+  /// Remove nodes dropped from their eclass from the indexes by opcode and
+  /// by use, their keys must be forgotten.
+  pub(crate) fn remove(&mut self, nodes: &[ENode<D>]) {
+    for node in nodes {
+      let RawENode::Use(op, _) = &node.body else {
+        self.atoms.retain(|n| !same(&n.body, &node.body));
+        continue;
+      };
+      let op = op.as_ref().borrow();
+      if let Some(ops) = self.ops.get_mut(&(op.opcode.clone(), op.uses.len())) {
+        ops.retain(|n| !same(&n.body, &node.body));
+      }
+      for id in &op.uses {
+        if let Some(users) = self.users.get_mut(&id.find()) {
+          users.retain(|n| !same(&n.body, &node.body));
+        }
+      }
+    }
+  }
+
+  /// Number of distinct nodes.
+  pub fn len(&self) -> usize {
+    self.memo.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.memo.is_empty()
+  }
+
+  /// Op nodes with this opcode and arity, for every result offset.
+  pub fn ops(&self, opcode: &Name, arity: usize) -> &[ENode<D>] {
+    self
+      .ops
+      .get(&(opcode.clone(), arity))
+      .map(Vec::as_slice)
+      .unwrap_or_default()
+  }
+
+  /// Opcodes and arities with their op nodes, by opcode.
+  pub fn opcodes(&self) -> impl Iterator<Item = (&Name, usize, &[ENode<D>])> {
+    self
+      .ops
+      .iter()
+      .map(|((opcode, arity), nodes)| (opcode, *arity, nodes.as_slice()))
+  }
+
+  pub fn atoms(&self) -> &[ENode<D>] {
+    &self.atoms
+  }
+
+  /// Op nodes that use the eclass `id`, which must be canonical.
+  pub fn users(&self, id: &Id<D>) -> &[ENode<D>] {
+    self.users.get(id).map(Vec::as_slice).unwrap_or_default()
+  }
+
+  /// Move the users of `other` to `leader` when they are merged.
+  pub(crate) fn merge_users(&mut self, leader: &Id<D>, other: &Id<D>) {
+    if let Some(users) = self.users.remove(other) {
+      self.users.entry(leader.clone()).or_default().extend(users);
+    }
+  }
+}
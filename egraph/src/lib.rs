@@ -1,9 +1,24 @@
+pub mod analysis;
+pub mod const_fold;
+pub mod dump;
 pub mod eclass;
 pub mod egraph;
-pub mod elike;
 pub mod enode;
+pub mod explain;
+pub mod extract;
+pub mod frontier;
+pub mod frozen;
+pub mod gc;
 
 pub mod gen_cfir;
+pub mod hashcons;
+pub mod machine;
 pub mod matching;
+pub mod region;
+pub mod relational;
 pub mod rewriter;
+pub mod rule;
+pub mod runner;
+pub mod snapshot;
+pub mod soundness;
 // pub mod tem_based_rewriter;
@@ -0,0 +1,584 @@
+use std::{cmp::Ordering, marker::PhantomData};
+
+use cfir::{
+  dialect::Symmetry,
+  op::Attr,
+  rewriter::pattern::{Bindings, Catch, OpMeta, OpPat, OpPatHand, OpcodePat, ValuePat},
+  symbol::{Name, Symbol},
+  types::Type,
+  value::{Argument, Constant, Label},
+};
+
+use crate::{
+  eclass::Id,
+  egraph::EGraph,
+  enode::{ENode, RawENode},
+  matching::{flattens_into, permutations, MatchRecord},
+};
+
+/// Register of the matching machine, it holds an eclass.
+pub type Reg = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+  /// For every op node of eclass `i` with an opcode matching `opcode`, this
+  /// arity and result offset, and whose attributes and sign satisfy `meta`,
+  /// put its children in the registers from `out` on. The uses of an
+  /// associative-commutative op are flattened as in `flat`.
+  Bind {
+    i: Reg,
+    opcode: Catch<OpcodePat>,
+    arity: usize,
+    offset: usize,
+    meta: OpMeta,
+    out: Reg,
+    flat: Vec<Flat>,
+  },
+  /// Go on if eclass `i` has the atom `atom`.
+  CheckAtom { i: Reg, atom: Atom },
+  /// Go on if `i` and `j` are the same eclass.
+  Compare { i: Reg, j: Reg },
+  /// Emit the substitution of the variables.
+  Yield,
+}
+
+/// An op pattern flattened through its children of `opcode`, like the tree
+/// matcher does for an associative-commutative op of `opcode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flat {
+  pub opcode: Name,
+  /// Registers of the flattened uses.
+  pub regs: Vec<Reg>,
+  /// The `Bind`s of the children flattened away, they are skipped.
+  pub skip: Vec<usize>,
+}
+
+/// Pattern of a node without uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Atom {
+  Const(Constant),
+  Argument(Argument),
+  Label(Label),
+  Input(Symbol),
+}
+
+impl Atom {
+  /// `None` if `pat` is an op.
+  pub fn new(pat: &ValuePat) -> Option<Self> {
+    match pat {
+      ValuePat::Use(..) => None,
+      ValuePat::Const(v) => Some(Atom::Const(v.clone())),
+      ValuePat::Argument(v) => Some(Atom::Argument(v.clone())),
+      ValuePat::Label(v) => Some(Atom::Label(v.clone())),
+      ValuePat::Input(v) => Some(Atom::Input(v.clone())),
+    }
+  }
+
+  pub fn matches<D>(&self, node: &RawENode<D>) -> bool {
+    match (self, node) {
+      (Atom::Const(v), RawENode::Const(v1)) => v == v1,
+      (Atom::Argument(v), RawENode::Argument(v1)) => v == v1,
+      (Atom::Label(v), RawENode::Label(v1)) => v == v1,
+      (Atom::Input(v), RawENode::Input(v1)) => v == v1,
+      _ => false,
+    }
+  }
+}
+
+/// Root of a compiled pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Root {
+  Op {
+    opcode: Catch<OpcodePat>,
+    arity: usize,
+    offset: usize,
+    meta: OpMeta,
+    flat: Vec<Flat>,
+  },
+  Atom(Atom),
+}
+
+/// A pattern compiled into a linear sequence of instructions, run with
+/// backtracking on every `Bind`.
+///
+/// The root of the pattern is matched against the candidate nodes of its
+/// opcode, its children are in the registers from 0 on. Unlike the pattern
+/// it is `Send` and `Sync`, it can run on a `Frozen` egraph from any thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+  pub root: Root,
+  pub instructions: Vec<Instruction>,
+  /// Register holding each variable.
+  pub vars: Vec<(Symbol, Reg)>,
+}
+
+struct Compiler {
+  instructions: Vec<Instruction>,
+  vars: Vec<(Symbol, Reg)>,
+  regs: usize,
+}
+
+impl Compiler {
+  fn catch(&mut self, catch: &Catch<ValuePat>, i: Reg) {
+    if let Some(sym) = &catch.1 {
+      match self.vars.iter().find(|(var, _)| var == sym) {
+        Some(&(_, j)) => self.instructions.push(Instruction::Compare { i, j }),
+        None => self.vars.push((sym.clone(), i)),
+      }
+    }
+    if let Some(pat) = &catch.0 {
+      self.value(pat, i);
+    }
+  }
+
+  fn value(&mut self, pat: &ValuePat, i: Reg) {
+    match pat {
+      ValuePat::Use(op, offset) => {
+        let op = op.as_ref().borrow();
+        let out = self.regs;
+        self.regs += op.1.len();
+        let pc = self.instructions.len();
+        self.instructions.push(Instruction::Bind {
+          i,
+          opcode: op.0.clone(),
+          arity: op.1.len(),
+          offset: *offset,
+          meta: op.3.clone(),
+          out,
+          flat: vec![],
+        });
+        self.children(&op, out);
+        let flats = self.flat(&op, out);
+        if let Instruction::Bind { flat, .. } = &mut self.instructions[pc] {
+          *flat = flats;
+        }
+      },
+      atom => self.instructions.push(Instruction::CheckAtom {
+        i,
+        atom: Atom::new(atom).unwrap(),
+      }),
+    }
+  }
+
+  fn children(&mut self, op: &OpPat, out: Reg) {
+    for (k, catch) in op.1.iter().enumerate() {
+      self.catch(catch, out + k);
+    }
+  }
+
+  /// Flattenings of `op`, its children already compiled, one for every
+  /// opcode of a child it may be flattened through.
+  fn flat(&self, op: &OpPat, out: Reg) -> Vec<Flat> {
+    let mut flats: Vec<Flat> = vec![];
+    for catch in &op.1 {
+      let Catch(Some(ValuePat::Use(child, 0)), None) = catch else {
+        continue;
+      };
+      let child = child.as_ref().borrow();
+      let Some(name) = child.0.name() else {
+        continue;
+      };
+      if flattens_into(&child, name) && flats.iter().all(|flat| &flat.opcode != name) {
+        let mut flat = Flat {
+          opcode: name.clone(),
+          regs: vec![],
+          skip: vec![],
+        };
+        self.flatten(op, out, &mut flat);
+        flats.push(flat);
+      }
+    }
+    flats
+  }
+
+  fn flatten(&self, op: &OpPat, out: Reg, flat: &mut Flat) {
+    for (k, catch) in op.1.iter().enumerate() {
+      match catch {
+        Catch(Some(ValuePat::Use(child, 0)), None)
+          if flattens_into(&child.as_ref().borrow(), &flat.opcode) =>
+        {
+          let pc = self
+            .instructions
+            .iter()
+            .position(|ins| matches!(ins, Instruction::Bind { i, .. } if *i == out + k))
+            .unwrap();
+          let Instruction::Bind { out, .. } = self.instructions[pc] else {
+            unreachable!()
+          };
+          flat.skip.push(pc);
+          self.flatten(&child.as_ref().borrow(), out, flat);
+        },
+        _ => flat.regs.push(out + k),
+      }
+    }
+  }
+}
+
+impl Program {
+  pub fn compile_op(op: &OpPat) -> Self {
+    Program::compile(&ValuePat::Use(OpPatHand::new(op.clone()), 0))
+  }
+
+  pub fn compile(pat: &ValuePat) -> Self {
+    let mut compiler = Compiler {
+      instructions: vec![],
+      vars: vec![],
+      regs: 0,
+    };
+    let root = match pat {
+      ValuePat::Use(op, offset) => {
+        let op = op.as_ref().borrow();
+        compiler.regs = op.1.len();
+        compiler.children(&op, 0);
+        Root::Op {
+          opcode: op.0.clone(),
+          arity: op.1.len(),
+          offset: *offset,
+          meta: op.3.clone(),
+          flat: compiler.flat(&op, 0),
+        }
+      },
+      atom => Root::Atom(Atom::new(atom).unwrap()),
+    };
+    compiler.instructions.push(Instruction::Yield);
+    Program {
+      root,
+      instructions: compiler.instructions,
+      vars: compiler.vars,
+    }
+  }
+
+  /// Substitutions under which `node` matches the pattern.
+  pub fn run<D>(&self, node: &ENode<D>) -> Vec<MatchRecord<D>> {
+    let classes = Live(PhantomData);
+    match &node.body {
+      RawENode::Use(op, offset) => {
+        let op = op.as_ref().borrow();
+        let uses = op.uses.iter().map(Id::find).collect::<Vec<_>>();
+        self.run_op(&classes, OpNode::new(&op, &uses, *offset))
+      },
+      body => self.run_atom(&classes, |atom| atom.matches(body)),
+    }
+  }
+
+  /// Substitutions under which an op node matches the pattern.
+  pub(crate) fn run_op<C: Classes>(&self, classes: &C, op: OpNode<'_, C::Class>) -> Vec<C::Record> {
+    let mut out = vec![];
+    let Root::Op {
+      opcode,
+      arity,
+      offset,
+      meta,
+      flat,
+    } = &self.root
+    else {
+      return out;
+    };
+    let mut bindings = Bindings::default();
+    if op.offset != *offset
+      || op.uses.len() != *arity
+      || !opcode.matching(op.opcode, &mut bindings)
+      || !meta.matching(op.attr, op.sign, &mut bindings)
+    {
+      return out;
+    }
+    let operands = operands(classes, &op, *arity, 0, flat);
+    let mut regs = vec![];
+    for uses in operands.lists {
+      place(&mut regs, &operands.at, uses);
+      self.step(classes, 0, &mut regs, &bindings, operands.skip, &mut out);
+    }
+    out
+  }
+
+  /// Substitutions under which an atom node matches the pattern.
+  pub(crate) fn run_atom<C: Classes>(
+    &self,
+    classes: &C,
+    matches: impl FnOnce(&Atom) -> bool,
+  ) -> Vec<C::Record> {
+    let mut out = vec![];
+    if let Root::Atom(atom) = &self.root {
+      if matches(atom) {
+        self.step(classes, 0, &mut vec![], &Bindings::default(), &[], &mut out);
+      }
+    }
+    out
+  }
+
+  fn step<C: Classes>(
+    &self,
+    classes: &C,
+    pc: usize,
+    regs: &mut Vec<C::Class>,
+    bindings: &Bindings,
+    skip: &[usize],
+    out: &mut Vec<C::Record>,
+  ) {
+    match &self.instructions[pc] {
+      Instruction::Bind { .. } if skip.contains(&pc) => {
+        self.step(classes, pc + 1, regs, bindings, skip, out)
+      },
+      Instruction::Bind {
+        i,
+        opcode,
+        arity,
+        offset,
+        meta,
+        out: o,
+        flat,
+      } => {
+        let class = regs[*i].clone();
+        classes.each_op(&class, &mut |op| {
+          if op.offset != *offset {
+            return;
+          }
+          let mut bindings = bindings.clone();
+          if !opcode.matching(op.opcode, &mut bindings)
+            || !meta.matching(op.attr, op.sign, &mut bindings)
+          {
+            return;
+          }
+          let operands = operands(classes, &op, *arity, *o, flat);
+          let skip = skip
+            .iter()
+            .chain(operands.skip)
+            .copied()
+            .collect::<Vec<_>>();
+          for uses in operands.lists {
+            place(regs, &operands.at, uses);
+            self.step(classes, pc + 1, regs, &bindings, &skip, out);
+          }
+        });
+      },
+      Instruction::CheckAtom { i, atom } => {
+        if classes.has_atom(&regs[*i], atom) {
+          self.step(classes, pc + 1, regs, bindings, skip, out);
+        }
+      },
+      Instruction::Compare { i, j } => {
+        if classes.find(&regs[*i]) == classes.find(&regs[*j]) {
+          self.step(classes, pc + 1, regs, bindings, skip, out);
+        }
+      },
+      Instruction::Yield => out.push(classes.record(&self.vars, regs, bindings)),
+    }
+  }
+}
+
+/// How the uses of an op node go to the registers of an op pattern.
+struct Operands<'f, T> {
+  /// Register of each use.
+  at: Vec<Reg>,
+  /// `Bind`s of the children flattened away.
+  skip: &'f [usize],
+  /// Every order of the uses.
+  lists: Vec<Vec<T>>,
+}
+
+/// Operands of `op` matched by an op pattern with `arity` uses from `out`
+/// on. Like the tree matcher, an associative-commutative op is flattened up
+/// to the uses of the pattern flattened through its children of the same
+/// opcode.
+fn operands<'f, C: Classes>(
+  classes: &C,
+  op: &OpNode<'_, C::Class>,
+  arity: usize,
+  out: Reg,
+  flat: &'f [Flat],
+) -> Operands<'f, C::Class> {
+  let (at, skip) = match flat.iter().find(|flat| flat.opcode == *op.opcode) {
+    Some(flat) if op.symmetry == Symmetry::AssocComm => (flat.regs.clone(), flat.skip.as_slice()),
+    _ => ((out..out + arity).collect::<Vec<_>>(), &[][..]),
+  };
+  let lists = match at.len().cmp(&op.uses.len()) {
+    Ordering::Equal => vec![op.uses.to_vec()],
+    Ordering::Greater if op.symmetry == Symmetry::AssocComm => {
+      flatten(classes, op.uses, op.opcode, at.len(), &mut vec![])
+        .into_iter()
+        .filter(|uses| uses.len() == at.len())
+        .collect()
+    },
+    _ => vec![],
+  };
+  let lists = lists
+    .iter()
+    .flat_map(|uses| permutations(uses, op.symmetry))
+    .collect();
+  Operands { at, skip, lists }
+}
+
+/// Put `uses` in the registers `at`.
+fn place<T: Clone>(regs: &mut Vec<T>, at: &[Reg], uses: Vec<T>) {
+  for (&reg, class) in at.iter().zip(uses) {
+    if regs.len() <= reg {
+      regs.resize(reg + 1, class.clone());
+    }
+    regs[reg] = class;
+  }
+}
+
+/// Operand lists of an op with `uses`, flattened through the ops of `opcode`
+/// in its operands, none longer than `max`. `path` holds the eclasses being
+/// flattened, an eclass is not flattened into itself.
+pub(crate) fn flatten<C: Classes>(
+  classes: &C,
+  uses: &[C::Class],
+  opcode: &Name,
+  max: usize,
+  path: &mut Vec<C::Class>,
+) -> Vec<Vec<C::Class>> {
+  let mut lists = vec![vec![]];
+  for (i, class) in uses.iter().enumerate() {
+    let class = classes.find(class);
+    let mut options = vec![vec![class.clone()]];
+    if !path.contains(&class) {
+      path.push(class.clone());
+      classes.each_op(&class, &mut |op| {
+        if op.offset == 0 && op.opcode == opcode && op.uses.len() >= 2 {
+          options.extend(flatten(classes, op.uses, opcode, max, path));
+        }
+      });
+      path.pop();
+    }
+    // every operand left takes at least one place
+    let left = uses.len() - i - 1;
+    lists = lists
+      .iter()
+      .flat_map(|list| {
+        options
+          .iter()
+          .filter(|option| list.len() + option.len() + left <= max)
+          .map(|option| list.iter().chain(option).cloned().collect())
+      })
+      .collect();
+  }
+  let mut distinct: Vec<Vec<C::Class>> = vec![];
+  for list in lists {
+    if !distinct.contains(&list) {
+      distinct.push(list);
+    }
+  }
+  distinct
+}
+
+/// Op node as the machine sees it, its uses are canonical.
+pub(crate) struct OpNode<'a, T> {
+  pub opcode: &'a Name,
+  pub uses: &'a [T],
+  pub offset: usize,
+  pub attr: &'a Attr,
+  pub sign: &'a [Type],
+  pub symmetry: Symmetry,
+}
+
+impl<'a, T> OpNode<'a, T> {
+  pub fn new<D>(op: &'a crate::enode::EOp<D>, uses: &'a [T], offset: usize) -> Self {
+    OpNode {
+      opcode: &op.opcode,
+      uses,
+      offset,
+      attr: &op.attr,
+      sign: &op.sign,
+      symmetry: op.symmetry,
+    }
+  }
+}
+
+/// Eclasses the machine runs over, those of an egraph or of a `Frozen` one.
+pub(crate) trait Classes {
+  /// What a register holds.
+  type Class: Clone + PartialEq;
+  /// What a match yields.
+  type Record;
+
+  /// Call `f` with every op node of `class`.
+  fn each_op(&self, class: &Self::Class, f: &mut dyn FnMut(OpNode<'_, Self::Class>));
+
+  fn has_atom(&self, class: &Self::Class, atom: &Atom) -> bool;
+
+  fn find(&self, class: &Self::Class) -> Self::Class;
+
+  fn record(
+    &self,
+    vars: &[(Symbol, Reg)],
+    regs: &[Self::Class],
+    bindings: &Bindings,
+  ) -> Self::Record;
+}
+
+/// The eclasses of the egraph the ids point into.
+pub(crate) struct Live<D>(pub PhantomData<D>);
+
+impl<D> Classes for Live<D> {
+  type Class = Id<D>;
+  type Record = MatchRecord<D>;
+
+  fn each_op(&self, class: &Id<D>, f: &mut dyn FnMut(OpNode<'_, Id<D>>)) {
+    let eclass = class.find();
+    let eclass = eclass.as_ref().borrow();
+    for node in &eclass.nodes {
+      if let RawENode::Use(op, offset) = &node.body {
+        let op = op.as_ref().borrow();
+        let uses = op.uses.iter().map(Id::find).collect::<Vec<_>>();
+        f(OpNode::new(&op, &uses, *offset));
+      }
+    }
+  }
+
+  fn has_atom(&self, class: &Id<D>, atom: &Atom) -> bool {
+    let eclass = class.find();
+    let found = eclass
+      .as_ref()
+      .borrow()
+      .nodes
+      .iter()
+      .any(|node| atom.matches(&node.body));
+    found
+  }
+
+  fn find(&self, class: &Id<D>) -> Id<D> {
+    class.find()
+  }
+
+  fn record(&self, vars: &[(Symbol, Reg)], regs: &[Id<D>], bindings: &Bindings) -> MatchRecord<D> {
+    vars
+      .iter()
+      .map(|(sym, reg)| {
+        let node = regs[*reg].find().as_ref().borrow().nodes[0].clone();
+        (sym.clone(), node)
+      })
+      .collect::<MatchRecord<D>>()
+      .with_bindings(bindings.clone())
+  }
+}
+
+/// Whether an atom pattern matches a node.
+pub(crate) fn atom_eq<D>(atom: &ValuePat, node: &RawENode<D>) -> bool {
+  match (atom, node) {
+    (ValuePat::Const(v), RawENode::Const(v1)) => v == v1,
+    (ValuePat::Argument(v), RawENode::Argument(v1)) => v == v1,
+    (ValuePat::Label(v), RawENode::Label(v1)) => v == v1,
+    (ValuePat::Input(v), RawENode::Input(v1)) => v == v1,
+    _ => false,
+  }
+}
+
+impl<D> EGraph<D> {
+  /// Matches of a compiled pattern, like `matching_value` on its source.
+  pub fn run_program(&self, program: &Program) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    let candidates = match &program.root {
+      Root::Op { opcode, arity, .. } => self.op_candidates(opcode, *arity),
+      Root::Atom(_) => self.atom_candidates(),
+    };
+    candidates
+      .into_iter()
+      .flat_map(|node| {
+        program
+          .run(&node)
+          .into_iter()
+          .map(move |record| (node.clone(), record))
+      })
+      .collect()
+  }
+}
@@ -1,33 +1,120 @@
-use std::collections::HashMap;
+use std::{
+  cmp::Ordering,
+  collections::HashMap,
+  marker::PhantomData,
+  ops::{Deref, DerefMut},
+};
 
 use cfir::{
+  dialect::Symmetry,
   rewriter::pattern::*,
-  rewriter::{
-    form::GetForm,
-    pattern::{Matcher, ValuePat},
-  },
-  symbol::Symbol,
+  rewriter::pattern::{Matcher, ValuePat},
+  symbol::{Name, Symbol},
 };
 
 use crate::{
-  eclass::EClass,
+  eclass::{EClass, Id},
   egraph::EGraph,
   enode::{ENode, EOp, EOpHand, RawENode},
+  machine::{flatten, Live},
 };
 
-type MatchRecord<D> = Vec<(Symbol, ENode<D>)>;
-type MatchRecord1<D> = HashMap<Symbol, ENode<D>>;
+/// Substitution of pattern variables, a variable stands for the eclass of its node.
+/// The variables of attributes and types are bound in `bindings`.
+#[derive(Debug)]
+pub struct MatchRecord<D> {
+  nodes: HashMap<Symbol, ENode<D>>,
+  pub bindings: Bindings,
+}
+
+impl<D> MatchRecord<D> {
+  pub fn new() -> Self {
+    Default::default()
+  }
 
-pub fn unify<D>(mut lhs: MatchRecord1<D>, rhs: MatchRecord1<D>) -> Option<MatchRecord1<D>> {
-  for (name, node) in rhs {
+  pub fn with_bindings(mut self, bindings: Bindings) -> Self {
+    self.bindings = bindings;
+    self
+  }
+}
+
+impl<D> Default for MatchRecord<D> {
+  fn default() -> Self {
+    MatchRecord {
+      nodes: HashMap::new(),
+      bindings: Bindings::default(),
+    }
+  }
+}
+
+impl<D> Clone for MatchRecord<D> {
+  fn clone(&self) -> Self {
+    MatchRecord {
+      nodes: self.nodes.clone(),
+      bindings: self.bindings.clone(),
+    }
+  }
+}
+
+impl<D> Deref for MatchRecord<D> {
+  type Target = HashMap<Symbol, ENode<D>>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.nodes
+  }
+}
+
+impl<D> DerefMut for MatchRecord<D> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.nodes
+  }
+}
+
+impl<D> FromIterator<(Symbol, ENode<D>)> for MatchRecord<D> {
+  fn from_iter<T: IntoIterator<Item = (Symbol, ENode<D>)>>(iter: T) -> Self {
+    MatchRecord {
+      nodes: iter.into_iter().collect(),
+      bindings: Bindings::default(),
+    }
+  }
+}
+
+impl<D> IntoIterator for MatchRecord<D> {
+  type Item = (Symbol, ENode<D>);
+  type IntoIter = std::collections::hash_map::IntoIter<Symbol, ENode<D>>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.nodes.into_iter()
+  }
+}
+
+impl<D, const N: usize> From<[(Symbol, ENode<D>); N]> for MatchRecord<D> {
+  fn from(nodes: [(Symbol, ENode<D>); N]) -> Self {
+    nodes.into_iter().collect()
+  }
+}
+
+/// Merge two substitutions, fails if a variable is bound to different eclasses
+/// or values.
+pub fn unify<D>(mut lhs: MatchRecord<D>, rhs: MatchRecord<D>) -> Option<MatchRecord<D>> {
+  for (name, node) in rhs.nodes {
     if let Some(rec) = lhs.get(&name) {
-      if rec != &node {
+      if rec.get_id() != node.get_id() {
         return None;
       }
+    } else {
+      lhs.insert(name, node);
     }
-    lhs.insert(name, node);
   }
-  Some(lhs)
+  lhs.bindings.merge(&rhs.bindings).then_some(lhs)
+}
+
+/// Every consistent combination of a substitution from `lhs` and one from `rhs`.
+pub fn product<D>(lhs: &[MatchRecord<D>], rhs: &[MatchRecord<D>]) -> Vec<MatchRecord<D>> {
+  lhs
+    .iter()
+    .flat_map(|l| rhs.iter().filter_map(|r| unify(l.clone(), r.clone())))
+    .collect()
 }
 
 impl<D> EGraph<D> {
@@ -36,17 +123,86 @@ impl<D> EGraph<D> {
     self.matching_value(value)
   }
 
-  pub fn matching_value(&mut self, value: ValuePat) -> Vec<(ENode<D>, MatchRecord<D>)> {
-    let form = value.get_form();
-    let form = form.unwrap();
-    let r = self.likes.find_collect(&form);
-    if r.is_none() {
-      return vec![];
+  /// Match every pattern of a multi-pattern, the patterns share their variables.
+  /// The node is the one matched by the first pattern.
+  pub fn matching_multi(&mut self, pats: &[Catch<ValuePat>]) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    let mut matches = vec![];
+    for (i, Catch(pat, sym)) in pats.iter().enumerate() {
+      let Some(pat) = pat else {
+        return vec![];
+      };
+      let pat_matches = self
+        .matching_value(pat.clone())
+        .into_iter()
+        .filter_map(|(node, record)| match sym {
+          Some(sym) => Some((
+            node.clone(),
+            unify(record, MatchRecord::from([(sym.clone(), node)]))?,
+          )),
+          None => Some((node, record)),
+        })
+        .collect::<Vec<_>>();
+      if i == 0 {
+        matches = pat_matches;
+        continue;
+      }
+      matches = matches
+        .iter()
+        .flat_map(|(root, l)| {
+          pat_matches
+            .iter()
+            .filter_map(|(_, r)| Some((root.clone(), unify(l.clone(), r.clone())?)))
+        })
+        .collect();
+      if matches.is_empty() {
+        break;
+      }
+    }
+    matches
+  }
+
+  /// Nodes that may match the root of `pat`, the ops with its opcode and
+  /// arity, the ones of every opcode it accepts for a wildcard. Only the nodes
+  /// in the frontier are taken while searching in one.
+  pub fn candidates(&self, pat: &ValuePat) -> Vec<ENode<D>> {
+    let ValuePat::Use(op, _) = pat else {
+      return self.atom_candidates();
+    };
+    let op = op.as_ref().borrow();
+    self.op_candidates(&op.0, op.1.len())
+  }
+
+  /// Nodes of the ops with `arity` uses whose opcode matches `opcode`.
+  pub(crate) fn op_candidates(&self, opcode: &Catch<OpcodePat>, arity: usize) -> Vec<ENode<D>> {
+    if let Some(name) = opcode.name() {
+      return self.in_frontier_of(self.hashcons.ops(name, arity));
     }
-    let r = r.unwrap();
+    self
+      .hashcons
+      .opcodes()
+      .filter(|(name, n, _)| *n == arity && opcode.0.as_ref().is_none_or(|pat| pat.accepts(name)))
+      .flat_map(|(_, _, nodes)| self.in_frontier_of(nodes))
+      .collect()
+  }
+
+  pub(crate) fn atom_candidates(&self) -> Vec<ENode<D>> {
+    self.in_frontier_of(self.hashcons.atoms())
+  }
+
+  fn in_frontier_of(&self, nodes: &[ENode<D>]) -> Vec<ENode<D>> {
+    nodes
+      .iter()
+      .filter(|node| self.in_frontier(node))
+      .cloned()
+      .collect()
+  }
 
-    r.iter()
-      .filter_map(|node| -> Option<(ENode<D>, Vec<MatchRecord<D>>)> { value.matching(node) })
+  pub fn matching_value(&mut self, value: ValuePat) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    // only the root is looked up, the children are matched against their eclasses
+    self
+      .candidates(&value)
+      .iter()
+      .filter_map(|node| value.matching(node))
       .flat_map(|(node, records)| -> Vec<(ENode<D>, MatchRecord<D>)> {
         records
           .into_iter()
@@ -61,14 +217,142 @@ pub type MatchValue<D> = ENode<D>;
 
 impl<D> Matcher<EOp<D>> for OpPat {
   type Output = Vec<MatchRecord<D>>;
+
+  /// The uses of a commutative op are matched in any order. Those of an
+  /// associative-commutative op are also flattened, `add(add(?x, 1), ?y)`
+  /// matches `add(x, add(y, 1))`.
   fn matching(&self, op: &EOp<D>) -> Self::Output {
-    let _: Option<()> = self.0.matching(&op.opcode);
-    self
-      .1
+    let mut bindings = Bindings::default();
+    if !self.0.matching(&op.opcode, &mut bindings)
+      || !self.3.matching(&op.attr, &op.sign, &mut bindings)
+    {
+      return vec![];
+    }
+    let pats = match op.symmetry {
+      Symmetry::AssocComm => flat_uses(self, &op.opcode),
+      _ => self.1.clone(),
+    };
+    let operands = match pats.len().cmp(&op.uses.len()) {
+      Ordering::Equal => vec![op.uses.clone()],
+      Ordering::Greater if op.symmetry == Symmetry::AssocComm => {
+        let uses = op.uses.iter().map(Id::find).collect::<Vec<_>>();
+        flatten(
+          &Live(PhantomData),
+          &uses,
+          &op.opcode,
+          pats.len(),
+          &mut vec![],
+        )
+        .into_iter()
+        .filter(|uses| uses.len() == pats.len())
+        .collect()
+      },
+      _ => vec![],
+    };
+    let seed = vec![MatchRecord::new().with_bindings(bindings)];
+    operands
       .iter()
-      .zip(op.uses.iter())
-      .flat_map(|(a, b)| a.matching(&b.as_ref().borrow() as &EClass<D>))
-      .collect::<Vec<_>>()
+      .flat_map(|uses| orders(uses, op.symmetry))
+      .flat_map(|uses| {
+        pats
+          .iter()
+          .zip(uses.iter())
+          .fold(seed.clone(), |records, (a, b)| {
+            if records.is_empty() {
+              return records;
+            }
+            let eclass = b.find();
+            let child = a.matching(&eclass.as_ref().borrow() as &EClass<D>);
+            product(&records, &child)
+          })
+      })
+      .collect()
+  }
+}
+
+/// Uses of `pat` flattened through its plain uses of `opcode`, the ones with
+/// neither a variable nor constraints of their own.
+fn flat_uses(pat: &OpPat, opcode: &Name) -> Vec<Catch<ValuePat>> {
+  let mut out = vec![];
+  for catch in &pat.1 {
+    match catch {
+      Catch(Some(ValuePat::Use(op, 0)), None) if flattens_into(&op.as_ref().borrow(), opcode) => {
+        out.extend(flat_uses(&op.as_ref().borrow(), opcode))
+      },
+      _ => out.push(catch.clone()),
+    }
+  }
+  out
+}
+
+pub(crate) fn flattens_into(pat: &OpPat, opcode: &Name) -> bool {
+  pat.0.name() == Some(opcode) && pat.3.is_empty() && pat.1.len() >= 2
+}
+
+/// Orders in which the uses of an op with this symmetry are matched, each
+/// distinct order once.
+pub(crate) fn orders<D>(uses: &[Id<D>], symmetry: Symmetry) -> Vec<Vec<Id<D>>> {
+  match symmetry {
+    Symmetry::None => vec![uses.to_vec()],
+    _ => permutations(&uses.iter().map(Id::find).collect::<Vec<_>>(), symmetry),
+  }
+}
+
+/// Like `orders`, for uses already canonical.
+pub(crate) fn permutations<T: Clone + PartialEq>(uses: &[T], symmetry: Symmetry) -> Vec<Vec<T>> {
+  fn permute<T: Clone + PartialEq>(rest: &[T], prefix: &mut Vec<T>, out: &mut Vec<Vec<T>>) {
+    if rest.is_empty() {
+      out.push(prefix.clone());
+      return;
+    }
+    let mut seen: Vec<&T> = vec![];
+    for (i, used) in rest.iter().enumerate() {
+      if seen.contains(&used) {
+        continue;
+      }
+      seen.push(used);
+      let mut rest = rest.to_vec();
+      rest.remove(i);
+      prefix.push(used.clone());
+      permute(&rest, prefix, out);
+      prefix.pop();
+    }
+  }
+  match symmetry {
+    Symmetry::None => vec![uses.to_vec()],
+    Symmetry::Commutative | Symmetry::AssocComm => {
+      let mut out = vec![];
+      permute(uses, &mut vec![], &mut out);
+      out
+    },
+  }
+}
+
+impl<D> EGraph<D> {
+  /// Whether matching may flatten some op of `pat` through a child pattern,
+  /// which then matches deeper than it is written.
+  pub fn flattens(&self, pat: &ValuePat) -> bool {
+    let ValuePat::Use(op, _) = pat else {
+      return false;
+    };
+    let op = op.as_ref().borrow();
+    op.1.iter().any(|catch| {
+      let Some(pat) = &catch.0 else {
+        return false;
+      };
+      let nested = match (pat, &catch.1) {
+        (ValuePat::Use(child, 0), None) => {
+          let child = child.as_ref().borrow();
+          child.0.name().is_some_and(|name| {
+            flattens_into(&child, name)
+              && op.0 .0.as_ref().is_none_or(|pat| pat.accepts(name))
+              && self.registry.symmetry(name) == Symmetry::AssocComm
+          })
+        },
+        _ => false,
+      };
+      nested || self.flattens(pat)
+    })
   }
 }
 
@@ -84,21 +368,20 @@ impl<D> Matcher<EClass<D>> for Catch<ValuePat> {
   type Output = Vec<MatchRecord<D>>;
   fn matching(&self, i: &EClass<D>) -> Self::Output {
     match (&self.0, &self.1) {
+      // any node stands for the whole eclass
       (None, Some(sym)) => i
         .nodes
-        .iter()
-        .map(|node| vec![(sym.clone(), node.clone())])
+        .first()
+        .map(|node| MatchRecord::from([(sym.clone(), node.clone())]))
+        .into_iter()
         .collect(),
       (Some(pat), None) => pat.matching(i).into_iter().map(|(_, r)| r).collect(),
       (Some(pat), Some(sym)) => pat
         .matching(i)
         .into_iter()
-        .map(|(node, mut r)| {
-          r.push((sym.clone(), node));
-          r
-        })
+        .filter_map(|(node, r)| unify(r, MatchRecord::from([(sym.clone(), node)])))
         .collect(),
-      (None, None) => vec![],
+      (None, None) => vec![MatchRecord::new()],
     }
   }
 }
@@ -122,6 +405,9 @@ impl<D> Matcher<ENode<D>> for ValuePat {
   type Output = Option<(ENode<D>, Vec<MatchRecord<D>>)>;
   fn matching(&self, i: &ENode<D>) -> Self::Output {
     let r = self.matching(&i.body)?;
+    if r.is_empty() {
+      return None;
+    }
     Some((i.clone(), r))
   }
 }
@@ -129,36 +415,23 @@ impl<D> Matcher<ENode<D>> for ValuePat {
 impl<D> Matcher<RawENode<D>> for ValuePat {
   type Output = Option<Vec<MatchRecord<D>>>;
   fn matching(&self, i: &RawENode<D>) -> Self::Output {
-    match (self, i) {
+    let matched = match (self, i) {
       (ValuePat::Use(op, loff), RawENode::Use(op1, roff)) => {
         if loff == roff {
-          Some(op.matching(op1))
-        } else {
-          None
-        }
-      },
-      (ValuePat::Const(v), RawENode::Const(v1)) => {
-        if v == v1 {
-          Some(vec![])
-        } else {
-          None
-        }
-      },
-      (ValuePat::Argument(v), RawENode::Argument(v1)) => {
-        if v == v1 {
-          Some(vec![])
-        } else {
-          None
-        }
-      },
-      (ValuePat::Label(v), RawENode::Label(v1)) => {
-        if v == v1 {
-          Some(vec![])
-        } else {
-          None
+          return Some(op.matching(op1));
         }
+        false
       },
-      _ => None,
+      (ValuePat::Const(v), RawENode::Const(v1)) => v == v1,
+      (ValuePat::Argument(v), RawENode::Argument(v1)) => v == v1,
+      (ValuePat::Label(v), RawENode::Label(v1)) => v == v1,
+      (ValuePat::Input(v), RawENode::Input(v1)) => v == v1,
+      _ => false,
+    };
+    if matched {
+      Some(vec![MatchRecord::new()])
+    } else {
+      None
     }
   }
 }
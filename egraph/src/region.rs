@@ -0,0 +1,312 @@
+use std::collections::{HashMap, HashSet};
+
+use cfir::{
+  block::{Block, Region},
+  dialect::Symmetry,
+  op::{Op, Space},
+  rewriter::form::{Form, GetForm},
+  symbol::{Name, Symbol},
+  types::{FuncType, Type},
+  value::{Argument, Constant, Value},
+};
+
+use crate::{
+  analysis::Analysis,
+  eclass::Id,
+  egraph::EGraph,
+  enode::{EOp, EOpHand, RawENode},
+};
+
+/// Opcode of the term standing for a block.
+pub fn block_opcode() -> Name {
+  Name(Some(Symbol::new("region")), Symbol::new("block"))
+}
+
+/// Argument node of the `index`th argument of the block `depth` levels out,
+/// 0 is the innermost block.
+///
+/// Arguments are numbered by first use, so blocks equal up to the names of
+/// their arguments get the same term.
+pub fn binder(depth: usize, index: usize) -> Argument {
+  Argument(Symbol::new(&format!("%{}_{}", depth, index)), None)
+}
+
+/// State on entry of the innermost block, a binder of depth 0 so that a block
+/// with effects is never closed.
+pub fn entry_state() -> Argument {
+  Argument(Symbol::new("%0_state"), None)
+}
+
+/// Depth of a binder made by `binder` or `entry_state`.
+pub fn binder_depth(arg: &Argument) -> Option<usize> {
+  let (depth, _) = arg.0 .0.strip_prefix('%')?.split_once('_')?;
+  depth.parse().ok()
+}
+
+/// Depth and index of a binder made by `binder`.
+pub fn binder_index(arg: &Argument) -> Option<(usize, usize)> {
+  let (depth, index) = arg.0 .0.strip_prefix('%')?.split_once('_')?;
+  Some((depth.parse().ok()?, index.parse().ok()?))
+}
+
+impl<D> EGraph<D> {
+  /// Whether eclass `id` is the state of a block, on entry or left by an
+  /// effectful op.
+  pub fn is_state(&self, id: &Id<D>) -> bool {
+    let eclass = id.find();
+    let eclass = eclass.as_ref().borrow();
+    eclass.nodes.iter().any(|node| match &node.body {
+      RawENode::Argument(arg) => *arg == entry_state(),
+      RawENode::Use(eop, offset) => {
+        let eop = eop.as_ref().borrow();
+        eop.state && *offset == eop.defs.len()
+      },
+      _ => false,
+    })
+  }
+}
+
+/// Names visible in a block, its arguments and the results defined so far.
+struct Frame<D> {
+  args: HashMap<Symbol, usize>,
+  defs: HashMap<Symbol, RawENode<D>>,
+  /// State left by the last effectful op.
+  state: RawENode<D>,
+}
+
+impl<D> Frame<D> {
+  fn new(args: HashMap<Symbol, usize>) -> Self {
+    Frame {
+      args,
+      defs: HashMap::new(),
+      state: RawENode::Argument(entry_state()),
+    }
+  }
+}
+
+/// Blocks enclosing the op being added, the innermost last.
+pub struct Scope<D>(Vec<Frame<D>>);
+
+impl<D> Default for Scope<D> {
+  fn default() -> Self {
+    Scope(vec![])
+  }
+}
+
+impl<D> Scope<D> {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Node of `sym`, `None` if it is free.
+  pub fn resolve(&self, sym: &Symbol) -> Option<RawENode<D>> {
+    self.0.iter().rev().enumerate().find_map(|(depth, frame)| {
+      if let Some(node) = frame.defs.get(sym) {
+        return Some(node.clone());
+      }
+      let index = frame.args.get(sym)?;
+      Some(RawENode::Argument(binder(depth, *index)))
+    })
+  }
+
+  /// Current state of the innermost block, `None` outside of any block.
+  pub fn state(&self) -> Option<RawENode<D>> {
+    Some(self.0.last()?.state.clone())
+  }
+
+  pub fn set_state(&mut self, state: RawENode<D>) {
+    if let Some(frame) = self.0.last_mut() {
+      frame.state = state;
+    }
+  }
+}
+
+/// Symbols used by `op`, including its nested ops and regions, in order of first use.
+fn inputs(op: &Op, out: &mut Vec<Symbol>) {
+  for value in &op.uses {
+    match value {
+      Value::Input(sym) if !out.contains(sym) => out.push(sym.clone()),
+      Value::Use(op, _) => inputs(&op.as_ref().borrow(), out),
+      _ => {},
+    }
+  }
+  for block in sorted_blocks(&op.region) {
+    for op in &block.2 {
+      inputs(&op.as_ref().borrow(), out);
+    }
+  }
+}
+
+fn sorted_blocks(region: &Region) -> Vec<&Block> {
+  let mut blocks = region.values().collect::<Vec<_>>();
+  blocks.sort_by(|l, r| l.0.as_ref().map(|s| &s.0).cmp(&r.0.as_ref().map(|s| &s.0)));
+  blocks
+}
+
+/// Arguments of `block` in binder order, the used ones by first use and then
+/// the unused ones by type and name.
+fn binder_order(block: &Block) -> Vec<(Symbol, Type)> {
+  let mut used = vec![];
+  for op in &block.2 {
+    inputs(&op.as_ref().borrow(), &mut used);
+  }
+  let mut args = used
+    .into_iter()
+    .filter_map(|sym| Some((sym.clone(), block.1.get(&sym)?.clone())))
+    .collect::<Vec<_>>();
+  let mut unused = block
+    .1
+    .iter()
+    .filter(|(sym, _)| !args.iter().any(|(arg, _)| arg == *sym))
+    .map(|(sym, ty)| (sym.clone(), ty.clone()))
+    .collect::<Vec<_>>();
+  unused.sort_by_key(|(sym, ty)| (ty.to_string(), sym.0.clone()));
+  args.extend(unused);
+  args
+}
+
+impl<D: Analysis> EGraph<D> {
+  /// Add the ops of a block, later ops see the results of earlier ones.
+  /// Returns the eclass of every op and the state left by the last effectful
+  /// op, `entry_state` if there is none.
+  pub fn add_block(&mut self, ops: &Space) -> (Vec<Id<D>>, Id<D>) {
+    let mut scope = Scope(vec![Frame::new(HashMap::new())]);
+    let ids = self.add_ops(ops, &mut scope);
+    let (state, _) = self.add_raw_node(scope.state().unwrap());
+    (ids, state)
+  }
+
+  fn add_ops(&mut self, ops: &Space, scope: &mut Scope<D>) -> Vec<Id<D>> {
+    ops
+      .iter()
+      .map(|op| {
+        let op = op.as_ref().borrow();
+        let (id, eop) = self.add_op_in(&op, scope);
+        let frame = scope.0.last_mut().unwrap();
+        for (offset, def) in op.defs.iter().enumerate() {
+          frame
+            .defs
+            .insert(def.clone(), RawENode::Use(eop.clone(), offset));
+        }
+        id
+      })
+      .collect()
+  }
+
+  /// A `region.block` term for each block of `region`, sorted by label.
+  ///
+  /// The uses of a block term are its root ops, the pure ones whose results
+  /// are not used in the block, then its final state if it has effects. Its
+  /// sign is the types of its arguments in binder order and its label is the
+  /// attribute `label`.
+  pub(crate) fn add_region(&mut self, region: &Region, scope: &mut Scope<D>) -> Vec<(Form, Id<D>)> {
+    sorted_blocks(region)
+      .into_iter()
+      .map(|block| self.add_block_term(block, scope))
+      .collect()
+  }
+
+  fn add_block_term(&mut self, block: &Block, scope: &mut Scope<D>) -> (Form, Id<D>) {
+    let args = binder_order(block);
+    scope.0.push(Frame::new(
+      args
+        .iter()
+        .enumerate()
+        .map(|(i, (sym, _))| (sym.clone(), i))
+        .collect(),
+    ));
+    let ids = self.add_ops(&block.2, scope);
+    let state = scope.0.pop().unwrap().state;
+
+    let mut used = vec![];
+    for op in &block.2 {
+      inputs(&op.as_ref().borrow(), &mut used);
+    }
+    let mut roots = block
+      .2
+      .iter()
+      .zip(ids)
+      .filter(|(op, _)| {
+        let op = op.as_ref().borrow();
+        self.registry.is_pure(&op.opcode) && !op.defs.iter().any(|def| used.contains(def))
+      })
+      .map(|(_, id)| id)
+      .collect::<Vec<_>>();
+    // the effectful ops are reached from the final state
+    if state != RawENode::Argument(entry_state()) {
+      roots.push(self.add_raw_node(state).0);
+    }
+
+    let mut attr = HashMap::new();
+    if let Some(label) = &block.0 {
+      attr.insert(Symbol::new("label"), Constant::String(label.0.to_string()));
+    }
+    let forms = roots
+      .iter()
+      .map(|id| id.get_forms().into_iter().next())
+      .collect();
+    let eop = EOp {
+      form_cache: Form::Form(block_opcode(), forms),
+      opcode: block_opcode(),
+      defs: vec![],
+      uses: roots,
+      attr,
+      region: Region::new(),
+      sign: vec![Type::FuncType(FuncType(
+        args.into_iter().map(|(_, ty)| ty).collect(),
+        vec![],
+      ))],
+      symmetry: Symmetry::None,
+      state: false,
+    };
+    let node = RawENode::Use(EOpHand::new(eop), 0);
+    let form = node.get_form().unwrap();
+    let (id, _) = self.add_raw_node(node);
+    (form, id)
+  }
+}
+
+impl<D> EGraph<D> {
+  /// Whether eclass `id` has a term using no argument of the blocks from
+  /// `depth` levels out, such a term may be moved out of `depth + 1` blocks.
+  pub fn binder_free(&self, id: &Id<D>, depth: usize) -> bool {
+    binder_free(id, depth, &mut HashMap::new(), &mut HashSet::new())
+  }
+}
+
+#[allow(clippy::mutable_key_type)]
+fn binder_free<D>(
+  id: &Id<D>,
+  depth: usize,
+  memo: &mut HashMap<(Id<D>, usize), bool>,
+  visiting: &mut HashSet<Id<D>>,
+) -> bool {
+  let key = (id.find(), depth);
+  if let Some(&free) = memo.get(&key) {
+    return free;
+  }
+  // a term going through itself is infinite, it does not count,
+  // whatever the depth since a cycle may go through a block
+  if !visiting.insert(key.0.clone()) {
+    return false;
+  }
+  let nodes = key.0.as_ref().borrow().nodes.clone();
+  let free = nodes.iter().any(|node| match &node.body {
+    RawENode::Argument(arg) => binder_depth(arg).is_none_or(|d| d < depth),
+    RawENode::Use(op, _) => {
+      let op = op.as_ref().borrow();
+      let depth = if op.opcode == block_opcode() {
+        depth + 1
+      } else {
+        depth
+      };
+      op.uses
+        .iter()
+        .all(|id| binder_free(id, depth, memo, visiting))
+    },
+    _ => true,
+  });
+  visiting.remove(&key.0);
+  memo.insert(key, free);
+  free
+}
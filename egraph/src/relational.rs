@@ -0,0 +1,573 @@
+use std::{
+  cell::OnceCell,
+  collections::{BTreeMap, HashMap},
+  marker::PhantomData,
+};
+
+use cfir::{
+  dialect::Symmetry,
+  rewriter::pattern::{Bindings, Catch, OpMeta, OpPat, OpPatHand, OpcodePat, ValuePat},
+  symbol::{Name, Symbol},
+};
+
+use crate::{
+  eclass::Id,
+  egraph::EGraph,
+  enode::{ENode, RawENode},
+  machine::{atom_eq, flatten, Live},
+  matching::{flattens_into, orders, permutations, MatchRecord},
+};
+
+/// Query variable, it stands for an eclass.
+pub type Var = usize;
+
+/// Relation of a query, its first argument is the eclass of the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Relation {
+  /// `opcode(eclass, child0, child1, ...)` over the op nodes with this result
+  /// offset, a wildcard is the union of the relations of its opcodes.
+  Op(Catch<OpcodePat>, usize),
+  /// `atom(eclass)` over the eclasses holding the atom.
+  Atom(ValuePat),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryAtom {
+  pub relation: Relation,
+  pub args: Vec<Var>,
+  /// Attributes and sign of the op, checked on the nodes of a join result.
+  pub meta: OpMeta,
+  pub shape: Shape,
+  /// Number of uses of the node, the root of a pattern is matched only
+  /// against the ops of its arity, like the candidates of the tree matcher.
+  pub uses: Option<usize>,
+}
+
+/// Which op nodes an op atom is over, the tree matcher flattens the
+/// children of an associative-commutative op of the same opcode into it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+  /// The pattern as written, over the op nodes that are not an
+  /// associative-commutative op of one of these opcodes.
+  Nested(Vec<Name>),
+  /// The pattern flattened through its children of this opcode, over the
+  /// associative-commutative op nodes of it.
+  Flat(Name),
+}
+
+/// Patterns compiled into a conjunctive query, evaluated by generic join.
+///
+/// Every op pattern is an atom over its eclass and its children, variables
+/// shared by patterns are joined. Unnamed uses get fresh variables, so the
+/// matches are the ones of the tree matcher, with the same multiplicity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conjunction {
+  /// Name of each variable, if it has one.
+  pub vars: Vec<Option<Symbol>>,
+  pub atoms: Vec<QueryAtom>,
+  /// Atom of the first pattern, its node is the one returned with a match.
+  pub root: usize,
+}
+
+/// Union of a conjunctive query for every way the patterns may be flattened,
+/// they match disjoint sets of nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+  pub branches: Vec<Conjunction>,
+}
+
+impl Query {
+  pub fn compile_op(op: &OpPat) -> Self {
+    let pat = ValuePat::Use(OpPatHand::new(op.clone()), 0);
+    Query::compile(&[Catch(Some(pat), None)])
+  }
+
+  /// Query matching all of `pats` at once, they share their variables.
+  /// A pattern without a value pattern matches nothing, like in the tree
+  /// matcher.
+  pub fn compile(pats: &[Catch<ValuePat>]) -> Self {
+    let mut branches = vec![Conjunction {
+      vars: vec![],
+      atoms: vec![],
+      root: 0,
+    }];
+    for (i, catch) in pats.iter().enumerate() {
+      let Some(pat) = &catch.0 else {
+        return Query { branches: vec![] };
+      };
+      branches = branches
+        .into_iter()
+        .flat_map(|mut branch| {
+          let var = branch.var(catch);
+          branch.value(pat, var, true)
+        })
+        .map(|(mut branch, atom)| {
+          if i == 0 {
+            branch.root = atom;
+          }
+          branch
+        })
+        .collect();
+    }
+    Query { branches }
+  }
+}
+
+/// Shapes an op pattern is matched in, with its uses in each.
+fn shapes(op: &OpPat) -> Vec<(Shape, Vec<Catch<ValuePat>>)> {
+  let mut names: Vec<Name> = vec![];
+  for catch in &op.1 {
+    if let Catch(Some(ValuePat::Use(child, 0)), None) = catch {
+      let child = child.as_ref().borrow();
+      if let Some(name) = child.0.name() {
+        if flattens_into(&child, name) && !names.contains(name) {
+          names.push(name.clone());
+        }
+      }
+    }
+  }
+  let flat = names
+    .iter()
+    .map(|name| (Shape::Flat(name.clone()), flat_uses(op, name)))
+    .collect::<Vec<_>>();
+  std::iter::once((Shape::Nested(names), op.1.clone()))
+    .chain(flat)
+    .collect()
+}
+
+/// Uses of `op` flattened through its children of `opcode`.
+fn flat_uses(op: &OpPat, opcode: &Name) -> Vec<Catch<ValuePat>> {
+  let mut out = vec![];
+  for catch in &op.1 {
+    match catch {
+      Catch(Some(ValuePat::Use(child, 0)), None)
+        if flattens_into(&child.as_ref().borrow(), opcode) =>
+      {
+        out.extend(flat_uses(&child.as_ref().borrow(), opcode))
+      },
+      _ => out.push(catch.clone()),
+    }
+  }
+  out
+}
+
+impl Conjunction {
+  fn var(&mut self, catch: &Catch<ValuePat>) -> Var {
+    if let Some(sym) = &catch.1 {
+      if let Some(var) = self.vars.iter().position(|v| v.as_ref() == Some(sym)) {
+        return var;
+      }
+    }
+    self.vars.push(catch.1.clone());
+    self.vars.len() - 1
+  }
+
+  /// Add the atoms of `pat` matched at `var`, in every shape of its ops.
+  /// Returns the branches with the index of its own atom, `root` if it is
+  /// the root of a pattern.
+  fn value(self, pat: &ValuePat, var: Var, root: bool) -> Vec<(Self, usize)> {
+    let ValuePat::Use(op, offset) = pat else {
+      let mut branch = self;
+      branch.atoms.push(QueryAtom {
+        relation: Relation::Atom(pat.clone()),
+        args: vec![var],
+        meta: OpMeta::default(),
+        shape: Shape::Nested(vec![]),
+        uses: None,
+      });
+      let atom = branch.atoms.len() - 1;
+      return vec![(branch, atom)];
+    };
+    let op = op.as_ref().borrow();
+    let mut out = vec![];
+    for (shape, uses) in shapes(&op) {
+      let mut branch = self.clone();
+      let children = uses.iter().map(|c| branch.var(c)).collect::<Vec<_>>();
+      let atom = branch.atoms.len();
+      branch.atoms.push(QueryAtom {
+        relation: Relation::Op(op.0.clone(), *offset),
+        args: std::iter::once(var)
+          .chain(children.iter().copied())
+          .collect(),
+        meta: op.3.clone(),
+        shape,
+        uses: root.then_some(op.1.len()),
+      });
+      let mut branches = vec![branch];
+      for (catch, var) in uses.iter().zip(children) {
+        if let Some(pat) = &catch.0 {
+          branches = branches
+            .into_iter()
+            .flat_map(|branch| branch.value(pat, var, false))
+            .map(|(branch, _)| branch)
+            .collect();
+        }
+      }
+      out.extend(branches.into_iter().map(|branch| (branch, atom)));
+    }
+    out
+  }
+
+  /// Join order, the variables shared by most atoms first.
+  fn order(&self) -> Vec<Var> {
+    let count = |var: &Var| {
+      self
+        .atoms
+        .iter()
+        .filter(|atom| atom.args.contains(var))
+        .count()
+    };
+    let mut order = (0..self.vars.len()).collect::<Vec<_>>();
+    order.sort_by_key(|var| std::cmp::Reverse(count(var)));
+    order
+  }
+}
+
+/// Tuples of a relation, keyed by the variables of an atom in join order.
+#[derive(Debug)]
+struct Trie<D> {
+  /// Ordered, the join visits the eclasses in the order they were added.
+  children: BTreeMap<usize, Trie<D>>,
+  nodes: Vec<ENode<D>>,
+}
+
+impl<D> Trie<D> {
+  fn new() -> Self {
+    Trie {
+      children: BTreeMap::new(),
+      nodes: vec![],
+    }
+  }
+
+  fn insert(&mut self, keys: &[usize], node: ENode<D>) {
+    match keys.split_first() {
+      Some((key, rest)) => self
+        .children
+        .entry(*key)
+        .or_insert_with(Trie::new)
+        .insert(rest, node),
+      None => self.nodes.push(node),
+    }
+  }
+}
+
+/// Eclasses numbered for the join.
+#[derive(Debug)]
+struct Classes<D> {
+  ids: Vec<Id<D>>,
+  index: HashMap<Id<D>, usize>,
+}
+
+impl<D> Classes<D> {
+  fn of(&self, id: &Id<D>) -> usize {
+    self.index[&id.find()]
+  }
+}
+
+/// Tuple of eclass numbers and the node it comes from.
+type Tuple<D> = (Vec<usize>, ENode<D>);
+
+/// Node with the number of its eclass.
+type Member<D> = (usize, ENode<D>);
+
+/// Relations of an egraph, indexed by opcode, arity and result offset.
+#[derive(Debug)]
+pub struct Relations<D> {
+  classes: Classes<D>,
+  ops: BTreeMap<(Name, usize, usize), Vec<Tuple<D>>>,
+  /// Associative-commutative op nodes by opcode and result offset, with
+  /// their eclass, flattened into the relations of a greater arity.
+  assoc: BTreeMap<(Name, usize), Vec<Member<D>>>,
+  atoms: Vec<(RawENode<D>, usize, ENode<D>)>,
+}
+
+impl<D> Relations<D> {
+  /// Index every node of a rebuilt egraph.
+  pub fn new(egraph: &EGraph<D>) -> Self {
+    let ids = egraph.classes();
+    #[allow(clippy::mutable_key_type)]
+    let index = ids
+      .iter()
+      .enumerate()
+      .map(|(i, id)| (id.clone(), i))
+      .collect();
+    let classes = Classes { ids, index };
+    let mut ops: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    let mut assoc: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    let mut atoms = vec![];
+    for (i, id) in classes.ids.iter().enumerate() {
+      for node in &id.as_ref().borrow().nodes {
+        match &node.body {
+          RawENode::Use(op, offset) => {
+            let op = op.as_ref().borrow();
+            // a tuple for every order the uses may be matched in
+            for uses in orders(&op.uses, op.symmetry) {
+              let tuple = std::iter::once(i)
+                .chain(uses.iter().map(|id| classes.of(id)))
+                .collect();
+              ops
+                .entry((op.opcode.clone(), op.uses.len(), *offset))
+                .or_default()
+                .push((tuple, node.clone()));
+            }
+            if op.symmetry == Symmetry::AssocComm {
+              assoc
+                .entry((op.opcode.clone(), *offset))
+                .or_default()
+                .push((i, node.clone()));
+            }
+          },
+          atom => atoms.push((atom.clone(), i, node.clone())),
+        }
+      }
+    }
+    Relations {
+      classes,
+      ops,
+      assoc,
+      atoms,
+    }
+  }
+
+  fn tuples(&self, atom: &QueryAtom) -> Vec<Tuple<D>> {
+    match &atom.relation {
+      Relation::Op(opcode, offset) => {
+        let arity = atom.args.len() - 1;
+        let accepts = |name: &Name| match (&atom.shape, opcode.name()) {
+          (Shape::Flat(flat), _) => name == flat,
+          (Shape::Nested(_), Some(literal)) => name == literal,
+          (Shape::Nested(_), None) => opcode.0.as_ref().is_none_or(|pat| pat.accepts(name)),
+        };
+        // an associative-commutative op of a flat shape is flattened, and only
+        // matched in it
+        let in_shape = |name: &Name, node: &ENode<D>| {
+          let assoc = match &node.body {
+            RawENode::Use(op, _) => op.as_ref().borrow().symmetry == Symmetry::AssocComm,
+            _ => false,
+          };
+          match &atom.shape {
+            Shape::Flat(_) => assoc,
+            Shape::Nested(flat) => !(assoc && flat.contains(name)),
+          }
+        };
+        let mut tuples = vec![];
+        if atom.uses.is_none_or(|uses| uses == arity) {
+          tuples.extend(
+            self
+              .ops
+              .iter()
+              .filter(|((name, a, o), _)| *a == arity && o == offset && accepts(name))
+              .flat_map(|((name, _, _), tuples)| {
+                tuples
+                  .iter()
+                  .filter(|(_, node)| in_shape(name, node))
+                  .cloned()
+              }),
+          );
+        }
+        // the uses of an associative-commutative op flattened up to the arity
+        for ((name, _), nodes) in self
+          .assoc
+          .iter()
+          .filter(|((name, o), _)| o == offset && accepts(name))
+        {
+          for (class, node) in nodes.iter().filter(|(_, node)| in_shape(name, node)) {
+            let RawENode::Use(op, _) = &node.body else {
+              continue;
+            };
+            let uses = op
+              .as_ref()
+              .borrow()
+              .uses
+              .iter()
+              .map(Id::find)
+              .collect::<Vec<_>>();
+            if uses.len() >= arity || atom.uses.is_some_and(|n| n != uses.len()) {
+              continue;
+            }
+            let lists = flatten(&Live(PhantomData), &uses, name, arity, &mut vec![]);
+            for list in lists.iter().filter(|list| list.len() == arity) {
+              for list in permutations(list, Symmetry::AssocComm) {
+                let tuple = std::iter::once(*class)
+                  .chain(list.iter().map(|id| self.classes.of(id)))
+                  .collect();
+                tuples.push((tuple, node.clone()));
+              }
+            }
+          }
+        }
+        tuples
+      },
+      Relation::Atom(pat) => self
+        .atoms
+        .iter()
+        .filter(|(atom, _, _)| atom_eq(pat, atom))
+        .map(|(_, i, node)| (vec![*i], node.clone()))
+        .collect(),
+    }
+  }
+
+  /// Matches of `query`, with the node of its root atom.
+  pub fn run(&self, query: &Query) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    query
+      .branches
+      .iter()
+      .flat_map(|branch| self.join(branch))
+      .collect()
+  }
+
+  fn join(&self, query: &Conjunction) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    let order = query.order();
+    let rank = |var: &Var| order.iter().position(|v| v == var).unwrap();
+    // variables of each atom in join order, each one once
+    let atom_vars = query
+      .atoms
+      .iter()
+      .map(|atom| {
+        let mut vars = atom.args.clone();
+        vars.sort_by_key(rank);
+        vars.dedup();
+        vars
+      })
+      .collect::<Vec<_>>();
+    let tries = query
+      .atoms
+      .iter()
+      .zip(&atom_vars)
+      .map(|(atom, vars)| {
+        let mut trie = Trie::new();
+        'tuples: for (tuple, node) in self.tuples(atom) {
+          let mut keys = vec![None; vars.len()];
+          for (var, value) in atom.args.iter().zip(&tuple) {
+            let k = vars.iter().position(|v| v == var).unwrap();
+            match keys[k] {
+              Some(key) if key != *value => continue 'tuples,
+              _ => keys[k] = Some(*value),
+            }
+          }
+          let keys = keys.into_iter().map(Option::unwrap).collect::<Vec<_>>();
+          trie.insert(&keys, node);
+        }
+        trie
+      })
+      .collect::<Vec<_>>();
+
+    let mut join = Join {
+      query,
+      order: &order,
+      atom_vars: &atom_vars,
+      assignment: vec![0; query.vars.len()],
+      relations: self,
+      out: vec![],
+    };
+    join.step(0, tries.iter().collect());
+    join.out
+  }
+}
+
+struct Join<'a, D> {
+  query: &'a Conjunction,
+  order: &'a [Var],
+  atom_vars: &'a [Vec<Var>],
+  assignment: Vec<usize>,
+  relations: &'a Relations<D>,
+  out: Vec<(ENode<D>, MatchRecord<D>)>,
+}
+
+impl<D> Join<'_, D> {
+  fn step(&mut self, depth: usize, tries: Vec<&Trie<D>>) {
+    let Some(&var) = self.order.get(depth) else {
+      self.emit(&tries);
+      return;
+    };
+    let joined = (0..tries.len())
+      .filter(|&a| self.atom_vars[a].contains(&var))
+      .collect::<Vec<_>>();
+    // iterate the smallest relation and probe the others
+    let Some(&smallest) = joined.iter().min_by_key(|&&a| tries[a].children.len()) else {
+      return;
+    };
+    for (&value, _) in tries[smallest].children.iter() {
+      let mut next = tries.clone();
+      let found = joined.iter().all(|&a| match tries[a].children.get(&value) {
+        Some(child) => {
+          next[a] = child;
+          true
+        },
+        None => false,
+      });
+      if found {
+        self.assignment[var] = value;
+        self.step(depth + 1, next);
+      }
+    }
+  }
+
+  fn emit(&mut self, tries: &[&Trie<D>]) {
+    let record: MatchRecord<D> = self
+      .query
+      .vars
+      .iter()
+      .zip(&self.assignment)
+      .filter_map(|(sym, &value)| {
+        let id = &self.relations.classes.ids[value];
+        Some((sym.clone()?, id.as_ref().borrow().nodes[0].clone()))
+      })
+      .collect();
+    // duplicate nodes of the atoms are separate matches for the tree matcher,
+    // each combination with its own opcodes, attributes and sign
+    let mut combos = vec![(None, Bindings::default())];
+    for (a, trie) in tries.iter().enumerate() {
+      let atom = &self.query.atoms[a];
+      let is_root = a == self.query.root;
+      combos = combos
+        .into_iter()
+        .flat_map(|(root, bindings)| {
+          trie.nodes.iter().filter_map(move |node| {
+            let mut bindings = bindings.clone();
+            if let (Relation::Op(opcode, _), RawENode::Use(op, _)) = (&atom.relation, &node.body) {
+              let op = op.as_ref().borrow();
+              if !opcode.matching(&op.opcode, &mut bindings)
+                || !atom.meta.matching(&op.attr, &op.sign, &mut bindings)
+              {
+                return None;
+              }
+            }
+            let root = match is_root {
+              true => Some(node.clone()),
+              false => root.clone(),
+            };
+            Some((root, bindings))
+          })
+        })
+        .collect();
+    }
+    for (root, bindings) in combos {
+      let record = record.clone().with_bindings(bindings);
+      self.out.push((root.unwrap(), record));
+    }
+  }
+}
+
+impl<D> EGraph<D> {
+  /// Matches of a query, the egraph must be rebuilt. The relations are
+  /// indexed once per search phase, and for every query outside of one.
+  pub fn run_query(&self, query: &Query) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    match &self.relations {
+      Some(relations) => relations.get_or_init(|| Relations::new(self)).run(query),
+      None => Relations::new(self).run(query),
+    }
+  }
+
+  /// Run `search` as a search phase, the queries run in it share the
+  /// relations of the egraph. It must not change the egraph.
+  pub fn search_phase<R>(&mut self, search: impl FnOnce(&mut Self) -> R) -> R {
+    if self.relations.is_some() {
+      return search(self);
+    }
+    self.relations = Some(OnceCell::new());
+    let found = search(self);
+    self.relations = None;
+    found
+  }
+}
@@ -1,32 +1,32 @@
-use std::collections::HashMap;
-
 use cfir::{
   block::Region,
-  op::Attr,
   rewriter::{
     form::{Form, GetForm},
     pattern::{Catch, OpPat, OpPatHand, ValuePat},
   },
-  symbol::Symbol,
-  types::Type,
 };
 
 use crate::{
+  analysis::Analysis,
   egraph::EGraph,
   enode::{ENode, EOp, EOpHand, RawENode},
+  matching::MatchRecord,
 };
 
-type MatchRecord<D> = HashMap<Symbol, ENode<D>>;
-
+/// Build the rhs of a rule from the substitution of its lhs.
+///
+/// Uses are built left to right, `?x:pat` binds the term built for `pat` to `?x`
+/// so later uses of `?x` share it. If `?x` is already bound, the term is asserted
+/// equal to the binding.
 pub trait Rewriter<D> {
   type Output;
-  fn rewrite(&self, record: &MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output;
+  fn rewrite(&self, record: &mut MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output;
 }
 
-impl<D: Default> Rewriter<D> for OpPat {
+impl<D: Analysis> Rewriter<D> for OpPat {
   type Output = Option<EOp<D>>;
 
-  fn rewrite(&self, record: &MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output {
+  fn rewrite(&self, record: &mut MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output {
     let uses = self
       .1
       .iter()
@@ -35,27 +35,35 @@ impl<D: Default> Rewriter<D> for OpPat {
 
     let forms = uses.iter().map(GetForm::get_form).collect();
 
-    let uses = uses.iter().map(|node| node.get_id()).collect();
+    let uses: Vec<_> = uses.iter().map(|node| node.get_id()).collect();
+
+    let opcode = self.0.build(&record.bindings)?;
+    // FIXME: type inference, the sign is `any` unless the pattern gives one
+    let (attr, sign) = self.3.build(&record.bindings)?;
+    let symmetry = egraph.registry.symmetry(&opcode);
+    // an effectful op built in a block is given the state explicitly
+    let state =
+      !egraph.registry.is_pure(&opcode) && uses.last().is_some_and(|id| egraph.is_state(id));
 
     Some(EOp {
-      form_cache: Form::Form(self.0.clone(), forms),
-      opcode: self.0.clone(),
+      form_cache: Form::Form(opcode.clone(), forms),
+      opcode,
       // def: None, // FIXME: gen new id
       defs: vec![], // FIXME: gen new id
       uses,
-      attr: Attr::new(),
+      attr,
       region: Region::new(),
-      // sign: self.2.clone(),
-      // sign: FuncType(vec![], vec![Type::any_type()]), // FIXME: type inference
-      sign: vec![Type::any_type()],
+      sign,
+      symmetry,
+      state,
     })
   }
 }
 
-impl<D: Default> Rewriter<D> for OpPatHand {
+impl<D: Analysis> Rewriter<D> for OpPatHand {
   type Output = Option<EOpHand<D>>;
 
-  fn rewrite(&self, record: &MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output {
+  fn rewrite(&self, record: &mut MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output {
     self
       .as_ref()
       .borrow()
@@ -64,25 +72,32 @@ impl<D: Default> Rewriter<D> for OpPatHand {
   }
 }
 
-impl<D: Default> Rewriter<D> for Catch<ValuePat> {
+impl<D: Analysis> Rewriter<D> for Catch<ValuePat> {
   type Output = Option<ENode<D>>;
 
-  fn rewrite(&self, record: &MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output {
+  fn rewrite(&self, record: &mut MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output {
     match (&self.0, &self.1) {
       (None, None) => None,
       (None, Some(sym)) => record.get(sym).cloned(),
       (Some(pat), None) => pat.rewrite(record, egraph),
-      (Some(_pat), Some(_sym)) => {
-        todo!()
+      (Some(pat), Some(sym)) => {
+        let node = pat.rewrite(record, egraph)?;
+        if let Some(bound) = record.get(sym).cloned() {
+          egraph.union_nodes(&bound, &node);
+          Some(bound)
+        } else {
+          record.insert(sym.clone(), node.clone());
+          Some(node)
+        }
       },
     }
   }
 }
 
-impl<D: Default> Rewriter<D> for ValuePat {
+impl<D: Analysis> Rewriter<D> for ValuePat {
   type Output = Option<ENode<D>>;
 
-  fn rewrite(&self, record: &MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output {
+  fn rewrite(&self, record: &mut MatchRecord<D>, egraph: &mut EGraph<D>) -> Self::Output {
     let node = match self {
       ValuePat::Use(u, offset) => RawENode::Use(u.rewrite(record, egraph)?, *offset),
       ValuePat::Const(v) => RawENode::Const(v.clone()),
@@ -94,3 +109,21 @@ impl<D: Default> Rewriter<D> for ValuePat {
     Some(r)
   }
 }
+
+/// Rhs of a rule computed in Rust, for rewrites a template can't express such
+/// as constants computed from the matched ones.
+///
+/// The returned node is unioned with the node the lhs matched, `None` skips
+/// the match. Further unions can be made through `egraph`.
+pub trait Applier<D> {
+  fn apply(&self, record: &mut MatchRecord<D>, egraph: &mut EGraph<D>) -> Option<ENode<D>>;
+}
+
+impl<D, F> Applier<D> for F
+where
+  F: Fn(&mut MatchRecord<D>, &mut EGraph<D>) -> Option<ENode<D>>,
+{
+  fn apply(&self, record: &mut MatchRecord<D>, egraph: &mut EGraph<D>) -> Option<ENode<D>> {
+    self(record, egraph)
+  }
+}
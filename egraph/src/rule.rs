@@ -0,0 +1,377 @@
+use std::rc::Rc;
+
+use cfir::{
+  dialect::OpTrait,
+  rewriter::{
+    pattern::{Catch, OpPat, ValuePat},
+    rule::{Cond, Operand, RuleDef, RuleGroup, RuleLhs, RuleRhs},
+  },
+  symbol::Symbol,
+  value::Constant,
+};
+
+use crate::{
+  analysis::Analysis,
+  const_fold::{value, IntType},
+  egraph::EGraph,
+  enode::{ENode, RawENode},
+  explain::Justification,
+  machine::Program,
+  matching::MatchRecord,
+  relational::Query,
+  rewriter::{Applier, Rewriter},
+};
+
+/// Side condition of a rule over the match bindings and the analysis data.
+pub type Guard<D> = Rc<dyn Fn(&EGraph<D>, &MatchRecord<D>) -> bool>;
+
+/// `lhs => rhs`, applied only where every guard holds.
+pub struct Rule<D> {
+  pub name: Symbol,
+  pub lhs: RuleLhs,
+  /// `None` for a dynamic rule, its rhs is built by `applier`.
+  pub rhs: Option<RuleRhs>,
+  pub guards: Vec<Guard<D>>,
+  /// Conditions of a parsed rule the guards were compiled from.
+  pub conds: Vec<Cond>,
+  /// Builds the rhs of a rule without `rhs`, see `Rule::dynamic`.
+  pub applier: Option<Rc<dyn Applier<D>>>,
+  /// Whether the guards only look at the bound eclasses, a resumed run
+  /// searches the other rules in full.
+  pub local: bool,
+  compiled: Compiled,
+}
+
+/// How the lhs of a rule is matched, all of them find the same matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matcher {
+  /// Recursive matcher of `EGraph::matching_op`.
+  Tree,
+  /// Compiled matching machine, used for single-pattern rules by default.
+  Machine,
+  /// Generic join over the nodes of each opcode, for patterns sharing many
+  /// variables. Multi-pattern rules use the tree matcher by default.
+  Relational,
+}
+
+/// `lhs` compiled for its matcher.
+#[derive(Debug, Clone)]
+enum Compiled {
+  Tree,
+  Machine(Program),
+  Relational(Query),
+}
+
+impl<D> Rule<D> {
+  fn build(name: Symbol, lhs: RuleLhs, rhs: Option<RuleRhs>) -> Self {
+    let matcher = match &lhs {
+      RuleLhs::Op(_) => Matcher::Machine,
+      RuleLhs::Multi(_) => Matcher::Tree,
+    };
+    Rule {
+      name,
+      lhs,
+      rhs,
+      guards: vec![],
+      conds: vec![],
+      applier: None,
+      local: true,
+      compiled: Compiled::Tree,
+    }
+    .with_matcher(matcher)
+  }
+
+  /// Match the lhs with `matcher`, the machine only runs single patterns
+  /// and falls back to the tree matcher.
+  pub fn with_matcher(mut self, matcher: Matcher) -> Self {
+    self.compiled = match (matcher, &self.lhs) {
+      (Matcher::Tree, _) | (Matcher::Machine, RuleLhs::Multi(_)) => Compiled::Tree,
+      (Matcher::Machine, RuleLhs::Op(op)) => Compiled::Machine(Program::compile_op(op)),
+      (Matcher::Relational, RuleLhs::Op(op)) => Compiled::Relational(Query::compile_op(op)),
+      (Matcher::Relational, RuleLhs::Multi(pats)) => Compiled::Relational(Query::compile(pats)),
+    };
+    self
+  }
+
+  /// The compiled machine, if `lhs` is matched with it.
+  pub fn program(&self) -> Option<&Program> {
+    match &self.compiled {
+      Compiled::Machine(program) => Some(program),
+      _ => None,
+    }
+  }
+
+  pub fn matcher(&self) -> Matcher {
+    match self.compiled {
+      Compiled::Tree => Matcher::Tree,
+      Compiled::Machine(_) => Matcher::Machine,
+      Compiled::Relational(_) => Matcher::Relational,
+    }
+  }
+
+  pub fn new(name: &str, lhs: OpPat, rhs: Catch<ValuePat>) -> Self {
+    Rule::build(
+      Symbol::new(name),
+      RuleLhs::Op(lhs),
+      Some(RuleRhs::Value(rhs)),
+    )
+  }
+
+  /// Rule over several patterns matched at once, every rhs term is `?x:pat`
+  /// and is unioned with the eclass bound to `?x`. A pattern that is a bare
+  /// variable is an error, it would match every eclass.
+  pub fn multi(
+    name: &str,
+    lhs: Vec<Catch<ValuePat>>,
+    rhs: Vec<Catch<ValuePat>>,
+  ) -> Result<Self, String> {
+    let lhs = RuleLhs::Multi(lhs);
+    check_lhs(name, &lhs)?;
+    Ok(Rule::build(
+      Symbol::new(name),
+      lhs,
+      Some(RuleRhs::Multi(rhs)),
+    ))
+  }
+
+  /// Rule whose rhs is computed by `applier`, it has no `rhs`.
+  pub fn dynamic(name: &str, lhs: OpPat, applier: impl Applier<D> + 'static) -> Self {
+    Rule {
+      applier: Some(Rc::new(applier)),
+      ..Rule::build(Symbol::new(name), RuleLhs::Op(lhs), None)
+    }
+  }
+
+  pub fn with_guard(
+    mut self,
+    guard: impl Fn(&EGraph<D>, &MatchRecord<D>) -> bool + 'static,
+  ) -> Self {
+    self.guards.push(Rc::new(guard));
+    self
+  }
+
+  pub fn check(&self, egraph: &EGraph<D>, record: &MatchRecord<D>) -> bool {
+    self.guards.iter().all(|guard| guard(egraph, record))
+  }
+}
+
+impl<D> Rule<D> {
+  /// Compile a parsed rule, its conditions become guards.
+  pub fn from_def(def: &RuleDef) -> Result<Self, String> {
+    check_lhs(&def.name.0, &def.lhs)?;
+    let mut rule = Rule::build(def.name.clone(), def.lhs.clone(), Some(def.rhs.clone()));
+    for cond in &def.guards {
+      cond
+        .check()
+        .map_err(|e| format!("rule `{}`: {}", def.name.0, e))?;
+      let cond = cond.clone();
+      // `closed` looks at the whole term under the eclass
+      rule.local &= !matches!(&cond, Cond::Call(name, _) if name.0.as_str() == "closed");
+      rule.conds.push(cond.clone());
+      rule = rule.with_guard(move |egraph, record| eval_cond(egraph, &cond, record));
+    }
+    Ok(rule)
+  }
+
+  pub fn from_groups(groups: &[RuleGroup]) -> Result<Vec<Self>, String> {
+    groups
+      .iter()
+      .flat_map(|group| &group.rules)
+      .map(Rule::from_def)
+      .collect()
+  }
+}
+
+/// Whether every pattern of a multi-pattern lhs is more than a variable.
+fn check_lhs(name: &str, lhs: &RuleLhs) -> Result<(), String> {
+  let RuleLhs::Multi(pats) = lhs else {
+    return Ok(());
+  };
+  match pats.iter().position(|catch| catch.0.is_none()) {
+    Some(i) => Err(format!(
+      "rule `{name}`: pattern {} is a bare variable",
+      i + 1
+    )),
+    None => Ok(()),
+  }
+}
+
+/// Constant held by the eclass of `node`.
+pub fn const_of<D>(node: &ENode<D>) -> Option<Constant> {
+  let id = node.get_id();
+  let eclass = id.as_ref().borrow();
+  eclass.nodes.iter().find_map(|node| match &node.body {
+    RawENode::Const(c) => Some(c.clone()),
+    _ => None,
+  })
+}
+
+/// Integer type of the eclass of `node`, from the first typed op in it, else
+/// from its constant.
+fn type_of<D>(node: &ENode<D>) -> Option<IntType> {
+  let id = node.get_id();
+  let typed = id
+    .as_ref()
+    .borrow()
+    .nodes
+    .iter()
+    .find_map(|node| IntType::from_type(&node.body.get_type()?));
+  typed.or_else(|| IntType::of(&const_of(node)?))
+}
+
+/// Whether two operands are equal, `None` if it is unknown.
+fn operand_eq<D>(l: &Operand, r: &Operand, record: &MatchRecord<D>) -> Option<bool> {
+  let constant = |operand: &Operand| match operand {
+    Operand::Var(sym) => match record.get(sym) {
+      Some(node) => const_of(node),
+      // an attribute variable
+      None => record.bindings.attrs.get(sym).cloned(),
+    },
+    Operand::Const(c) => Some(c.clone()),
+  };
+  if let (Operand::Var(l), Operand::Var(r)) = (l, r) {
+    let opcodes = &record.bindings.opcodes;
+    if let (Some(l), Some(r)) = (opcodes.get(l), opcodes.get(r)) {
+      return Some(l == r);
+    }
+    if let (Some(l), Some(r)) = (record.get(l), record.get(r)) {
+      if l.get_id() == r.get_id() {
+        return Some(true);
+      }
+    }
+  }
+  let (l, r) = (constant(l)?, constant(r)?);
+  // integer literals are untyped, `?c == 2` holds for a signed or unsigned 2;
+  // booleans and strings only equal constants of their own kind
+  let integer = |c: &Constant| matches!(c, Constant::Int(_) | Constant::Uint(_));
+  Some(l == r || integer(&l) && integer(&r) && value(&l) == value(&r))
+}
+
+fn eval_cond<D>(egraph: &EGraph<D>, cond: &Cond, record: &MatchRecord<D>) -> bool {
+  match cond {
+    Cond::Eq(l, r) => operand_eq(l, r, record) == Some(true),
+    Cond::Ne(l, r) => operand_eq(l, r, record) == Some(false),
+    Cond::Call(name, args) => {
+      let [Operand::Var(sym)] = args.as_slice() else {
+        return false;
+      };
+      if let Some(t) = OpTrait::from_name(&name.0) {
+        let opcode = record.bindings.opcodes.get(sym);
+        return opcode.is_some_and(|opcode| egraph.registry.has(opcode, t));
+      }
+      let Some(node) = record.get(sym) else {
+        return false;
+      };
+      match name.0.as_str() {
+        // uses no argument of the blocks it is in
+        "closed" => egraph.binder_free(&node.get_id(), 0),
+        "const" => const_of(node).is_some(),
+        "pow2" => const_of(node)
+          .as_ref()
+          .and_then(value)
+          .is_some_and(|v| v > 0 && v & (v - 1) == 0),
+        "signed" => matches!(type_of(node), Some(IntType::Int(_))),
+        "unsigned" => matches!(type_of(node), Some(IntType::Uint(_))),
+        _ => false,
+      }
+    },
+  }
+}
+
+impl<D> Clone for Rule<D> {
+  fn clone(&self) -> Self {
+    Rule {
+      name: self.name.clone(),
+      lhs: self.lhs.clone(),
+      rhs: self.rhs.clone(),
+      guards: self.guards.clone(),
+      conds: self.conds.clone(),
+      applier: self.applier.clone(),
+      local: self.local,
+      compiled: self.compiled.clone(),
+    }
+  }
+}
+
+impl<D> std::fmt::Debug for Rule<D> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Rule")
+      .field("name", &self.name)
+      .field("lhs", &self.lhs)
+      .field("rhs", &self.rhs)
+      .field("guards", &self.guards.len())
+      .field("conds", &self.conds)
+      .field("applier", &self.applier.is_some())
+      .field("local", &self.local)
+      .field("matcher", &self.matcher())
+      .finish()
+  }
+}
+
+impl<D: Analysis> EGraph<D> {
+  /// Matches of `rule` whose guards hold, with the node matched by the
+  /// first pattern of the lhs.
+  pub fn search_rule(&mut self, rule: &Rule<D>) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    let matches = match (&rule.lhs, &rule.compiled) {
+      (_, Compiled::Machine(program)) => self.run_program(program),
+      (_, Compiled::Relational(query)) => self.run_query(query),
+      (RuleLhs::Op(lhs), Compiled::Tree) => self.matching_op(lhs.clone()),
+      (RuleLhs::Multi(lhs), Compiled::Tree) => self.matching_multi(lhs),
+    };
+    // the relational matcher does not go through the candidates
+    matches
+      .into_iter()
+      .filter(|(node, record)| self.in_frontier(node) && rule.check(self, record))
+      .collect()
+  }
+
+  /// Build the rhs of `rule` for every match and union it with the matched eclass,
+  /// returns the number of unions.
+  pub fn apply_matches(
+    &mut self,
+    rule: &Rule<D>,
+    matches: Vec<(ENode<D>, MatchRecord<D>)>,
+  ) -> usize {
+    let mut unions = 0;
+    for (node, mut record) in matches {
+      let why = match self.explain.as_mut() {
+        Some(explain) => explain.rule(&rule.name, &record),
+        None => Justification::Given,
+      };
+      unions += self.with_reason(why, |egraph| match (&rule.applier, &rule.rhs) {
+        (Some(applier), _) => match applier.apply(&mut record, egraph) {
+          Some(new) => egraph.union_nodes(&node, &new) as usize,
+          None => 0,
+        },
+        (None, Some(RuleRhs::Value(rhs))) => match rhs.rewrite(&mut record, egraph) {
+          Some(new) => egraph.union_nodes(&node, &new) as usize,
+          None => 0,
+        },
+        (None, Some(RuleRhs::Multi(rhs))) => {
+          let mut unions = 0;
+          for Catch(pat, sym) in rhs {
+            let (Some(pat), Some(sym)) = (pat, sym) else {
+              continue;
+            };
+            let Some(new) = pat.rewrite(&mut record, egraph) else {
+              continue;
+            };
+            if let Some(bound) = record.get(sym).cloned() {
+              unions += egraph.union_nodes(&bound, &new) as usize;
+            } else {
+              record.insert(sym.clone(), new);
+            }
+          }
+          unions
+        },
+        (None, None) => 0,
+      });
+    }
+    unions
+  }
+
+  pub fn apply_rule(&mut self, rule: &Rule<D>) -> usize {
+    let matches = self.search_rule(rule);
+    self.apply_matches(rule, matches)
+  }
+}
@@ -0,0 +1,345 @@
+use std::fmt;
+
+use cfir::symbol::Symbol;
+
+use crate::{
+  analysis::Analysis, egraph::EGraph, enode::ENode, frontier::Frontier, matching::MatchRecord,
+  rule::Rule,
+};
+
+/// Decides which rules are searched and which matches are applied in an iteration.
+pub trait Scheduler<D> {
+  /// Called when an iteration made no change, the run goes on if it returns false.
+  fn can_stop(&mut self, _iteration: usize) -> bool {
+    true
+  }
+
+  /// Whether the `index`th rule is skipped in `iteration`.
+  fn is_banned(&self, _iteration: usize, _index: usize) -> bool {
+    false
+  }
+
+  /// Matches of the `index`th rule to apply in `iteration`.
+  fn filter_matches(
+    &mut self,
+    _iteration: usize,
+    _index: usize,
+    matches: Vec<(ENode<D>, MatchRecord<D>)>,
+  ) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    matches
+  }
+}
+
+/// Applies every match of every rule.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimpleScheduler;
+
+impl<D> Scheduler<D> for SimpleScheduler {}
+
+/// Bans a rule for `ban_length` iterations when it has more than `match_limit`
+/// matches, both double each time the rule is banned.
+#[derive(Debug, Clone)]
+pub struct BackoffScheduler {
+  pub match_limit: usize,
+  pub ban_length: usize,
+  stats: Vec<BackoffStats>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BackoffStats {
+  times_banned: u32,
+  banned_until: usize,
+}
+
+impl Default for BackoffScheduler {
+  fn default() -> Self {
+    BackoffScheduler::new(1000, 5)
+  }
+}
+
+impl BackoffScheduler {
+  pub fn new(match_limit: usize, ban_length: usize) -> Self {
+    BackoffScheduler {
+      match_limit,
+      ban_length,
+      stats: vec![],
+    }
+  }
+
+  fn stats(&mut self, index: usize) -> &mut BackoffStats {
+    if self.stats.len() <= index {
+      self.stats.resize(index + 1, BackoffStats::default());
+    }
+    &mut self.stats[index]
+  }
+}
+
+/// `n` doubled `times` times, `usize::MAX` once it overflows.
+fn doubled(n: usize, times: u32) -> usize {
+  match 1usize.checked_shl(times) {
+    _ if n == 0 => 0,
+    Some(factor) => n.saturating_mul(factor),
+    None => usize::MAX,
+  }
+}
+
+impl<D> Scheduler<D> for BackoffScheduler {
+  fn can_stop(&mut self, iteration: usize) -> bool {
+    // a banned rule may still change the egraph, so give it another chance
+    let banned = self.stats.iter().any(|s| s.banned_until > iteration);
+    for stats in self.stats.iter_mut() {
+      stats.banned_until = stats.banned_until.min(iteration);
+    }
+    !banned
+  }
+
+  fn filter_matches(
+    &mut self,
+    iteration: usize,
+    index: usize,
+    matches: Vec<(ENode<D>, MatchRecord<D>)>,
+  ) -> Vec<(ENode<D>, MatchRecord<D>)> {
+    let (match_limit, ban_length) = (self.match_limit, self.ban_length);
+    let stats = self.stats(index);
+    if matches.len() > doubled(match_limit, stats.times_banned) {
+      let ban = doubled(ban_length, stats.times_banned);
+      stats.banned_until = iteration.saturating_add(ban);
+      stats.times_banned = stats.times_banned.saturating_add(1);
+      return vec![];
+    }
+    matches
+  }
+
+  fn is_banned(&self, iteration: usize, index: usize) -> bool {
+    self
+      .stats
+      .get(index)
+      .is_some_and(|s| s.banned_until > iteration)
+  }
+}
+
+/// Why a run stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+  Saturated,
+  IterationLimit(usize),
+}
+
+/// Counts of one rule over a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleReport {
+  pub name: Symbol,
+  /// Matches found, including the ones dropped by the scheduler.
+  pub matches: usize,
+  /// Matches applied.
+  pub applied: usize,
+  /// Unions made by the applied matches.
+  pub unions: usize,
+  /// Iterations the rule was banned.
+  pub banned: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+  pub iterations: usize,
+  pub stop_reason: StopReason,
+  /// Eclasses and enodes at the end of the run.
+  pub classes: usize,
+  pub nodes: usize,
+  pub rules: Vec<RuleReport>,
+  /// Eclasses in the frontier of each iteration of a resumed run, empty for
+  /// a full run.
+  pub frontier: Vec<usize>,
+}
+
+impl fmt::Display for Report {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(
+      f,
+      "{} iterations, stopped by {:?}, {} eclasses, {} enodes",
+      self.iterations, self.stop_reason, self.classes, self.nodes
+    )?;
+    if !self.frontier.is_empty() {
+      writeln!(f, "  frontier: {:?}", self.frontier)?;
+    }
+    for rule in &self.rules {
+      writeln!(
+        f,
+        "  {}: {} matches, {} applied, {} unions, banned {} iterations",
+        rule.name.0, rule.matches, rule.applied, rule.unions, rule.banned
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Saturation loop over a set of rules.
+pub struct Runner<D> {
+  pub iter_limit: usize,
+  /// Threads the machine-compiled rules are searched on, the other rules and
+  /// the guards run on the calling thread, see `EGraph::search_rules`.
+  pub threads: usize,
+  pub scheduler: Box<dyn Scheduler<D>>,
+}
+
+impl<D> Default for Runner<D> {
+  fn default() -> Self {
+    Runner {
+      iter_limit: 30,
+      threads: 1,
+      scheduler: Box::new(SimpleScheduler),
+    }
+  }
+}
+
+impl<D> Runner<D> {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn with_iter_limit(mut self, iter_limit: usize) -> Self {
+    self.iter_limit = iter_limit;
+    self
+  }
+
+  /// Search the machine-compiled rules on `threads` threads, the matches and
+  /// the results are the same as on one. The tree and relational rules and
+  /// the guards run on the calling thread, and a resumed run searches its
+  /// frontier on one thread.
+  pub fn with_threads(mut self, threads: usize) -> Self {
+    self.threads = threads;
+    self
+  }
+
+  pub fn with_scheduler(mut self, scheduler: impl Scheduler<D> + 'static) -> Self {
+    self.scheduler = Box::new(scheduler);
+    self
+  }
+}
+
+impl<D: Analysis> Runner<D> {
+  /// Apply `rules` until nothing changes or the iteration limit is reached.
+  pub fn run(&mut self, egraph: &mut EGraph<D>, rules: &[Rule<D>]) -> Report {
+    self.run_from(egraph, rules, false)
+  }
+
+  /// Like `run` on an egraph run with `rules` before, only the matches rooted
+  /// near the eclasses added or changed since are searched.
+  pub fn resume(&mut self, egraph: &mut EGraph<D>, rules: &[Rule<D>]) -> Report {
+    self.run_from(egraph, rules, true)
+  }
+
+  fn run_from(&mut self, egraph: &mut EGraph<D>, rules: &[Rule<D>], resume: bool) -> Report {
+    let mut reports = rules
+      .iter()
+      .map(|rule| RuleReport {
+        name: rule.name.clone(),
+        matches: 0,
+        applied: 0,
+        unions: 0,
+        banned: 0,
+      })
+      .collect::<Vec<_>>();
+    let mut stop_reason = StopReason::IterationLimit(self.iter_limit);
+    let mut iterations = self.iter_limit;
+    let mut frontiers = vec![];
+    let depth = rules
+      .iter()
+      .filter_map(|rule| egraph.frontier_depth(rule))
+      .max()
+      .unwrap_or(0);
+    // rules whose matches were dropped are searched in full again
+    let mut dropped = vec![false; rules.len()];
+
+    egraph.rebuild();
+    for iter in 0..self.iter_limit {
+      let frontier = match resume {
+        true => Some(egraph.take_frontier(depth)),
+        false => {
+          egraph.touched.clear();
+          None
+        },
+      };
+      frontiers.extend(frontier.as_ref().map(Frontier::len));
+      // search everything before changing the egraph
+      let banned = (0..rules.len())
+        .map(|index| self.scheduler.is_banned(iter, index))
+        .collect::<Vec<_>>();
+      let found = egraph.search_phase(|egraph| match &frontier {
+        // a frontier is only searched on this thread
+        None if self.threads > 1 => {
+          let active = rules
+            .iter()
+            .zip(&banned)
+            .filter(|(_, banned)| !**banned)
+            .map(|(rule, _)| rule)
+            .collect::<Vec<_>>();
+          egraph.search_rules(&active, self.threads)
+        },
+        _ => (0..rules.len())
+          .filter(|index| !banned[*index])
+          .map(|index| match &frontier {
+            Some(frontier) if !dropped[index] => egraph.search_rule_in(&rules[index], frontier),
+            _ => egraph.search_rule(&rules[index]),
+          })
+          .collect(),
+      });
+      let mut found = found.into_iter();
+      let mut matches = vec![];
+      for index in 0..rules.len() {
+        if banned[index] {
+          reports[index].banned += 1;
+          dropped[index] = true;
+          matches.push(vec![]);
+          continue;
+        }
+        let found = found.next().unwrap();
+        reports[index].matches += found.len();
+        let count = found.len();
+        let found = self.scheduler.filter_matches(iter, index, found);
+        dropped[index] = found.len() < count;
+        if self.scheduler.is_banned(iter, index) {
+          reports[index].banned += 1;
+        }
+        matches.push(found);
+      }
+      let mut unions = 0;
+      for ((rule, matches), report) in rules.iter().zip(matches).zip(reports.iter_mut()) {
+        report.applied += matches.len();
+        let n = egraph.apply_matches(rule, matches);
+        report.unions += n;
+        unions += n;
+      }
+      egraph.rebuild();
+      if unions == 0 && self.scheduler.can_stop(iter) {
+        stop_reason = StopReason::Saturated;
+        iterations = iter + 1;
+        break;
+      }
+    }
+
+    let classes = egraph.classes();
+    Report {
+      iterations,
+      stop_reason,
+      classes: classes.len(),
+      nodes: classes
+        .iter()
+        .map(|id| id.as_ref().borrow().nodes.len())
+        .sum(),
+      rules: reports,
+      frontier: frontiers,
+    }
+  }
+}
+
+impl<D: Analysis> EGraph<D> {
+  /// Apply `rules` until nothing changes or `iter_limit` is reached,
+  /// returns the number of iterations run.
+  pub fn saturate(&mut self, rules: &[Rule<D>], iter_limit: usize) -> usize {
+    Runner::new()
+      .with_iter_limit(iter_limit)
+      .run(self, rules)
+      .iterations
+  }
+}
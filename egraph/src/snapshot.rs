@@ -0,0 +1,160 @@
+use std::{fmt, rc::Rc};
+
+use crate::{
+  eclass::Id,
+  egraph::EGraph,
+  enode::{ENode, EOpHand, RawENode},
+};
+
+/// Reverse of one change to the eclasses, logged while a snapshot is taken.
+pub(crate) enum Undo<D> {
+  /// Start of the changes after the snapshot with this number.
+  Mark(usize),
+  /// `other` was merged into `leader`, which had `nodes` nodes and `data`.
+  Merge {
+    leader: Id<D>,
+    other: Id<D>,
+    nodes: usize,
+    data: D,
+  },
+  /// Nodes of an eclass before duplicates were removed.
+  Nodes(Id<D>, Vec<ENode<D>>),
+  /// Data of an eclass before it changed.
+  Data(Id<D>, D),
+  /// Uses of an op before they were canonicalized.
+  Uses(EOpHand<D>, Vec<Id<D>>),
+  /// `EGraph::eclasses` before it was collected.
+  Classes(Vec<Id<D>>),
+}
+
+impl<D> fmt::Debug for Undo<D> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Undo::Mark(mark) => write!(f, "Mark({mark})"),
+      Undo::Merge { leader, other, .. } => write!(f, "Merge({leader:?}, {other:?})"),
+      Undo::Nodes(id, nodes) => write!(f, "Nodes({id:?}, {})", nodes.len()),
+      Undo::Data(id, _) => write!(f, "Data({id:?})"),
+      Undo::Uses(_, uses) => write!(f, "Uses({uses:?})"),
+      Undo::Classes(ids) => write!(f, "Classes({})", ids.len()),
+    }
+  }
+}
+
+impl<D> Undo<D> {
+  fn undo(self, egraph: &mut EGraph<D>) {
+    match self {
+      Undo::Mark(_) => {},
+      Undo::Merge {
+        leader,
+        other,
+        nodes,
+        data,
+      } => {
+        let mut moved = {
+          let mut leader = leader.as_ref().borrow_mut();
+          leader.data = data;
+          leader.nodes.split_off(nodes)
+        };
+        for node in moved.iter_mut() {
+          node.eclass = Rc::downgrade(&other.0);
+        }
+        let mut other = other.as_ref().borrow_mut();
+        other.nodes = moved;
+        other.leader = None;
+      },
+      Undo::Nodes(id, nodes) => id.as_ref().borrow_mut().nodes = nodes,
+      Undo::Data(id, data) => id.as_ref().borrow_mut().data = data,
+      Undo::Uses(op, uses) => op.as_ref().borrow_mut().uses = uses,
+      Undo::Classes(ids) => egraph.eclasses = ids,
+    }
+  }
+}
+
+/// State of an egraph to roll back to, see `EGraph::snapshot`.
+#[derive(Debug)]
+pub struct Snapshot<D> {
+  mark: usize,
+  log: usize,
+  eclasses: usize,
+  added: usize,
+  root: Vec<Id<D>>,
+  pending: Vec<Id<D>>,
+  touched: Vec<Id<D>>,
+  /// Enodes and unions of the proof forest.
+  explain: Option<(usize, usize)>,
+}
+
+impl<D> EGraph<D> {
+  /// Remember the current state, the changes made from now on are logged
+  /// until `rollback` or `commit`.
+  ///
+  /// `Analysis::modify` must change the egraph through its methods for the
+  /// changes to be undone.
+  pub fn snapshot(&mut self) -> Snapshot<D> {
+    let mark = self.marks;
+    self.marks += 1;
+    let log = self.log.get_or_insert_with(Vec::new);
+    log.push(Undo::Mark(mark));
+    Snapshot {
+      mark,
+      log: log.len() - 1,
+      eclasses: self.eclasses.len(),
+      added: self.added,
+      root: self.root.clone(),
+      pending: self.pending.clone(),
+      touched: self.touched.clone(),
+      explain: self.explain.as_ref().map(|explain| explain.len()),
+    }
+  }
+
+  /// Undo every change made since `snapshot` was taken, the snapshots taken
+  /// after it are dropped.
+  ///
+  /// Panics if an earlier snapshot was rolled back or the changes were
+  /// committed since.
+  pub fn rollback(&mut self, snapshot: Snapshot<D>) {
+    let log = self.log.as_mut().expect("no snapshot to roll back to");
+    assert!(
+      matches!(log.get(snapshot.log), Some(Undo::Mark(mark)) if *mark == snapshot.mark),
+      "the snapshot was rolled back or committed"
+    );
+    let undos = log.split_off(snapshot.log);
+    for undo in undos.into_iter().rev() {
+      undo.undo(self);
+    }
+    self.eclasses.truncate(snapshot.eclasses);
+    self.added = snapshot.added;
+    self.root = snapshot.root;
+    self.pending = snapshot.pending;
+    self.touched = snapshot.touched;
+    if let (Some(explain), Some((nodes, unions))) = (self.explain.as_mut(), snapshot.explain) {
+      explain.truncate(nodes, unions);
+    }
+    self.rebuild_hashcons();
+  }
+
+  /// Keep the changes made since every snapshot and stop logging them, the
+  /// snapshots can no longer be rolled back to.
+  pub fn commit(&mut self) {
+    self.log = None;
+  }
+
+  /// Log `undo` if a snapshot is taken.
+  pub(crate) fn record(&mut self, undo: impl FnOnce() -> Undo<D>) {
+    if let Some(log) = self.log.as_mut() {
+      log.push(undo());
+    }
+  }
+
+  /// Point the uses of `node` at their canonical eclasses, logged.
+  pub(crate) fn canonicalize(&mut self, node: &RawENode<D>) {
+    if let RawENode::Use(op, _) = node {
+      let uses = &op.as_ref().borrow().uses;
+      if uses.iter().any(|id| !id.is_canonical()) {
+        let uses = uses.clone();
+        self.record(|| Undo::Uses(op.clone(), uses));
+      }
+    }
+    node.canonicalize();
+  }
+}
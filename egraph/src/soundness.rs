@@ -0,0 +1,560 @@
+use std::{collections::HashMap, fmt};
+
+use cfir::{
+  rewriter::{
+    pattern::{Bindings, Catch, OpPat, OpPatHand, OpcodePat, ValuePat},
+    rule::{Cond, Operand, RuleLhs, RuleRhs},
+  },
+  symbol::{Name, Symbol},
+  types::signature,
+  value::Constant,
+};
+
+use crate::{
+  const_fold::{fold, value, ConstFold, IntType},
+  egraph::EGraph,
+  enode::{ENode, RawENode},
+  matching::MatchRecord,
+  rewriter::Rewriter,
+  rule::Rule,
+};
+
+/// Opcodes of `arthi` the reference evaluator knows, by arity.
+const UNARY: [&str; 1] = ["not"];
+const BINARY: [&str; 16] = [
+  "add", "sub", "mul", "div", "rem", "and", "or", "xor", "shl", "shr", "eq", "ne", "lt", "le",
+  "gt", "ge",
+];
+
+/// Checks rules by evaluating both sides on random constants, the constant
+/// folder of `arthi` is the reference evaluator.
+///
+/// Each sample binds the variables of the lhs to constants of their type,
+/// from the sign of the op using them or else `int` and `uint` in turn, and
+/// the opcode variables and wildcards to the opcodes the evaluator knows.
+/// A variable a guard compares with a constant is bound to it half the time.
+/// Samples whose lhs can't be evaluated or whose guards fail are skipped, a
+/// rule with fewer than `min_checked` samples left is not checked.
+#[derive(Debug, Clone)]
+pub struct Checker {
+  pub samples: usize,
+  pub min_checked: usize,
+  pub seed: u64,
+}
+
+impl Default for Checker {
+  fn default() -> Self {
+    Checker {
+      samples: 200,
+      min_checked: 10,
+      seed: 0x5eed,
+    }
+  }
+}
+
+/// Result of checking one rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleCheck {
+  pub rule: Symbol,
+  /// Samples evaluated on both sides.
+  pub checked: usize,
+  /// Samples needed to take the rule as checked.
+  pub required: usize,
+  /// First sample the sides differ on.
+  pub counterexample: Option<Counterexample>,
+}
+
+impl RuleCheck {
+  /// Whether enough samples were checked and none was a counterexample, a
+  /// rule the evaluator can't run is not taken as sound.
+  pub fn is_sound(&self) -> bool {
+    self.checked >= self.required.max(1) && self.counterexample.is_none()
+  }
+}
+
+/// Constants and opcodes the two sides of a rule differ on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counterexample {
+  /// Variables of the lhs, in order.
+  pub vars: Vec<(Symbol, Constant)>,
+  pub opcodes: Vec<(Symbol, Name)>,
+  pub lhs: Constant,
+  pub rhs: Constant,
+}
+
+impl fmt::Display for Counterexample {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let vars = self
+      .opcodes
+      .iter()
+      .map(|(sym, opcode)| format!("?{} = {opcode}", sym.0))
+      .chain(
+        self
+          .vars
+          .iter()
+          .map(|(sym, c)| format!("?{} = {}", sym.0, show(c))),
+      )
+      .collect::<Vec<_>>();
+    write!(
+      f,
+      "{}: lhs is {}, rhs is {}",
+      vars.join(", "),
+      show(&self.lhs),
+      show(&self.rhs)
+    )
+  }
+}
+
+impl fmt::Display for RuleCheck {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match (&self.counterexample, self.checked) {
+      (Some(counterexample), _) => write!(f, "{}: unsound, {counterexample}", self.rule.0),
+      (None, 0) => write!(
+        f,
+        "{}: not checked, no sample could be evaluated",
+        self.rule.0
+      ),
+      (None, n) if n < self.required => write!(
+        f,
+        "{}: not checked, only {n} of {} samples could be evaluated",
+        self.rule.0, self.required
+      ),
+      (None, n) => write!(f, "{}: ok, {n} samples", self.rule.0),
+    }
+  }
+}
+
+fn show(c: &Constant) -> String {
+  match c {
+    Constant::Uint(u) => format!("{u}u"),
+    c => value(c).map_or_else(|| format!("{c:?}"), |v| v.to_string()),
+  }
+}
+
+/// splitmix64, the samples are the same on every run with a seed.
+struct Random(u64);
+
+impl Random {
+  fn next(&mut self) -> u64 {
+    self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+    match items.len() {
+      0 => None,
+      n => items.get(self.next() as usize % n),
+    }
+  }
+}
+
+/// One instance of a rule.
+struct Sample<'a> {
+  random: &'a mut Random,
+  /// Constants of the rule and their neighbours, tried more often.
+  interesting: &'a [i128],
+  /// Constants a guard compares each variable with, bound half the time.
+  compared: &'a HashMap<Symbol, Vec<i128>>,
+  /// Type of the variables without one in a sign.
+  default: IntType,
+  types: HashMap<Symbol, IntType>,
+  bindings: Bindings,
+  vars: Vec<(Symbol, Constant)>,
+}
+
+impl Sample<'_> {
+  /// `pat` with a concrete opcode for every op, the opcode variables bound.
+  fn concrete(&mut self, pat: &ValuePat) -> Option<ValuePat> {
+    let ValuePat::Use(op, offset) = pat else {
+      return Some(pat.clone());
+    };
+    let op = op.as_ref().borrow();
+    let Catch(opcode, sym) = &op.0;
+    let bound = sym.as_ref().and_then(|sym| self.bindings.opcodes.get(sym));
+    let name = match (bound, opcode) {
+      (Some(name), _) => name.clone(),
+      (None, Some(OpcodePat::Name(name))) => name.clone(),
+      (None, opcode) => {
+        let ops: &[&str] = match op.1.len() {
+          1 => &UNARY,
+          2 => &BINARY,
+          _ => &[],
+        };
+        let dialect = Symbol::new("arthi");
+        let name = Name(Some(dialect), Symbol::new(self.random.pick(ops)?));
+        if opcode.as_ref().is_some_and(|pat| !pat.accepts(&name)) {
+          return None;
+        }
+        name
+      },
+    };
+    if let Some(sym) = sym {
+      self.bindings.opcodes.insert(sym.clone(), name.clone());
+    }
+    let params = op
+      .3
+      .sign
+      .iter()
+      .flatten()
+      .map(|ty| ty.build_type(&Bindings::default()))
+      .collect::<Option<Vec<_>>>()
+      .unwrap_or_default();
+    let params = signature(&params).0;
+    let uses = op
+      .1
+      .iter()
+      .enumerate()
+      .map(|(k, Catch(pat, sym))| {
+        if let (None, Some(sym)) = (pat, sym) {
+          if let Some(ty) = params.get(k).and_then(IntType::from_type) {
+            self.types.entry(sym.clone()).or_insert(ty);
+          }
+        }
+        let pat = match pat {
+          Some(pat) => Some(self.concrete(pat)?),
+          None => None,
+        };
+        Some(Catch(pat, sym.clone()))
+      })
+      .collect::<Option<Vec<_>>>()?;
+    let opcode = Catch(Some(OpcodePat::Name(name)), None);
+    let op = OpPat(opcode, uses, op.2.clone(), op.3.clone());
+    Some(ValuePat::Use(OpPatHand::new(op), *offset))
+  }
+
+  /// Bind the variables of `pat` not bound yet to constants.
+  fn bind(
+    &mut self,
+    pat: &ValuePat,
+    record: &mut MatchRecord<ConstFold>,
+    egraph: &mut EGraph<ConstFold>,
+  ) {
+    let ValuePat::Use(op, _) = pat else {
+      return;
+    };
+    for Catch(pat, sym) in &op.as_ref().borrow().1 {
+      match (pat, sym) {
+        (Some(pat), _) => self.bind(pat, record, egraph),
+        (None, Some(sym)) if !record.contains_key(sym) => {
+          let ty = self.types.get(sym).copied().unwrap_or(self.default);
+          let v = match self.compared.get(sym) {
+            Some(vs) if self.random.next() & 1 == 0 => *self.random.pick(vs).unwrap(),
+            _ => self.constant(),
+          };
+          let c = ty.constant(v);
+          let (_, node) = egraph.add_raw_node(RawENode::Const(c.clone()));
+          self.vars.push((sym.clone(), c));
+          record.insert(sym.clone(), node);
+        },
+        _ => {},
+      }
+    }
+  }
+
+  fn constant(&mut self) -> i128 {
+    let edges = [
+      0,
+      1,
+      2,
+      -1,
+      i64::MIN as i128,
+      i64::MAX as i128,
+      u64::MAX as i128,
+    ];
+    match self.random.next() % 4 {
+      0 => *self.random.pick(&edges).unwrap(),
+      1 => self.random.pick(self.interesting).copied().unwrap_or(0),
+      // small values make guards like `?c == 2` hold now and then
+      2 => (self.random.next() % 64) as i128 - 32,
+      _ => self.random.next() as i128,
+    }
+  }
+}
+
+/// Constant of the eclass of `node`.
+fn eval(node: &ENode<ConstFold>) -> Option<Constant> {
+  node.get_id().get_data().0
+}
+
+/// Type of the term of `node`, from its sign or else its constant.
+fn type_of(node: &ENode<ConstFold>) -> Option<IntType> {
+  node
+    .body
+    .get_type()
+    .as_ref()
+    .and_then(IntType::from_type)
+    .or_else(|| IntType::of(&eval(node)?))
+}
+
+/// Values of `lhs` and `rhs` in the type of `lhs`.
+fn sides(lhs: &ENode<ConstFold>, rhs: &ENode<ConstFold>) -> Option<(Constant, Constant)> {
+  let ty = type_of(lhs)?;
+  Some((
+    ty.constant(value(&eval(lhs)?)?),
+    ty.constant(value(&eval(rhs)?)?),
+  ))
+}
+
+/// Constants in `cond`, and the variable `?x == c` compares with `c`.
+fn cond_constants(cond: &Cond, out: &mut Vec<i128>, compared: &mut HashMap<Symbol, Vec<i128>>) {
+  let operands = match cond {
+    Cond::Eq(l, r) | Cond::Ne(l, r) => vec![l, r],
+    Cond::Call(_, args) => args.iter().collect(),
+  };
+  for operand in &operands {
+    if let Operand::Const(c) = operand {
+      out.extend(value(c));
+    }
+  }
+  if let Cond::Eq(Operand::Var(sym), Operand::Const(c))
+  | Cond::Eq(Operand::Const(c), Operand::Var(sym)) = cond
+  {
+    if let Some(v) = value(c) {
+      compared.entry(sym.clone()).or_default().push(v);
+    }
+  }
+}
+
+/// Constants in `pat`.
+fn constants(pat: &ValuePat, out: &mut Vec<i128>) {
+  match pat {
+    ValuePat::Const(c) => out.extend(value(c)),
+    ValuePat::Use(op, _) => {
+      for pat in op.as_ref().borrow().1.iter().flat_map(|catch| &catch.0) {
+        constants(pat, out);
+      }
+    },
+    _ => {},
+  }
+}
+
+impl Checker {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn with_samples(mut self, samples: usize) -> Self {
+    self.samples = samples;
+    self
+  }
+
+  pub fn with_min_checked(mut self, min_checked: usize) -> Self {
+    self.min_checked = min_checked;
+    self
+  }
+
+  pub fn with_seed(mut self, seed: u64) -> Self {
+    self.seed = seed;
+    self
+  }
+
+  pub fn check(&self, rules: &[Rule<ConstFold>]) -> Vec<RuleCheck> {
+    rules.iter().map(|rule| self.check_rule(rule)).collect()
+  }
+
+  pub fn check_rule(&self, rule: &Rule<ConstFold>) -> RuleCheck {
+    let lhs = match &rule.lhs {
+      RuleLhs::Op(op) => vec![Catch(
+        Some(ValuePat::Use(OpPatHand::new(op.clone()), 0)),
+        None,
+      )],
+      RuleLhs::Multi(pats) => pats.clone(),
+    };
+    let mut interesting = vec![];
+    // the rhs of a dynamic rule is only known from its applier
+    let rhs = match &rule.rhs {
+      Some(RuleRhs::Value(catch)) => std::slice::from_ref(catch),
+      Some(RuleRhs::Multi(catches)) => catches.as_slice(),
+      None => &[],
+    };
+    for pat in lhs.iter().chain(rhs).flat_map(|catch| &catch.0) {
+      constants(pat, &mut interesting);
+    }
+    let mut compared = HashMap::new();
+    for cond in &rule.conds {
+      cond_constants(cond, &mut interesting, &mut compared);
+    }
+    interesting.extend(interesting.clone().iter().flat_map(|c| [c - 1, c + 1]));
+
+    let mut random = Random(self.seed);
+    let mut check = RuleCheck {
+      rule: rule.name.clone(),
+      checked: 0,
+      required: self.min_checked,
+      counterexample: None,
+    };
+    for i in 0..self.samples {
+      let mut sample = Sample {
+        random: &mut random,
+        interesting: &interesting,
+        compared: &compared,
+        default: match i % 2 {
+          0 => IntType::Int(64),
+          _ => IntType::Uint(64),
+        },
+        types: HashMap::new(),
+        bindings: Bindings::default(),
+        vars: vec![],
+      };
+      let Some(sides) = sample.run(rule, &lhs) else {
+        continue;
+      };
+      check.checked += 1;
+      if let Some((lhs, rhs)) = sides.into_iter().find(|(l, r)| l != r) {
+        let mut opcodes = sample.bindings.opcodes.into_iter().collect::<Vec<_>>();
+        opcodes.sort_by(|l, r| l.0.cmp(&r.0));
+        check.counterexample = Some(Counterexample {
+          vars: sample.vars,
+          opcodes,
+          lhs,
+          rhs,
+        });
+        break;
+      }
+    }
+    check
+  }
+}
+
+impl Sample<'_> {
+  /// Values of the sides of `rule` the sample is checked on, `None` if it
+  /// can't be evaluated or a guard fails.
+  fn run(
+    &mut self,
+    rule: &Rule<ConstFold>,
+    lhs: &[Catch<ValuePat>],
+  ) -> Option<Vec<(Constant, Constant)>> {
+    let mut egraph = EGraph::new();
+    let mut record = MatchRecord::new();
+    let lhs = lhs
+      .iter()
+      .map(|Catch(pat, sym)| Some(Catch(Some(self.concrete(pat.as_ref()?)?), sym.clone())))
+      .collect::<Option<Vec<_>>>()?;
+    record.bindings = self.bindings.clone();
+    for pat in lhs.iter().flat_map(|catch| &catch.0) {
+      self.bind(pat, &mut record, &mut egraph);
+    }
+    let roots = lhs
+      .iter()
+      .map(|catch| catch.rewrite(&mut record, &mut egraph))
+      .collect::<Option<Vec<_>>>()?;
+    egraph.rebuild();
+    if !rule.check(&egraph, &record) {
+      return None;
+    }
+    let mut sides = vec![];
+    if let Some(applier) = &rule.applier {
+      let new = applier.apply(&mut record, &mut egraph)?;
+      egraph.rebuild();
+      sides.push(self::sides(&roots[0], &new)?);
+      return Some(sides);
+    }
+    // the rhs is evaluated on its own, a bound `?x:pat` is a claim that
+    // `pat` equals `?x` and is checked as another side
+    let mut values = HashMap::new();
+    for (sym, node) in record.iter() {
+      if let Some(c) = eval(node) {
+        values.insert(sym.clone(), c);
+      }
+    }
+    match rule.rhs.as_ref()? {
+      RuleRhs::Value(rhs) => {
+        let ty = type_of(&roots[0])?;
+        let l = ty.constant(value(&eval(&roots[0])?)?);
+        let r = ty.constant(value(&self.eval(rhs, ty, &mut values, &mut sides)?)?);
+        sides.insert(0, (l, r));
+      },
+      RuleRhs::Multi(rhs) => {
+        for catch in rhs {
+          let ty = type_of(record.get(catch.1.as_ref()?)?)?;
+          self.eval(catch, ty, &mut values, &mut sides)?;
+        }
+      },
+    }
+    (!sides.is_empty()).then_some(sides)
+  }
+
+  /// Value of the rhs term `catch` in place of a term of type `ty`, the
+  /// literals and the ops without a sign take this type. The bound catches
+  /// push the values they claim equal to `claims`.
+  fn eval(
+    &self,
+    catch: &Catch<ValuePat>,
+    ty: IntType,
+    values: &mut HashMap<Symbol, Constant>,
+    claims: &mut Vec<(Constant, Constant)>,
+  ) -> Option<Constant> {
+    let Catch(pat, sym) = catch;
+    let Some(pat) = pat else {
+      return values.get(sym.as_ref()?).cloned();
+    };
+    let c = match pat {
+      ValuePat::Const(c) => ty.constant(value(c)?),
+      ValuePat::Use(op, 0) => self.eval_op(&op.as_ref().borrow(), ty, values, claims)?,
+      _ => return None,
+    };
+    if let Some(sym) = sym {
+      match values.get(sym) {
+        Some(bound) => claims.push((ty.constant(value(bound)?), c.clone())),
+        None => {
+          values.insert(sym.clone(), c.clone());
+        },
+      }
+    }
+    Some(c)
+  }
+
+  fn eval_op(
+    &self,
+    op: &OpPat,
+    ty: IntType,
+    values: &mut HashMap<Symbol, Constant>,
+    claims: &mut Vec<(Constant, Constant)>,
+  ) -> Option<Constant> {
+    let name = match &op.0 {
+      Catch(Some(OpcodePat::Name(name)), _) => name.clone(),
+      Catch(_, Some(sym)) => self.bindings.opcodes.get(sym)?.clone(),
+      Catch(..) => return None,
+    };
+    let Name(Some(dialect), opcode) = &name else {
+      return None;
+    };
+    if dialect.0.as_str() != "arthi" {
+      return None;
+    }
+    let compare = matches!(opcode.0.as_str(), "eq" | "ne" | "lt" | "le" | "gt" | "ge");
+    let sign = op
+      .3
+      .sign
+      .iter()
+      .flatten()
+      .map(|ty| ty.build_type(&self.bindings))
+      .collect::<Option<Vec<_>>>()
+      .unwrap_or_default();
+    let (params, rets) = signature(&sign);
+    // the operands of a comparison have the type of its first variable
+    let operand = op.1.iter().find_map(|catch| match catch {
+      Catch(None, Some(sym)) => Some(self.types.get(sym).copied().unwrap_or(self.default)),
+      _ => None,
+    });
+    let arg_ty = match params.first().and_then(IntType::from_type) {
+      Some(arg_ty) => arg_ty,
+      None if compare => operand.unwrap_or(self.default),
+      None => ty,
+    };
+    let ret = match rets.first().and_then(IntType::from_type) {
+      Some(ret) => ret,
+      None if compare => IntType::Bool,
+      None => arg_ty,
+    };
+    let args = op
+      .1
+      .iter()
+      .map(|catch| self.eval(catch, arg_ty, values, claims))
+      .collect::<Option<Vec<_>>>()?;
+    fold(opcode.0.as_str(), &args, arg_ty, ret)
+  }
+}
@@ -1,4 +1,73 @@
-fn main() {}
+use std::process::ExitCode;
+
+use cfir_frontend::rule_parser::parse_rules;
+use egraph::{const_fold::ConstFold, rule::Rule, soundness::Checker};
+
+const USAGE: &str = "usage: cfvm check [--samples N] [--min-checked N] [--seed N] RULE-FILE...";
+
+fn main() -> ExitCode {
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  match args.split_first() {
+    Some((command, args)) if command == "check" => match check(args) {
+      Ok((report, sound)) => {
+        print!("{report}");
+        ExitCode::from(!sound as u8)
+      },
+      Err(error) => {
+        eprintln!("{error}");
+        ExitCode::from(2)
+      },
+    },
+    _ => {
+      eprintln!("{USAGE}");
+      ExitCode::from(2)
+    },
+  }
+}
+
+/// Check the rules of each file by randomized evaluation, returns the report
+/// and whether every rule is sound, a rule that could not be checked is not.
+fn check(args: &[String]) -> Result<(String, bool), String> {
+  let mut checker = Checker::new();
+  let mut paths = vec![];
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    let mut number = |name: &str| {
+      args
+        .next()
+        .and_then(|n| n.parse().ok())
+        .ok_or(format!("{name} takes a number\n{USAGE}"))
+    };
+    match arg.as_str() {
+      "--samples" => checker.samples = number("--samples")? as usize,
+      "--min-checked" => checker.min_checked = number("--min-checked")? as usize,
+      "--seed" => checker.seed = number("--seed")?,
+      path => paths.push(path),
+    }
+  }
+  if paths.is_empty() {
+    return Err(USAGE.to_string());
+  }
+  let mut report = String::new();
+  let mut sound = true;
+  for path in paths {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let groups = parse_rules(&src, path).map_err(|diagnostics| {
+      diagnostics
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+    })?;
+    let rules: Vec<Rule<ConstFold>> =
+      Rule::from_groups(&groups).map_err(|e| format!("{path}: {e}"))?;
+    for result in checker.check(&rules) {
+      sound &= result.is_sound();
+      report += &format!("{path}: {result}\n");
+    }
+  }
+  Ok((report, sound))
+}
 
 #[test]
 fn matching_test() {
@@ -13,6 +82,46 @@ fn matching_test() {
 
   let r = egg.matching_op(op_pat);
   println!("op_pat: {:?}", r);
+  assert_eq!(r.len(), 1);
+}
+
+#[test]
+fn non_linear_matching_test() {
+  use cfir::{symbol::Symbol, value::Constant, value::Value};
+  use cfir_frontend::{cfir_expr, pat};
+  use egraph::egraph::EGraph;
+
+  let mut egg: EGraph<()> = EGraph::new();
+  let (root, _) = egg.add_op(&cfir_expr!(
+    "add(add(a, 1): (int, int) -> int, 1): (int, int) -> int"
+  ));
+  // ?b is bound twice to different eclasses
+  egg.add_op(&cfir_expr!(
+    "add(add(a, 1): (int, int) -> int, 2): (int, int) -> int"
+  ));
+  // the inner opcode differs
+  egg.add_op(&cfir_expr!(
+    "add(sub(a, 1): (int, int) -> int, 1): (int, int) -> int"
+  ));
+
+  let r = egg.matching_op(pat!("add(add(?a, ?b), ?b)"));
+  assert_eq!(r.len(), 1);
+  let (node, record) = &r[0];
+  assert_eq!(node.get_id(), root);
+  let (_, a) = egg.add_value(&Value::Input(Symbol::new("a")));
+  let (_, one) = egg.add_value(&Value::Const(Constant::Int(1)));
+  assert_eq!(record[&Symbol::new("a")].get_id(), a);
+  assert_eq!(record[&Symbol::new("b")].get_id(), one);
+
+  assert_eq!(egg.matching_op(pat!("add(?x, ?x)")).len(), 0);
+  assert_eq!(egg.matching_op(pat!("add(_, 2)")).len(), 1);
+
+  // after 2 == 1 both operands of the second add are in the same eclass
+  let (_, two) = egg.add_value(&Value::Const(Constant::Int(2)));
+  egg.union(&one, &two);
+  egg.rebuild();
+  assert_eq!(egg.matching_op(pat!("add(add(?a, ?b), ?b)")).len(), 1);
+  assert_eq!(egg.matching_op(pat!("add(?x:add(?a, ?b), ?b)")).len(), 1);
 }
 
 #[test]
@@ -35,3 +144,1643 @@ fn relinking_test() {
     Value::Use(relinked_ops[0].clone(), 0)
   );
 }
+
+#[test]
+fn analysis_test() {
+  use cfir::{symbol::Symbol, value::Value};
+  use cfir_frontend::cfir_expr;
+  use egraph::{analysis::Analysis, egraph::EGraph, enode::RawENode};
+
+  /// Depth of the shallowest term in an eclass.
+  #[derive(Debug, Clone, PartialEq)]
+  struct Depth(usize);
+
+  impl Analysis for Depth {
+    fn make(_egraph: &EGraph<Self>, enode: &RawENode<Self>) -> Self {
+      let children = enode.children();
+      let depth = children.iter().map(|id| id.get_data().0 + 1).max();
+      Depth(depth.unwrap_or(0))
+    }
+
+    fn merge(&mut self, other: Self) -> bool {
+      let changed = other.0 < self.0;
+      self.0 = self.0.min(other.0);
+      changed
+    }
+  }
+
+  let mut egg: EGraph<Depth> = EGraph::new();
+  let (lhs, _) = egg.add_op(&cfir_expr!(
+    "add(add(a, 1): (int, int) -> int, 1): (int, int) -> int"
+  ));
+  let (rhs, _) = egg.add_op(&cfir_expr!(
+    "add(add(b, 1): (int, int) -> int, 1): (int, int) -> int"
+  ));
+  assert_eq!(lhs.get_data(), Depth(2));
+  assert_ne!(lhs, rhs);
+
+  let (_, a) = egg.add_value(&Value::Input(Symbol::new("a")));
+  let (_, b) = egg.add_value(&Value::Input(Symbol::new("b")));
+  egg.union(&a, &b);
+  // add(a, 1) == add(b, 1), then the outer adds by congruence
+  assert_eq!(egg.rebuild(), 2);
+  assert_eq!(lhs.find(), rhs.find());
+
+  egg.union(&lhs, &a);
+  egg.rebuild();
+  assert_eq!(lhs.get_data(), Depth(0));
+}
+
+#[test]
+fn const_fold_test() {
+  use cfir::{symbol::Symbol, value::Constant, value::Value};
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{const_fold::ConstFold, egraph::EGraph, enode::RawENode, rule::Rule};
+
+  let mut egg: EGraph<ConstFold> = EGraph::new();
+  let mut fold = |src| {
+    let (id, _) = egg.add_op(&src);
+    egg.rebuild();
+    id.get_data().0
+  };
+  assert_eq!(
+    fold(cfir_expr!(
+      "arthi.add(arthi.mul(2, 3): (int, int) -> int, 1): (int, int) -> int"
+    )),
+    Some(Constant::Int(7))
+  );
+  assert_eq!(
+    fold(cfir_expr!("arthi.add(127, 1): (i8, i8) -> i8")),
+    Some(Constant::Int(-128))
+  );
+  assert_eq!(
+    fold(cfir_expr!("arthi.sub(0u, 1u): (u8, u8) -> u8")),
+    Some(Constant::Uint(255))
+  );
+  assert_eq!(
+    fold(cfir_expr!("arthi.shr(-8, 1): (i32, i32) -> i32")),
+    Some(Constant::Int(-4))
+  );
+  assert_eq!(
+    fold(cfir_expr!("arthi.shr(-8, 1): (u32, u32) -> u32")),
+    Some(Constant::Uint(0x7fff_fffc))
+  );
+  assert_eq!(
+    fold(cfir_expr!("arthi.lt(-1, 1): (int, int) -> bool")),
+    Some(Constant::Bool(true))
+  );
+  assert_eq!(
+    fold(cfir_expr!("arthi.lt(-1, 1): (uint, uint) -> bool")),
+    Some(Constant::Bool(false))
+  );
+  assert_eq!(
+    fold(cfir_expr!("arthi.shl(1, 64): (int, int) -> int")),
+    None
+  );
+  assert_eq!(fold(cfir_expr!("arthi.add(1, 2): (f64, f64) -> f64")), None);
+
+  let (id, _) = egg.add_op(&cfir_expr!("arthi.add(a, 1): (int, int) -> int"));
+  assert_eq!(id.get_data(), ConstFold(None));
+  let (_, a) = egg.add_value(&Value::Input(Symbol::new("a")));
+  let (_, c) = egg.add_value(&Value::Const(Constant::Int(41)));
+  egg.union(&a, &c);
+  egg.rebuild();
+  assert_eq!(id.get_data(), ConstFold(Some(Constant::Int(42))));
+  assert!(id
+    .find()
+    .as_ref()
+    .borrow()
+    .nodes
+    .iter()
+    .any(|node| node.body == RawENode::Const(Constant::Int(42))));
+
+  // the fold `5u` joins the literal `5`
+  let (id, _) = egg.add_op(&cfir_expr!("arthi.add(5, 0): (u32, u32) -> u32"));
+  let rule = Rule::new("add-zero", pat!("arthi.add(?x, 0)"), catch!("?x"));
+  egg.saturate(&[rule], 8);
+  assert_eq!(id.get_data(), ConstFold(Some(Constant::Uint(5))));
+}
+
+#[test]
+fn guard_test() {
+  use cfir::symbol::{Name, Symbol};
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{const_fold::IntType, eclass::Id, egraph::EGraph, enode::RawENode, rule::Rule};
+
+  // x / 2 => x >> 1 holds for unsigned x only
+  let rule = Rule::new(
+    "udiv-pow2",
+    pat!("arthi.div(?x, 2)"),
+    catch!("arthi.shr(?x, 1)"),
+  )
+  .with_guard(|_, record| {
+    let ty = record[&Symbol::new("x")].body.get_type();
+    matches!(
+      ty.as_ref().and_then(IntType::from_type),
+      Some(IntType::Uint(_))
+    )
+  });
+
+  let mut egg: EGraph<()> = EGraph::new();
+  let (unsigned, _) = egg.add_op(&cfir_expr!(
+    "arthi.div(arthi.add(a, b): (u32, u32) -> u32, 2): (u32, u32) -> u32"
+  ));
+  let (signed, _) = egg.add_op(&cfir_expr!(
+    "arthi.div(arthi.add(a, b): (i32, i32) -> i32, 2): (i32, i32) -> i32"
+  ));
+  egg.saturate(&[rule], 8);
+
+  let shr = Name(Some(Symbol::new("arthi")), Symbol::new("shr"));
+  let has_shr = |id: &Id<()>| {
+    id.find()
+      .as_ref()
+      .borrow()
+      .nodes
+      .iter()
+      .any(|node| match &node.body {
+        RawENode::Use(op, _) => op.as_ref().borrow().opcode == shr,
+        _ => false,
+      })
+  };
+  assert!(has_shr(&unsigned));
+  assert!(!has_shr(&signed));
+}
+
+#[test]
+fn rhs_catch_test() {
+  use cfir::{symbol::Symbol, value::Value};
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{egraph::EGraph, enode::RawENode, rule::Rule};
+
+  let square = Rule::new(
+    "square",
+    pat!("arthi.sq(?x)"),
+    catch!("arthi.mul(?s:arthi.add(?x, 1), ?s)"),
+  );
+  // ?x is bound by the lhs, so the built term is unioned with it
+  let mul_one = Rule::new(
+    "mul-one",
+    pat!("arthi.add(?x, 0)"),
+    catch!("?x:arthi.mul(?x, 1)"),
+  );
+
+  let mut egg: EGraph<()> = EGraph::new();
+  let (sq, _) = egg.add_op(&cfir_expr!("arthi.sq(a): (int) -> int"));
+  let (add, _) = egg.add_op(&cfir_expr!("arthi.add(a, 0): (int, int) -> int"));
+  egg.saturate(&[square, mul_one], 4);
+
+  let shared = sq
+    .find()
+    .as_ref()
+    .borrow()
+    .nodes
+    .iter()
+    .any(|node| match &node.body {
+      RawENode::Use(op, _) => {
+        let op = op.as_ref().borrow();
+        op.uses.len() == 2 && op.uses[0] == op.uses[1]
+      },
+      _ => false,
+    });
+  assert!(shared);
+
+  let (_, a) = egg.add_value(&Value::Input(Symbol::new("a")));
+  let muls = egg.matching_op(pat!("arthi.mul(?x, 1)"));
+  assert_eq!(muls.len(), 1);
+  assert!(muls[0].0.get_id() == a);
+  assert!(add.find() == a);
+}
+
+#[test]
+fn multi_pattern_test() {
+  use cfir::symbol::Symbol;
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{egraph::EGraph, rule::Rule};
+
+  // both quotient and remainder come from one divrem
+  let divrem = Rule::multi(
+    "divrem",
+    vec![
+      catch!("?q:arthi.div(?a, ?b)"),
+      catch!("?r:arthi.rem(?a, ?b)"),
+    ],
+    vec![
+      catch!("?q:arthi.fst(?d:arthi.divrem(?a, ?b))"),
+      catch!("?r:arthi.snd(?d)"),
+    ],
+  )
+  .unwrap();
+
+  let mut egg: EGraph<()> = EGraph::new();
+  let (div, _) = egg.add_op(&cfir_expr!("arthi.div(a, b): (int, int) -> int"));
+  let (rem, _) = egg.add_op(&cfir_expr!("arthi.rem(a, b): (int, int) -> int"));
+  let (other, _) = egg.add_op(&cfir_expr!("arthi.rem(a, c): (int, int) -> int"));
+  egg.saturate(&[divrem], 4);
+
+  // the variables are shared, so `rem(a, c)` does not match with `div(a, b)`
+  let fst = egg.matching_op(pat!("arthi.fst(?d)"));
+  let snd = egg.matching_op(pat!("arthi.snd(?d)"));
+  assert_eq!(fst.len(), 1);
+  assert_eq!(snd.len(), 1);
+  assert!(fst[0].0.get_id() == div.find());
+  assert!(snd[0].0.get_id() == rem.find());
+  assert!(other.find() != rem.find());
+  assert_eq!(egg.matching_op(pat!("arthi.divrem(?a, ?b)")).len(), 1);
+  let d = Symbol::new("d");
+  assert!(fst[0].1[&d].get_id() == snd[0].1[&d].get_id());
+}
+
+#[test]
+fn backoff_scheduler_test() {
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{
+    egraph::EGraph,
+    rule::Rule,
+    runner::{BackoffScheduler, Runner, Scheduler, StopReason},
+  };
+
+  let rules = || {
+    vec![
+      Rule::new(
+        "add-comm",
+        pat!("arthi.add(?a, ?b)"),
+        catch!("arthi.add(?b, ?a)"),
+      ),
+      Rule::new(
+        "add-assoc",
+        pat!("arthi.add(?a, arthi.add(?b, ?c))"),
+        catch!("arthi.add(arthi.add(?a, ?b), ?c)"),
+      ),
+    ]
+  };
+  let expr = || {
+    cfir_expr!(
+      "arthi.add(a, arthi.add(b, arthi.add(c, d): (int, int) -> int): (int, int) -> int): (int, int) -> int"
+    )
+  };
+
+  let mut simple: EGraph<()> = EGraph::new();
+  simple.add_op(&expr());
+  let simple = Runner::new().with_iter_limit(4).run(&mut simple, &rules());
+
+  let mut backoff: EGraph<()> = EGraph::new();
+  backoff.add_op(&expr());
+  let backoff = Runner::new()
+    .with_iter_limit(4)
+    .with_scheduler(BackoffScheduler::new(8, 2))
+    .run(&mut backoff, &rules());
+
+  assert_eq!(simple.stop_reason, StopReason::IterationLimit(4));
+  assert!(simple.rules.iter().all(|r| r.banned == 0));
+  assert!(simple.rules.iter().all(|r| r.applied == r.matches));
+  assert!(backoff.rules.iter().any(|r| r.banned > 0));
+  assert!(backoff.rules.iter().any(|r| r.applied < r.matches));
+  assert!(backoff.nodes < simple.nodes);
+  let report = backoff.to_string();
+  assert!(report.contains("add-comm:"));
+  assert!(report.contains("add-assoc:"));
+
+  // the limit and the ban length stop growing instead of overflowing
+  let mut egg: EGraph<()> = EGraph::new();
+  egg.add_op(&expr());
+  let found = egg.search_rule(&rules()[0]);
+  let mut scheduler = BackoffScheduler::new(0, usize::MAX / 2);
+  for iter in 0..100 {
+    let kept = Scheduler::<()>::filter_matches(&mut scheduler, iter, 0, found.clone());
+    assert!(kept.is_empty());
+    assert!(Scheduler::<()>::is_banned(&scheduler, iter, 0));
+  }
+}
+
+#[test]
+fn explanation_test() {
+  use cfir::{dialect::Registry, symbol::Symbol};
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{egraph::EGraph, enode::RawENode, rule::Rule};
+
+  let rules = [
+    Rule::new(
+      "add-comm",
+      pat!("arthi.add(?a, ?b)"),
+      catch!("arthi.add(?b, ?a)"),
+    ),
+    Rule::new("add-zero", pat!("arthi.add(?x, 0)"), catch!("?x")),
+  ];
+  // without the traits of arthi, add-zero only matches after add-comm
+  let mut egg: EGraph<()> = EGraph::new()
+    .with_registry(Registry::new())
+    .with_explanations();
+  let (_, lhs) = egg.add_op(&cfir_expr!(
+    "arthi.mul(b, arthi.add(0, a): (int, int) -> int): (int, int) -> int"
+  ));
+  let (_, rhs) = egg.add_op(&cfir_expr!("arthi.mul(b, a): (int, int) -> int"));
+  egg.saturate(&rules, 8);
+
+  let explanation = egg
+    .explain_equivalence(&RawENode::Use(lhs, 0), &RawENode::Use(rhs.clone(), 0))
+    .unwrap();
+  assert_eq!(explanation.steps.len(), 2);
+  assert_eq!(
+    explanation.rules(),
+    [Symbol::new("add-comm"), Symbol::new("add-zero")]
+  );
+  assert_eq!(
+    explanation.to_string(),
+    "\
+arthi.mul(b, arthi.add(0, a): (int, int) -> int): (int, int) -> int
+= arthi.mul(b, a): (int, int) -> int  by congruence
+    arthi.add(0, a): (int, int) -> int
+    = arthi.add(a, 0): any  by add-comm, ?a = 0, ?b = a
+    = a  by add-zero, ?x = a
+"
+  );
+
+  let not_equal = egg.add_op(&cfir_expr!("arthi.mul(a, a): (int, int) -> int"));
+  assert!(egg
+    .explain_equivalence(&RawENode::Use(not_equal.1, 0), &RawENode::Use(rhs, 0))
+    .is_none());
+}
+
+#[test]
+fn machine_test() {
+  use cfir::value::{Constant, Value};
+  use cfir_frontend::{cfir_expr, pat};
+  use egraph::{egraph::EGraph, enode::ENode, machine::Program, matching::MatchRecord};
+
+  let mut egg: EGraph<()> = EGraph::new();
+  egg.add_op(&cfir_expr!(
+    "add(add(a, 1): (int, int) -> int, 1): (int, int) -> int"
+  ));
+  egg.add_op(&cfir_expr!(
+    "add(add(a, 1): (int, int) -> int, 2): (int, int) -> int"
+  ));
+  let (sub, _) = egg.add_op(&cfir_expr!(
+    "add(sub(a, 1): (int, int) -> int, 1): (int, int) -> int"
+  ));
+  let (_, two) = egg.add_value(&Value::Const(Constant::Int(2)));
+  let (_, a) = egg.add_value(&Value::Input(cfir::symbol::Symbol::new("a")));
+  egg.union(&sub, &two);
+  egg.union(&sub, &a);
+  egg.rebuild();
+
+  // nodes are compared by eclass, the machine binds the first node of an eclass
+  let by_eclass = |(node, record): (ENode<()>, MatchRecord<()>)| {
+    let mut record = record
+      .into_iter()
+      .map(|(sym, node)| (sym.0.to_string(), node.get_id().0.as_ptr()))
+      .collect::<Vec<_>>();
+    record.sort();
+    (node.get_id().0.as_ptr(), record)
+  };
+
+  for src in [
+    "add(?a, ?b)",
+    "add(add(?a, ?b), ?b)",
+    "add(?x:add(?a, ?b), ?b)",
+    "add(_, 2)",
+    "add(?x, ?x)",
+    "add(add(a, 1), ?c)",
+    "sub(?a, 1)",
+  ] {
+    let op = pat!(src);
+    let mut recursive = egg
+      .matching_op(op.clone())
+      .into_iter()
+      .map(by_eclass)
+      .collect::<Vec<_>>();
+    let mut machine = egg
+      .run_program(&Program::compile_op(&op))
+      .into_iter()
+      .map(by_eclass)
+      .collect::<Vec<_>>();
+    recursive.sort();
+    machine.sort();
+    assert_eq!(recursive, machine, "{}", src);
+  }
+}
+
+#[test]
+fn relational_test() {
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{
+    egraph::EGraph,
+    enode::ENode,
+    matching::MatchRecord,
+    relational::Query,
+    rule::{Matcher, Rule},
+  };
+
+  let mut egg: EGraph<()> = EGraph::new();
+  for src in [
+    "add(add(a, 1): (int, int) -> int, 1): (int, int) -> int",
+    "add(add(a, 1): (int, int) -> int, 2): (int, int) -> int",
+    "add(sub(a, 1): (int, int) -> int, 1): (int, int) -> int",
+    "add(a, a): (int, int) -> int",
+    "div(a, b): (int, int) -> int",
+    "rem(a, b): (int, int) -> int",
+    "rem(a, c): (int, int) -> int",
+  ] {
+    egg.add_op(&cfir_expr!(src));
+  }
+  egg.rebuild();
+
+  let by_eclass = |(node, record): (ENode<()>, MatchRecord<()>)| {
+    let mut record = record
+      .into_iter()
+      .map(|(sym, node)| (sym.0.to_string(), node.get_id().0.as_ptr()))
+      .collect::<Vec<_>>();
+    record.sort();
+    (node.get_id().0.as_ptr(), record)
+  };
+  for src in [
+    "add(?a, ?b)",
+    "add(add(?a, ?b), ?b)",
+    "add(?x:add(?a, ?b), ?b)",
+    "add(_, _)",
+    "add(?x, ?x)",
+    "add(add(a, 1), ?c)",
+    "add(?a, 1)",
+  ] {
+    let op = pat!(src);
+    let mut tree = egg
+      .matching_op(op.clone())
+      .into_iter()
+      .map(by_eclass)
+      .collect::<Vec<_>>();
+    let mut relational = egg
+      .run_query(&Query::compile_op(&op))
+      .into_iter()
+      .map(by_eclass)
+      .collect::<Vec<_>>();
+    tree.sort();
+    relational.sort();
+    assert_eq!(tree, relational, "{}", src);
+  }
+
+  let multi = [catch!("?q:div(?a, ?b)"), catch!("?r:rem(?a, ?b)")];
+  let tree = egg.matching_multi(&multi);
+  let relational = egg.run_query(&Query::compile(&multi));
+  assert_eq!(tree.len(), 1);
+  assert_eq!(relational.len(), 1);
+  assert_eq!(by_eclass(tree[0].clone()), by_eclass(relational[0].clone()));
+
+  let rule = Rule::new("add-comm", pat!("add(?a, ?b)"), catch!("add(?b, ?a)"));
+  assert_eq!(rule.matcher(), Matcher::Machine);
+  let rule = rule.with_matcher(Matcher::Relational);
+  assert_eq!(rule.matcher(), Matcher::Relational);
+  assert_eq!(egg.search_rule(&rule).len(), 5);
+  // the rules of a search phase share the relations
+  let divrem = Rule::multi("divrem", multi.to_vec(), vec![])
+    .unwrap()
+    .with_matcher(Matcher::Relational);
+  let found = egg.search_phase(|egg| (egg.search_rule(&rule), egg.search_rule(&divrem)));
+  assert_eq!((found.0.len(), found.1.len()), (5, 1));
+
+  // a bare variable matches every eclass, it is no multi-pattern
+  let any = Rule::<()>::multi("any", vec![catch!("?x"), catch!("?r:rem(?a, ?b)")], vec![]);
+  assert_eq!(any.unwrap_err(), "rule `any`: pattern 1 is a bare variable");
+  assert!(egg.run_query(&Query::compile(&[catch!("?x")])).is_empty());
+}
+
+#[test]
+fn rule_file_test() {
+  use cfir::{
+    rewriter::rule::{Cond, Operand},
+    symbol::Symbol,
+    value::Value,
+  };
+  use cfir_frontend::{cfir_expr, rule_parser::parse_rules};
+  use egraph::{const_fold::ConstFold, egraph::EGraph, rule::Rule};
+
+  let groups = parse_rules(include_str!("../rules/arthi.cfrules"), "arthi.cfrules").unwrap();
+  let rules: Vec<Rule<ConstFold>> = Rule::from_groups(&groups).unwrap();
+
+  let mut egg = EGraph::new();
+  let (root, _) = egg.add_op(&cfir_expr!(
+    "arthi.add(0, arthi.mul(a, 1): (int, int) -> int): (int, int) -> int"
+  ));
+  egg.saturate(&rules, 8);
+  let (_, a) = egg.add_value(&Value::Input(Symbol::new("a")));
+  assert!(root.find() == a);
+
+  assert!(parse_rules("p: a(?x) => ?x if prime(?x)", "<test>").is_err());
+  let mut groups = parse_rules("p: a(?x) => ?x", "<test>").unwrap();
+  groups[0].rules[0].guards.push(Cond::Call(
+    Symbol::new("prime"),
+    vec![Operand::Var(Symbol::new("x"))],
+  ));
+  assert!(Rule::<ConstFold>::from_groups(&groups).is_err());
+
+  // an integer literal equals a signed or unsigned constant, not a boolean
+  let mut egg: EGraph<()> = EGraph::new();
+  for src in [
+    "f(true): (bool) -> int",
+    "f(1u): (uint) -> int",
+    "f(1): (int) -> int",
+  ] {
+    egg.add_op(&cfir_expr!(src));
+  }
+  for (guard, count) in [("?b == 1", 2), ("?b == true", 1), ("?b != 1", 1)] {
+    let src = format!("g: f(?b) => ?b if {guard}");
+    let rules: Vec<Rule<()>> = Rule::from_groups(&parse_rules(&src, "<test>").unwrap()).unwrap();
+    assert_eq!(egg.search_rule(&rules[0]).len(), count, "{guard}");
+  }
+}
+
+#[test]
+fn region_test() {
+  use cfir::value::Value;
+  use cfir_frontend::{catch, cfir_block, pat, rule_parser::parse_rules};
+  use egraph::{
+    egraph::EGraph,
+    extract::{AstSize, Extractor},
+    rule::Rule,
+  };
+
+  let mut egg: EGraph<()> = EGraph::new();
+  let inner = egg
+    .add_block(
+      &cfir_block!(
+        "
+  r = scf.for(n) {
+    ^body(i.int):
+      t = arthi.mul(x, y): (int, int) -> int
+      s = arthi.add(t, i): (int, int) -> int
+      scf.yield(s): (int) -> never
+  }: (int) -> int
+  "
+      )
+      .2,
+    )
+    .0;
+  // the same loop with `t` hoisted and its argument renamed
+  let hoisted = egg
+    .add_block(
+      &cfir_block!(
+        "
+  t = arthi.mul(x, y): (int, int) -> int
+  r = scf.for(n) {
+    ^body(j.int):
+      s = arthi.add(t, j): (int, int) -> int
+      scf.yield(s): (int) -> never
+  }: (int) -> int
+  "
+      )
+      .2,
+    )
+    .0;
+  assert!(inner[0].find() == hoisted[1].find());
+  let other = egg
+    .add_block(
+      &cfir_block!(
+        "
+  r = scf.for(n) {
+    ^body(i.int):
+      s = arthi.add(i, x): (int, int) -> int
+      scf.yield(s): (int) -> never
+  }: (int) -> int
+  "
+      )
+      .2,
+    )
+    .0;
+  assert!(inner[0].find() != other[0].find());
+
+  // rewriting inside a body
+  let mul = egg
+    .add_block(
+      &cfir_block!(
+        "
+  r = scf.for(n) {
+    ^body(i.int):
+      s = arthi.mul(i, 2): (int, int) -> int
+      scf.yield(s): (int) -> never
+  }: (int) -> int
+  "
+      )
+      .2,
+    )
+    .0;
+  let rule = Rule::new(
+    "mul-two",
+    pat!("arthi.mul(?a, 2)"),
+    catch!("arthi.shl(?a, 1)"),
+  );
+  egg.saturate(&[rule], 8);
+  let found = egg.matching_op(pat!(
+    "scf.for(?n, region.block(scf.yield(arthi.shl(?i, 1))))"
+  ));
+  assert_eq!(found.len(), 1);
+  assert!(found[0].0.get_id() == mul[0].find());
+
+  // rewriting across a region, only for a body not using its argument
+  let groups = parse_rules(
+    "hoist: scf.repeat(?n, region.block(scf.yield(?v))) => ?v if closed(?v)",
+    "<test>",
+  )
+  .unwrap();
+  let rules: Vec<Rule<()>> = Rule::from_groups(&groups).unwrap();
+  let closed = egg
+    .add_block(
+      &cfir_block!(
+        "
+  x1 = arthi.add(x, 1): (int, int) -> int
+  r = scf.repeat(n) {
+    ^body(i.int):
+      scf.yield(x1): (int) -> never
+  }: (int) -> int
+  "
+      )
+      .2,
+    )
+    .0;
+  let open = egg
+    .add_block(
+      &cfir_block!(
+        "
+  r = scf.repeat(n) {
+    ^body(i.int):
+      s = arthi.add(i, 1): (int, int) -> int
+      scf.yield(s): (int) -> never
+  }: (int) -> int
+  "
+      )
+      .2,
+    )
+    .0;
+  egg.saturate(&rules, 8);
+  assert!(closed[1].find() == closed[0].find());
+  assert_eq!(open[0].find().as_ref().borrow().nodes.len(), 1);
+
+  // a region is extracted back into blocks, with its arguments named
+  let src = cfir_block!(
+    "
+  r = scf.for(n) {
+    ^body(i.int):
+      x = mem.load(p): (ptr) -> int
+      s = arthi.add(x, i): (int, int) -> int
+      mem.store(p, s): (ptr, int) -> never
+      scf.yield(s): (int) -> never
+  }: (int) -> int
+  "
+  )
+  .2;
+  let (ids, _) = egg.add_block(&src);
+  let value = Extractor::new(&egg, AstSize).find_best(&ids[0]).unwrap();
+  let Value::Use(op, 0) = &value else {
+    panic!("{value}");
+  };
+  assert_eq!(
+    value.to_string(),
+    "scf.for(n){ ^body(a0_0.int): x = mem.load(p): (ptr) -> int \
+     mem.store(p, arthi.add(x, a0_0): (int, int) -> int): (ptr, int) -> never \
+     scf.yield(arthi.add(x, a0_0): (int, int) -> int): (int) -> never }: (int) -> int"
+  );
+  // and is the same term as the original
+  let (id, _) = egg.add_op(&op.as_ref().borrow());
+  assert!(id.find() == ids[0].find());
+}
+
+#[test]
+fn effect_test() {
+  use cfir_frontend::{catch, cfir_block, cfir_expr, pat};
+  use egraph::{
+    egraph::EGraph,
+    extract::{AstSize, Extractor},
+    rule::Rule,
+  };
+
+  let block = |src: &str| cfir_block!(src).2;
+  let mut egg: EGraph<()> = EGraph::new();
+  let (ops, state) = egg.add_block(&block(
+    "
+  p = arthi.add(a, 0): (ptr, int) -> ptr
+  mem.store(p, 1): (ptr, int) -> never
+  x = mem.load(p): (ptr) -> int
+  mem.store(q, x): (ptr, int) -> never
+  y = arthi.mul(x, 1): (int, int) -> int
+  fn.ret(y): (int) -> never
+  ",
+  ));
+  // `fn.ret` defines nothing, its state is its own result
+  assert!(state == ops[5]);
+
+  // the same stores in the other order leave another state
+  let (_, swapped) = egg.add_block(&block(
+    "
+  mem.store(p, 2): (ptr, int) -> never
+  mem.store(p, 1): (ptr, int) -> never
+  ",
+  ));
+  let (_, stores) = egg.add_block(&block(
+    "
+  mem.store(p, 1): (ptr, int) -> never
+  mem.store(p, 2): (ptr, int) -> never
+  ",
+  ));
+  assert!(swapped != stores);
+
+  let rules = [
+    Rule::new("add-zero", pat!("arthi.add(?x, 0)"), catch!("?x")),
+    Rule::new("mul-one", pat!("arthi.mul(?x, 1)"), catch!("?x")),
+  ];
+  egg.saturate(&rules, 8);
+
+  let extractor = Extractor::new(&egg, AstSize);
+  let ops = extractor
+    .extract_block(&state)
+    .unwrap()
+    .iter()
+    .map(|op| op.to_string())
+    .collect::<Vec<_>>();
+  assert_eq!(
+    ops,
+    [
+      "mem.store(a, 1): (ptr, int) -> never",
+      "mem.load(a): (ptr) -> int",
+      "mem.store(q, mem.load(a): (ptr) -> int): (ptr, int) -> never",
+      "fn.ret(mem.load(a): (ptr) -> int): (int) -> never",
+    ]
+  );
+
+  // outside of a block an effectful op has no state to drop
+  let (load, _) = egg.add_op(&cfir_expr!("mem.load(p): (ptr) -> int"));
+  let value = Extractor::new(&egg, AstSize).find_best(&load).unwrap();
+  assert_eq!(value.to_string(), "mem.load(p): (ptr) -> int");
+}
+
+#[test]
+fn dump_test() {
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{dump::DumpOptions, egraph::EGraph, rule::Rule};
+
+  let mut egg: EGraph<()> = EGraph::new();
+  let (root, _) = egg.add_op(&cfir_expr!(
+    "arthi.mul(b, arthi.add(a, 0): (int, int) -> int): (int, int) -> int"
+  ));
+  egg.root.push(root.clone());
+  let rule = Rule::new("add-zero", pat!("arthi.add(?x, 0)"), catch!("?x"));
+  let matches = egg.search_rule(&rule);
+  egg.saturate(&[rule], 8);
+  // `a` holds `arthi.add(a, 0)` now, a cycle
+  assert!(format!("{:?}", egg).contains("EGraph"));
+
+  let dot = egg.to_dot(&DumpOptions::new().highlight_matches(&matches));
+  assert_eq!(dot.matches("subgraph cluster_").count(), 4);
+  assert_eq!(dot.matches(" -> ").count(), 4);
+  assert_eq!(dot.matches("fillcolor=yellow").count(), 1);
+  assert!(dot.contains("[label=\"arthi.add\", style=filled, fillcolor=yellow]"));
+
+  let json = egg.to_json(&DumpOptions::new());
+  assert!(
+    json.starts_with("{\"nodes\": {\"0.0\": {\"op\": \"b\", \"children\": [], \"eclass\": \"0\"")
+  );
+  assert!(json.contains("\"root_eclasses\": [\"3\"]"));
+
+  // `mul` and its uses, `0` is two uses away
+  let near = egg.to_dot(&DumpOptions::new().around(&root, 1));
+  assert_eq!(near.matches("subgraph cluster_").count(), 3);
+  assert!(!near.contains(" [label=\"0\"]"));
+  assert!(near.contains("subgraph cluster_3 {"));
+}
+
+#[test]
+fn meta_pattern_test() {
+  use cfir_frontend::{catch, cfir_expr, pat, rule_parser::parse_rules};
+  use egraph::{
+    egraph::EGraph,
+    machine::Program,
+    relational::Query,
+    rule::{Matcher, Rule},
+  };
+
+  let build = || {
+    let mut egg: EGraph<()> = EGraph::new();
+    let (int, _) = egg.add_op(&cfir_expr!("arthi.add(a, b) [k: 1]: (i32, i32) -> i32"));
+    let (float, _) = egg.add_op(&cfir_expr!("arthi.add(c, d) [k: 2]: (f64, f64) -> f64"));
+    egg.add_op(&cfir_expr!("arthi.add(a, c): (i32, f64) -> f64"));
+    egg.rebuild();
+    (egg, int, float)
+  };
+  let (mut egg, _, _) = build();
+
+  for (src, count) in [
+    ("arthi.add(?a, ?b)", 6),
+    ("arthi.add(?a, ?b): (?t, ?t) -> ?t", 4),
+    ("arthi.add(?a, ?b): (i32, i32) -> i32", 2),
+    ("arthi.add(?a, ?b): (?t, f64) -> f64", 4),
+    ("arthi.add(?a, ?b) [k: ?v]", 4),
+    ("arthi.add(?a, ?b) [k: 2]", 2),
+    ("arthi.add(?a, ?b) [k: 2]: (i32, i32) -> i32", 0),
+  ] {
+    let op = pat!(src);
+    let tree = egg.matching_op(op.clone()).len();
+    let machine = egg.run_program(&Program::compile_op(&op)).len();
+    let relational = egg.run_query(&Query::compile_op(&op)).len();
+    assert_eq!(
+      (tree, machine, relational),
+      (count, count, count),
+      "{}",
+      src
+    );
+  }
+
+  // the rhs reuses the attribute and the type bound by the lhs
+  for matcher in [Matcher::Tree, Matcher::Machine, Matcher::Relational] {
+    let (mut egg, int, float) = build();
+    let rule = Rule::new(
+      "add-comm",
+      pat!("arthi.add(?a, ?b) [k: ?v]: (?t, ?t) -> ?t"),
+      catch!("arthi.add(?b, ?a) [k: ?v]: (?t, ?t) -> ?t"),
+    )
+    .with_matcher(matcher);
+    egg.saturate(&[rule], 4);
+    let (comm, _) = egg.add_op(&cfir_expr!("arthi.add(b, a) [k: 1]: (i32, i32) -> i32"));
+    assert!(comm.find() == int.find());
+    let (comm, _) = egg.add_op(&cfir_expr!("arthi.add(d, c) [k: 2]: (f64, f64) -> f64"));
+    assert!(comm.find() == float.find());
+  }
+
+  // attribute variables may be compared by the guards
+  let groups = parse_rules("one: arthi.add(?a, ?b) [k: ?v] => ?a if ?v == 1", "<test>").unwrap();
+  let rules: Vec<Rule<()>> = Rule::from_groups(&groups).unwrap();
+  assert_eq!(egg.search_rule(&rules[0]).len(), 2);
+}
+
+#[test]
+fn opcode_pattern_test() {
+  use cfir::symbol::Symbol;
+  use cfir_frontend::{cfir_expr, pat, rule_parser::parse_rules};
+  use egraph::{egraph::EGraph, machine::Program, relational::Query, rule::Rule};
+
+  let build = || {
+    let mut egg: EGraph<()> = EGraph::new();
+    let ids = [
+      "arthi.add(a, b): (i32, i32) -> i32",
+      "arthi.mul(a, b): (i32, i32) -> i32",
+      "arthi.sub(a, b): (i32, i32) -> i32",
+      "arthi.neg(a): (i32) -> i32",
+      "logic.and(a, b): (i1, i1) -> i1",
+    ]
+    .map(|src| egg.add_op(&cfir_expr!(src)).0);
+    egg.rebuild();
+    (egg, ids)
+  };
+  let (mut egg, _) = build();
+
+  for (src, count) in [
+    ("?op(?a, ?b)", 6),
+    ("?op(?a)", 1),
+    ("arthi.*(?a, ?b)", 5),
+    ("logic.*(?a, ?b)", 1),
+    ("?op(?a, ?a)", 0),
+    ("arthi.*(?a, ?b): (i1, i1) -> i1", 0),
+  ] {
+    let op = pat!(src);
+    let tree = egg.matching_op(op.clone()).len();
+    let machine = egg.run_program(&Program::compile_op(&op)).len();
+    let relational = egg.run_query(&Query::compile_op(&op)).len();
+    assert_eq!(
+      (tree, machine, relational),
+      (count, count, count),
+      "{}",
+      src
+    );
+  }
+
+  // one rule for every commutative op, the rhs reuses the opcode and the sign
+  let src = "comm: ?op(?a, ?b): ?s => ?op(?b, ?a): ?s if commutative(?op)";
+  let rules: Vec<Rule<()>> = Rule::from_groups(&parse_rules(src, "<test>").unwrap()).unwrap();
+  let (mut egg, [add, ..]) = build();
+  egg.saturate(&rules, 4);
+  for (src, count) in [
+    ("arthi.add(?x, ?y)", 4),
+    ("arthi.mul(?x, ?y)", 4),
+    ("arthi.sub(?x, ?y)", 1),
+    ("logic.and(?x, ?y)", 1),
+  ] {
+    assert_eq!(egg.matching_op(pat!(src)).len(), count, "{}", src);
+  }
+  let (node, record) = &egg.matching_op(pat!("arthi.add(?x, ?y)"))[0];
+  assert!(node.get_id().find() == add.find());
+  assert!(record[&Symbol::new("x")].get_id() != record[&Symbol::new("y")].get_id());
+
+  assert!(parse_rules("w: arthi.*(?a) => arthi.*(?a)", "<test>").is_err());
+  assert!(parse_rules("w: ?op(?a) => ?f(?a)", "<test>").is_err());
+}
+
+#[test]
+fn ac_matching_test() {
+  use cfir::{
+    dialect::{OpTrait, Registry},
+    symbol::{Name, Symbol},
+    value::Value,
+  };
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{
+    egraph::EGraph,
+    enode::ENode,
+    machine::Program,
+    matching::MatchRecord,
+    relational::Query,
+    rule::{Matcher, Rule},
+  };
+  use std::collections::HashMap;
+
+  let bound = |egg: &mut EGraph<()>, record: &HashMap<Symbol, _>, var: &str, input: &str| {
+    let (_, id) = egg.add_value(&Value::Input(Symbol::new(input)));
+    let node: &egraph::enode::ENode<()> = &record[&Symbol::new(var)];
+    node.get_id() == id.find()
+  };
+
+  // commutative ops match in any order, in every matcher
+  let mut egg: EGraph<()> = EGraph::new();
+  egg.add_op(&cfir_expr!("arthi.add(1, y): (i32, i32) -> i32"));
+  egg.rebuild();
+  let op = pat!("arthi.add(?x, 1)");
+  let tree = egg.matching_op(op.clone());
+  assert_eq!(tree.len(), 1);
+  assert!(bound(&mut egg, &tree[0].1, "x", "y"));
+  assert_eq!(egg.run_program(&Program::compile_op(&op)).len(), 1);
+  assert_eq!(egg.run_query(&Query::compile_op(&op)).len(), 1);
+
+  // the traits are per opcode
+  let mut plain: EGraph<()> = EGraph::new().with_registry(Registry::new());
+  plain.add_op(&cfir_expr!("arthi.add(1, y): (i32, i32) -> i32"));
+  plain.rebuild();
+  assert!(plain.matching_op(op.clone()).is_empty());
+  let max = Name(Some(Symbol::new("my")), Symbol::new("max"));
+  let mut custom: EGraph<()> =
+    EGraph::new().with_registry(Registry::new().with(max, &[OpTrait::Commutative]));
+  custom.add_op(&cfir_expr!("my.max(1, y): (i32, i32) -> i32"));
+  custom.rebuild();
+  assert_eq!(custom.matching_op(pat!("my.max(?x, 1)")).len(), 1);
+
+  // associative-commutative ops are flattened
+  let mut egg: EGraph<()> = EGraph::new();
+  let (root, _) = egg.add_op(&cfir_expr!(
+    "arthi.add(x, arthi.add(y, 1): (i32, i32) -> i32): (i32, i32) -> i32"
+  ));
+  egg.rebuild();
+  let op = pat!("arthi.add(arthi.add(?a, 1), ?b)");
+  let matches = egg.matching_op(op.clone());
+  assert_eq!(matches.len(), 2);
+  for (node, record) in &matches {
+    assert!(node.get_id() == root.find());
+    let xy = bound(&mut egg, record, "a", "x") && bound(&mut egg, record, "b", "y");
+    let yx = bound(&mut egg, record, "a", "y") && bound(&mut egg, record, "b", "x");
+    assert!(xy || yx);
+  }
+  // every matcher flattens, whatever the matcher of a rule
+  assert_eq!(egg.run_program(&Program::compile_op(&op)).len(), 2);
+  assert_eq!(egg.run_query(&Query::compile_op(&op)).len(), 2);
+  let rule: Rule<()> = Rule::new(
+    "assoc",
+    op,
+    cfir_frontend::catch!("arthi.add(?a, arthi.add(?b, 1))"),
+  );
+  for matcher in [Matcher::Tree, Matcher::Machine, Matcher::Relational] {
+    let rule = rule.clone().with_matcher(matcher);
+    assert_eq!(egg.search_rule(&rule).len(), 2);
+    assert_eq!(egg.search_rules(&[&rule], 2)[0].len(), 2);
+  }
+
+  // an eclass holding an add of itself is not flattened into itself
+  let (_, x) = egg.add_value(&Value::Input(Symbol::new("x")));
+  let (zero, _) = egg.add_op(&cfir_expr!("arthi.add(x, 0): (i32, i32) -> i32"));
+  egg.union(&zero, &x);
+  egg.rebuild();
+  let matches = egg.matching_op(pat!("arthi.add(arthi.add(arthi.add(?a, ?b), ?c), ?d)"));
+  assert!(!matches.is_empty());
+
+  // the three matchers agree on an egraph of associative-commutative ops
+  let mut egg: EGraph<()> = EGraph::new();
+  for src in [
+    "arthi.add(x, arthi.add(y, 1): (i32, i32) -> i32): (i32, i32) -> i32",
+    "arthi.add(x, y, z): (i32, i32, i32) -> i32",
+    "arthi.add(p, q): (i32, i32) -> i32",
+    "arthi.mul(p, arthi.add(q, 1): (i32, i32) -> i32): (i32, i32) -> i32",
+    "arthi.sub(x, arthi.add(x, x): (i32, i32) -> i32): (i32, i32) -> i32",
+  ] {
+    egg.add_op(&cfir_expr!(src));
+  }
+  let (_, p) = egg.add_value(&Value::Input(Symbol::new("p")));
+  let (_, q) = egg.add_value(&Value::Input(Symbol::new("q")));
+  let (xy, _) = egg.add_op(&cfir_expr!("arthi.add(x, y): (i32, i32) -> i32"));
+  let (z1, _) = egg.add_op(&cfir_expr!("arthi.add(1, z): (i32, i32) -> i32"));
+  let (x, _) = egg.add_op(&cfir_expr!("arthi.add(x, 0): (i32, i32) -> i32"));
+  let (_, xid) = egg.add_value(&Value::Input(Symbol::new("x")));
+  egg.union(&p, &xy);
+  egg.union(&q, &z1);
+  egg.union(&x, &xid);
+  egg.rebuild();
+
+  let summary = |matches: Vec<(ENode<()>, MatchRecord<()>)>| {
+    let mut matches = matches
+      .into_iter()
+      .map(|(node, record)| {
+        let mut record = record
+          .into_iter()
+          .map(|(sym, node)| (sym.0.to_string(), node.get_id().0.as_ptr()))
+          .collect::<Vec<_>>();
+        record.sort();
+        (node.get_id().0.as_ptr(), record)
+      })
+      .collect::<Vec<_>>();
+    matches.sort();
+    matches
+  };
+  for src in [
+    "arthi.add(?a, ?b)",
+    "arthi.add(?a, ?b, ?c)",
+    "arthi.add(arthi.add(?a, 1), ?b)",
+    "arthi.add(arthi.add(?a, ?b), arthi.add(?c, ?d))",
+    "arthi.add(?x:arthi.add(?a, ?b), ?c)",
+    "arthi.add(arthi.add(?a, ?a), ?b)",
+    "arthi.add(arthi.add(arthi.add(?a, ?b), ?c), ?d)",
+    "arthi.*(arthi.add(?a, ?b), ?c)",
+    "arthi.mul(arthi.add(?a, 1), ?b)",
+    "arthi.sub(?a, arthi.add(?a, ?b))",
+  ] {
+    let op = pat!(src);
+    let tree = summary(egg.matching_op(op.clone()));
+    assert!(!tree.is_empty(), "{}", src);
+    assert_eq!(
+      tree,
+      summary(egg.run_program(&Program::compile_op(&op))),
+      "{}",
+      src
+    );
+    assert_eq!(
+      tree,
+      summary(egg.run_query(&Query::compile_op(&op))),
+      "{}",
+      src
+    );
+  }
+  let multi = [
+    catch!("?s:arthi.add(arthi.add(?a, ?b), ?c)"),
+    catch!("?t:arthi.mul(?s, ?d)"),
+  ];
+  let tree = summary(egg.matching_multi(&multi));
+  assert!(!tree.is_empty());
+  assert_eq!(tree, summary(egg.run_query(&Query::compile(&multi))));
+}
+
+#[test]
+fn hashcons_test() {
+  use cfir::{
+    symbol::{Name, Symbol},
+    value::Value,
+  };
+  use cfir_frontend::{cfir_expr, pat};
+  use egraph::egraph::EGraph;
+
+  let mut egg: EGraph<()> = EGraph::new();
+  let (l, _) = egg.add_op(&cfir_expr!("arthi.sub(x, 1): (i32, i32) -> i32"));
+  let (r, _) = egg.add_op(&cfir_expr!("arthi.sub(x, 1): (i32, i32) -> i32"));
+  assert!(l == r);
+  let len = egg.hashcons.len();
+
+  // nodes differing only in their sign or attrs are distinct
+  let (wide, _) = egg.add_op(&cfir_expr!("arthi.sub(x, 1): (i64, i64) -> i64"));
+  assert!(wide != l);
+  assert_eq!(egg.hashcons.len(), len + 1);
+
+  // ops over merged eclasses are congruent after a rebuild
+  let (y, _) = egg.add_op(&cfir_expr!("arthi.sub(y, 1): (i32, i32) -> i32"));
+  let (_, x) = egg.add_value(&Value::Input(Symbol::new("x")));
+  let (_, yid) = egg.add_value(&Value::Input(Symbol::new("y")));
+  assert_eq!(egg.hashcons.users(&x).len(), 2);
+  // the users of the users are repaired in the next pass
+  let (lx, _) = egg.add_op(&cfir_expr!(
+    "arthi.mul(2, arthi.sub(x, 1): (i32, i32) -> i32): (i32, i32) -> i32"
+  ));
+  let (ly, _) = egg.add_op(&cfir_expr!(
+    "arthi.mul(2, arthi.sub(y, 1): (i32, i32) -> i32): (i32, i32) -> i32"
+  ));
+  egg.union(&x, &yid);
+  assert_eq!(egg.rebuild(), 2);
+  assert!(l.find() == y.find());
+  assert!(lx.find() == ly.find());
+  // only the repaired nodes are keyed again, the congruent ones are one node
+  let stats = egg.stats();
+  assert_eq!(stats.hashed, stats.nodes);
+  assert_eq!(egg.hashcons.users(&x.find()).len(), 2);
+  assert_eq!(
+    egg
+      .hashcons
+      .ops(&Name(Some(Symbol::new("arthi")), Symbol::new("sub")), 2)
+      .len(),
+    2
+  );
+  assert_eq!(egg.matching_op(pat!("arthi.sub(?a, 1)")).len(), 2);
+  let (again, _) = egg.add_op(&cfir_expr!("arthi.sub(y, 1): (i32, i32) -> i32"));
+  assert!(again == l.find());
+}
+
+#[test]
+fn resume_test() {
+  use cfir::{symbol::Symbol, value::Value};
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{
+    egraph::EGraph,
+    rule::Rule,
+    runner::{Runner, StopReason},
+  };
+
+  let rules = || {
+    vec![
+      Rule::new("add-0", pat!("arthi.add(?a, 0)"), catch!("?a")),
+      Rule::new("mul-1", pat!("arthi.mul(?a, 1)"), catch!("?a")),
+      Rule::new(
+        "sub-sub",
+        pat!("arthi.sub(?a, arthi.sub(?a, ?b))"),
+        catch!("?b"),
+      ),
+    ]
+  };
+  let old = (0..20)
+    .map(|i| format!("arthi.mul(1, arthi.add(x{i}, 0): (int, int) -> int): (int, int) -> int"))
+    .chain(["arthi.sub(p, arthi.sub(r, q): (int, int) -> int): (int, int) -> int".into()]);
+  let new = "arthi.mul(1, arthi.sub(y, arthi.sub(y, z): (int, int) -> int): (int, int) -> int): (int, int) -> int";
+  let input = |egg: &mut EGraph<()>, name: &str| egg.add_value(&Value::Input(Symbol::new(name))).1;
+
+  let mut egg: EGraph<()> = EGraph::new();
+  for src in old.clone() {
+    egg.add_op(&cfir_expr!(&src));
+  }
+  let first = Runner::new().run(&mut egg, &rules());
+  assert_eq!(first.stop_reason, StopReason::Saturated);
+  assert!(first.frontier.is_empty());
+
+  // a new term, and an old one matching once `p = r`
+  let (term, _) = egg.add_op(&cfir_expr!(new));
+  let (p, r) = (input(&mut egg, "p"), input(&mut egg, "r"));
+  egg.union(&p, &r);
+  let resumed = Runner::new().resume(&mut egg, &rules());
+  assert_eq!(resumed.stop_reason, StopReason::Saturated);
+  assert!(resumed.frontier[0] > 0 && resumed.frontier[0] < first.classes / 2);
+  assert!(resumed.to_string().contains("frontier:"));
+  assert!(term.find() == input(&mut egg, "z").find());
+  let (sub, _) = egg.add_op(&cfir_expr!(
+    "arthi.sub(p, arthi.sub(r, q): (int, int) -> int): (int, int) -> int"
+  ));
+  assert!(sub.find() == input(&mut egg, "q").find());
+
+  // same egraph as a full run over everything
+  let mut full: EGraph<()> = EGraph::new();
+  for src in old {
+    full.add_op(&cfir_expr!(&src));
+  }
+  full.add_op(&cfir_expr!(new));
+  let (p, r) = (input(&mut full, "p"), input(&mut full, "r"));
+  full.union(&p, &r);
+  let full = Runner::new().run(&mut full, &rules());
+  assert_eq!((resumed.classes, resumed.nodes), (full.classes, full.nodes));
+}
+
+#[test]
+fn snapshot_test() {
+  use cfir::{
+    symbol::Symbol,
+    value::{Constant, Value},
+  };
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{
+    const_fold::ConstFold, dump::DumpOptions, egraph::EGraph, enode::RawENode, rule::Rule,
+  };
+
+  let rules = [
+    Rule::new("add-0", pat!("arthi.add(?a, 0)"), catch!("?a")),
+    Rule::new(
+      "mul-add",
+      pat!("arthi.mul(?a, arthi.add(?b, ?c))"),
+      catch!("arthi.add(arthi.mul(?a, ?b), arthi.mul(?a, ?c))"),
+    ),
+  ];
+  let mut egg: EGraph<ConstFold> = EGraph::new().with_explanations();
+  let (_, term) = egg.add_op(&cfir_expr!(
+    "arthi.mul(x, arthi.add(y, arthi.sub(3, 3): (int, int) -> int): (int, int) -> int): (int, int) -> int"
+  ));
+  let (_, xy) = egg.add_op(&cfir_expr!("arthi.mul(x, y): (int, int) -> int"));
+  let xy = RawENode::Use(xy, 0);
+  let term = RawENode::Use(term, 0);
+  egg.rebuild();
+  let json = |egg: &EGraph<ConstFold>| egg.to_json(&DumpOptions::new());
+  let before = json(&egg);
+  let speculate = |egg: &mut EGraph<ConstFold>| {
+    let (_, x) = egg.add_value(&Value::Input(Symbol::new("x")));
+    let (_, z) = egg.add_value(&Value::Input(Symbol::new("z")));
+    let (_, five) = egg.add_value(&Value::Const(Constant::Int(5)));
+    egg.union(&x, &z);
+    egg.union(&z, &five);
+    egg.saturate(&rules, 8);
+  };
+
+  let x = || match &xy {
+    RawENode::Use(op, _) => op.as_ref().borrow().uses[0].find(),
+    _ => unreachable!(),
+  };
+
+  let snapshot = egg.snapshot();
+  speculate(&mut egg);
+  let after = json(&egg);
+  assert_ne!(before, after);
+  assert!(egg.explain_equivalence(&term, &xy).is_some());
+  assert_eq!(x().get_data().0, Some(Constant::Int(5)));
+  egg.rollback(snapshot);
+  assert_eq!(x().get_data().0, None);
+  assert_eq!(json(&egg), before);
+  assert!(egg.explain_equivalence(&term, &xy).is_none());
+  assert!(egg.matching_op(pat!("arthi.add(?a, 0)")).len() == 1);
+
+  // the same changes give the same egraph again
+  let outer = egg.snapshot();
+  speculate(&mut egg);
+  assert_eq!(json(&egg), after);
+  let inner = egg.snapshot();
+  egg.add_op(&cfir_expr!("arthi.mul(w, 2): (int, int) -> int"));
+  egg.rebuild();
+  assert_ne!(json(&egg), after);
+  egg.rollback(inner);
+  assert_eq!(json(&egg), after);
+  egg.rollback(outer);
+  assert_eq!(json(&egg), before);
+
+  let snapshot = egg.snapshot();
+  speculate(&mut egg);
+  egg.commit();
+  assert_eq!(json(&egg), after);
+  assert!(
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| egg.rollback(snapshot))).is_err()
+  );
+}
+
+#[test]
+fn gc_test() {
+  use cfir_frontend::{catch, cfir_expr, pat};
+  use egraph::{dump::DumpOptions, egraph::EGraph, extract::AstSize, rule::Rule};
+
+  let rules = [
+    Rule::new("add-0", pat!("arthi.add(?a, 0)"), catch!("?a")),
+    Rule::new(
+      "mul-2",
+      pat!("arthi.mul(?a, 2)"),
+      catch!("arthi.add(?a, ?a)"),
+    ),
+  ];
+  let mut egg: EGraph<()> = EGraph::new();
+  let (root, _) = egg.add_op(&cfir_expr!(
+    "arthi.mul(2, arthi.add(a, 0): (int, int) -> int): (int, int) -> int"
+  ));
+  for i in 0..10 {
+    let src = format!("arthi.sub(b{i}, c): (int, int) -> int");
+    egg.add_op(&cfir_expr!(&src));
+  }
+  // a deduplicated node adds no id
+  let ids = egg.eclasses.len();
+  egg.add_op(&cfir_expr!("arthi.sub(b0, c): (int, int) -> int"));
+  assert_eq!(egg.eclasses.len(), ids);
+  egg.saturate(&rules, 8);
+  // with no root there is nothing to keep eclasses from, none is dropped
+  let classes = egg.stats().classes;
+  assert_eq!(egg.gc(), 0);
+  assert_eq!(egg.stats().classes, classes);
+  egg.root.push(root.clone());
+
+  let before = egg.stats();
+  assert_eq!(before.hashed, before.nodes);
+  let snapshot = egg.snapshot();
+  let json = egg.to_json(&DumpOptions::new());
+  assert_eq!(egg.gc(), 21);
+  let after = egg.stats();
+  assert_eq!(after.classes, before.classes - 21);
+  assert_eq!(after.ids, after.classes);
+  assert!(after.bytes < before.bytes);
+  assert!(egg.matching_op(pat!("arthi.sub(?a, ?b)")).is_empty());
+  assert!(after
+    .to_string()
+    .contains(&format!("{} eclasses", after.classes)));
+  egg.rollback(snapshot);
+  assert_eq!(egg.to_json(&DumpOptions::new()), json);
+  assert_eq!(egg.stats().classes, before.classes);
+
+  // `a` holds `arthi.add(a, 0)`, the root `arthi.mul(2, a)` and
+  // `arthi.add(a, a)` of the same size
+  egg.gc();
+  let nodes = egg.stats().nodes;
+  assert_eq!(egg.prune(AstSize, 0), 1);
+  assert_eq!(egg.stats().nodes, nodes - 1);
+  let json = egg.to_json(&DumpOptions::new());
+  assert!(json.contains("arthi.mul"));
+  assert_eq!(json.matches("arthi.add").count(), 1);
+  // the `0` was only used by the dropped node
+  assert_eq!(egg.gc(), 1);
+  assert_eq!(egg.prune(AstSize, 0), 0);
+}
+
+#[test]
+fn determinism_test() {
+  use cfir_frontend::{cfir_expr, rule_parser::parse_rules};
+  use egraph::{
+    egraph::EGraph,
+    extract::{AstSize, Extractor},
+    rule::{Matcher, Rule},
+    runner::Runner,
+  };
+
+  let src = "
+    group shuffle {
+      swap: ?op(?a, ?b) => ?op(?b, ?a)
+      assoc: arthi.add(?a, arthi.add(?b, ?c)) => arthi.add(arthi.add(?a, ?b), ?c)
+      distribute: arthi.mul(?a, arthi.add(?b, ?c)) => arthi.add(arthi.mul(?a, ?b), arthi.mul(?a, ?c))
+      zero: arthi.*(?a, 0) => ?a
+    }
+  ";
+  let groups = parse_rules(src, "<test>").unwrap();
+  let run = |matcher| {
+    let rules = Rule::from_groups(&groups)
+      .unwrap()
+      .into_iter()
+      .map(|rule| rule.with_matcher(matcher))
+      .collect::<Vec<_>>();
+    let mut egg: EGraph<()> = EGraph::new();
+    let (root, _) = egg.add_op(&cfir_expr!(
+      "arthi.mul(x, arthi.add(y, arthi.sub(z, arthi.div(w, 0): (int, int) -> int): (int, int) -> int): (int, int) -> int): (int, int) -> int"
+    ));
+    let report = Runner::new().with_iter_limit(3).run(&mut egg, &rules);
+    let value = Extractor::new(&egg, AstSize).find_best(&root).unwrap();
+    format!(
+      "{report}{value}\n{}{egg:?}",
+      egg.to_json(&Default::default())
+    )
+  };
+  // every run gives the same egraph, the same ids and the same extracted term
+  for matcher in [Matcher::Tree, Matcher::Machine, Matcher::Relational] {
+    let golden = run(matcher);
+    for _ in 0..4 {
+      assert_eq!(run(matcher), golden);
+    }
+  }
+}
+
+#[test]
+fn parallel_test() {
+  use cfir_frontend::{cfir_expr, pat, rule_parser::parse_rules};
+  use egraph::{
+    egraph::EGraph,
+    enode::ENode,
+    extract::{AstSize, Extractor},
+    frozen::Frozen,
+    machine::Program,
+    matching::MatchRecord,
+    rule::{Matcher, Rule},
+    runner::Runner,
+  };
+
+  fn send_sync<T: Send + Sync>() {}
+  send_sync::<Frozen>();
+  send_sync::<Program>();
+
+  let src = "
+    group shuffle {
+      swap: ?op(?a, ?b) => ?op(?b, ?a)
+      assoc: arthi.add(?a, arthi.add(?b, ?c)) => arthi.add(arthi.add(?a, ?b), ?c)
+      distribute: arthi.mul(?a, arthi.add(?b, ?c)) => arthi.add(arthi.mul(?a, ?b), arthi.mul(?a, ?c))
+      zero: arthi.*(?a, 0) => ?a
+      same: arthi.sub(?a, ?a) => 0
+    }
+  ";
+  let groups = parse_rules(src, "<test>").unwrap();
+  let add = |egg: &mut EGraph<()>| {
+    egg
+      .add_op(&cfir_expr!(
+        "arthi.mul(x, arthi.add(y, arthi.sub(z, arthi.div(w, 0): (int, int) -> int): (int, int) -> int): (int, int) -> int): (int, int) -> int"
+      ))
+      .0
+  };
+
+  // the frozen egraph finds the matches of the egraph, in the same order
+  let mut egg: EGraph<()> = EGraph::new();
+  add(&mut egg);
+  egg.rebuild();
+  let frozen = egg.freeze();
+  for pat in [
+    pat!("arthi.mul(?a, arthi.add(?b, ?c))"),
+    pat!("?op(?a, ?b)"),
+    pat!("arthi.*(?a, 0)"),
+  ] {
+    let program = Program::compile_op(&pat);
+    let summary = |matches: Vec<(ENode<()>, MatchRecord<()>)>| {
+      matches
+        .into_iter()
+        .map(|(node, record)| {
+          let mut vars = record
+            .iter()
+            .map(|(sym, node)| (sym.clone(), node.get_id()))
+            .collect::<Vec<_>>();
+          vars.sort_by(|l, r| l.0 .0.cmp(&r.0 .0));
+          format!("{:?} {vars:?}", node.get_id())
+        })
+        .collect::<Vec<_>>()
+    };
+    let live = summary(egg.run_program(&program));
+    assert!(!live.is_empty());
+    assert_eq!(summary(egg.thaw(frozen.run_program(&program))), live);
+  }
+
+  let run = |matcher, threads| {
+    let rules = Rule::from_groups(&groups)
+      .unwrap()
+      .into_iter()
+      .map(|rule| rule.with_matcher(matcher))
+      .collect::<Vec<_>>();
+    let mut egg: EGraph<()> = EGraph::new();
+    let root = add(&mut egg);
+    let report = Runner::new()
+      .with_iter_limit(3)
+      .with_threads(threads)
+      .run(&mut egg, &rules);
+    let value = Extractor::new(&egg, AstSize).find_best(&root).unwrap();
+    format!(
+      "{report}{value}\n{}{egg:?}",
+      egg.to_json(&Default::default())
+    )
+  };
+  // searching on threads changes nothing, the rules the machine does not
+  // run are searched on the main thread
+  for matcher in [Matcher::Machine, Matcher::Tree, Matcher::Relational] {
+    let golden = run(matcher, 1);
+    for threads in [2, 4] {
+      assert_eq!(run(matcher, threads), golden);
+    }
+  }
+}
+
+#[test]
+fn applier_test() {
+  use cfir::{
+    symbol::Symbol,
+    value::{Constant, Value},
+  };
+  use cfir_frontend::{cfir_expr, pat, rule_parser::parse_rules};
+  use egraph::{
+    const_fold::value,
+    egraph::EGraph,
+    enode::ENode,
+    matching::MatchRecord,
+    rule::{const_of, Rule},
+    runner::Runner,
+  };
+
+  // one half of a 64-bit constant
+  let half = |shift: u32| {
+    move |record: &mut MatchRecord<()>, egraph: &mut EGraph<()>| {
+      let x = value(&const_of(&record[&Symbol::new("x")])?)?;
+      let half = Value::Const(Constant::Int((x >> shift) as i64 & 0xffff_ffff));
+      let (_, id) = egraph.add_value(&half);
+      let node = id.as_ref().borrow().nodes[0].clone();
+      Some::<ENode<()>>(node)
+    }
+  };
+  let mut rules = vec![
+    Rule::dynamic("lo", pat!("arthi.lo(?x)"), half(0)),
+    Rule::dynamic("hi", pat!("arthi.hi(?x)"), half(32)),
+  ];
+  // a dynamic rule has no template rhs
+  assert!(rules
+    .iter()
+    .all(|rule| rule.rhs.is_none() && rule.applier.is_some()));
+  assert!(format!("{:?}", rules[0]).contains("rhs: None"));
+  // template rules run next to them
+  let src = "
+    group halves {
+      join: arthi.or(?lo, arthi.shl(?hi, 32)) => arthi.join(?hi, ?lo)
+    }
+  ";
+  rules.extend(Rule::from_groups(&parse_rules(src, "<test>").unwrap()).unwrap());
+
+  let mut egg: EGraph<()> = EGraph::new();
+  let (root, _) = egg.add_op(&cfir_expr!("arthi.or(l, arthi.shl(h, 32): int): int"));
+  for (half, input) in [("lo", "l"), ("hi", "h")] {
+    let src = format!("arthi.{half}(4294967298): int");
+    let (id, _) = egg.add_op(&cfir_expr!(&src));
+    let (_, input) = egg.add_value(&Value::Input(Symbol::new(input)));
+    egg.union(&id, &input);
+  }
+  let (y, _) = egg.add_op(&cfir_expr!("arthi.lo(y): int"));
+  let report = Runner::new().run(&mut egg, &rules);
+  assert_eq!(report.rules[0].unions, 1);
+  assert_eq!(report.rules[1].unions, 1);
+  // the halves were computed and the template rule matched them
+  let joined = egg.matching_op(pat!("arthi.join(1, 2)"));
+  assert_eq!(joined.len(), 1);
+  assert_eq!(joined[0].0.get_id(), root.find());
+  // no constant, no rewrite
+  assert_eq!(y.as_ref().borrow().nodes.len(), 1);
+}
+
+#[test]
+fn soundness_test() {
+  use cfir::value::{Constant, Value};
+  use cfir_frontend::pat;
+  use egraph::{
+    const_fold::value,
+    rule::const_of,
+    soundness::{Checker, RuleCheck},
+  };
+
+  let src = "
+    group mixed {
+      swap: ?op(?a, ?b) => ?op(?b, ?a)
+      swap-comm: ?op(?a, ?b) => ?op(?b, ?a) if commutative(?op)
+      shr-div: arthi.div(?x, 2) => arthi.shr(?x, 1)
+      narrow: arthi.add(?x, ?y): (i8, i8) -> i8 => arthi.sub(?x, arthi.sub(0, ?y))
+      call: fn.call(?f) => ?f
+      shl-u8: arthi.shl(?x, 1): (u8, u8) -> u8 => arthi.shr(arthi.shl(?x, 2), 1)
+      catch: arthi.mul(?x, 0) => arthi.mul(?x:arthi.add(?x, 1), 0)
+      catch-ok: arthi.mul(?x, 1) => ?x:arthi.add(?x, 0)
+      div-shr: arthi.div(?x, ?c) => arthi.shr(?x, 3) if ?c == 8
+    }
+  ";
+  let mut rules: Vec<Rule<ConstFold>> =
+    Rule::from_groups(&parse_rules(src, "<test>").unwrap()).unwrap();
+  // appliers are checked like templates
+  let double = |shift: i128| {
+    move |record: &mut egraph::matching::MatchRecord<ConstFold>,
+          egraph: &mut egraph::egraph::EGraph<ConstFold>| {
+      let x = value(&const_of(&record[&cfir::symbol::Symbol::new("x")])?)?;
+      let (_, id) = egraph.add_value(&Value::Const(Constant::Int((x << shift) as i64)));
+      let node = id.as_ref().borrow().nodes[0].clone();
+      Some(node)
+    }
+  };
+  rules.push(Rule::dynamic(
+    "double",
+    pat!("arthi.add(?x, ?x)"),
+    double(1),
+  ));
+  rules.push(Rule::dynamic(
+    "quadruple",
+    pat!("arthi.add(?x, ?x)"),
+    double(2),
+  ));
+
+  let checks = Checker::new().check(&rules);
+  let sound = checks.iter().map(RuleCheck::is_sound).collect::<Vec<_>>();
+  assert_eq!(
+    sound,
+    [false, true, false, true, false, false, false, true, false, true, false]
+  );
+  // the counterexample is one the evaluator agrees with
+  let shr = checks[2].counterexample.as_ref().unwrap();
+  let x = value(&shr.vars[0].1).unwrap();
+  assert!(x < 0 && x % 2 != 0, "{shr}");
+  assert_ne!(shr.lhs, shr.rhs);
+  // a rule the evaluator can't run is reported, not passed
+  assert_eq!(checks[4].checked, 0);
+  assert!(checks[1].checked > 0 && checks[9].checked > 0);
+  // the ops of the rhs without a sign are evaluated in the type of the lhs
+  let shl = checks[5].counterexample.as_ref().unwrap();
+  assert!(value(&shl.vars[0].1).unwrap() & 0x40 != 0, "{shl}");
+  // a bound catch of the rhs claims its term equals the variable
+  let catch = checks[6].counterexample.as_ref().unwrap();
+  assert_eq!(
+    value(&catch.rhs).unwrap(),
+    value(&catch.lhs).unwrap().wrapping_add(1),
+    "{catch}"
+  );
+  assert!(checks[7].checked > 0);
+  // the constants of the guards are sampled too
+  let div = checks[8].counterexample.as_ref().unwrap();
+  assert!(div.vars.iter().any(|(_, c)| value(c) == Some(8)), "{div}");
+  // a few samples are not enough to pass a rule
+  let src = "rare: arthi.add(?x, ?y) => arthi.add(?y, ?x) if ?x == ?y";
+  let rare = Rule::from_groups(&parse_rules(src, "<test>").unwrap()).unwrap();
+  let few = Checker::new().with_samples(50).check_rule(&rare[0]);
+  assert!(few.checked > 0 && few.checked < few.required, "{few}");
+  assert!(!few.is_sound());
+  assert!(few.to_string().contains("rare: not checked, only"), "{few}");
+  // the same seed gives the same report
+  assert_eq!(Checker::new().check(&rules), checks);
+
+  // the rules shipped with the repo are sound, from the command line too
+  let (report, sound) = check(&[
+    "--samples".into(),
+    "50".into(),
+    "rules/arthi.cfrules".into(),
+  ])
+  .unwrap();
+  assert!(sound, "{report}");
+  assert!(report.contains("rules/arthi.cfrules: add-zero: ok, 50 samples"));
+  // and a rule that could not be checked fails the command
+  let path = std::env::temp_dir().join("cfvm-unchecked.cfrules");
+  std::fs::write(&path, "call: fn.call(?f) => ?f\n").unwrap();
+  let (report, sound) = check(&[path.to_str().unwrap().into()]).unwrap();
+  std::fs::remove_file(&path).unwrap();
+  assert!(!sound, "{report}");
+  assert!(report.contains("call: not checked"), "{report}");
+  assert!(check(&["--seed".into()]).is_err());
+  assert!(check(&["missing.cfrules".into()]).is_err());
+}